@@ -0,0 +1,24 @@
+use kanna::*;
+
+/// Plays the example script in high contrast mode and prints each line of dialogue as it is displayed.
+/// A text-to-speech engine or screen reader can be connected in the same way.
+pub fn main() -> ggez::GameResult {
+	let mut settings = Settings::default();
+	settings.resource_paths.push(env!("CARGO_MANIFEST_DIR").to_owned() + "/examples/resources");
+	settings.characters_path = Some("/characters.toml".to_owned());
+	settings.high_contrast = true;
+	settings.on_dialogue = Some(DialogueHook::new(|speaker, text| match speaker {
+		Some(speaker) => println!("{}: {}", speaker, text),
+		None => println!("{}", text),
+	}));
+
+	kanna::game::run(settings, |ctx, settings| {
+		let mut script = kanna::game::load_script(ctx, "/script.txt")?;
+		let path = settings.characters_path.as_ref().unwrap();
+		let (characters, spawn_defaults) = kanna::game::load_character_set(ctx, path)?;
+		script.characters = characters;
+		script.spawn_defaults = spawn_defaults;
+		kanna::game::load_resources(ctx, &mut script, settings)?;
+		Ok((script, History::default()))
+	})
+}
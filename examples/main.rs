@@ -3,13 +3,23 @@ use kanna::*;
 pub fn main() -> ggez::GameResult {
 	let mut settings = Settings::default();
 	settings.resource_paths.push(env!("CARGO_MANIFEST_DIR").to_owned() + "/examples/resources");
+	settings.characters_path = Some("/characters.toml".to_owned());
 
 	kanna::game::run(settings, |ctx, settings| {
 		let mut script = kanna::game::load_script(ctx, "/script.txt")?;
-		script.characters = kanna::game::load_characters(ctx, "/characters.toml")?;
-		let history = kanna::game::load_history(ctx, settings)
-			.unwrap_or_else(|_| History::default());
-		kanna::game::load_resources(ctx, &mut script)?;
+		let path = settings.characters_path.as_ref().unwrap();
+		let (characters, spawn_defaults) = kanna::game::load_character_set(ctx, path)?;
+		script.characters = characters;
+		script.spawn_defaults = spawn_defaults;
+		let history = match kanna::game::load_history(ctx, settings) {
+			Ok(history) => history,
+			Err(kanna::game::SaveError::Missing) => History::default(),
+			Err(error) => History {
+				notice: Some(format!("Your save could not be loaded and a new game has been started.\n{}", error)),
+				..History::default()
+			},
+		};
+		kanna::game::load_resources(ctx, &mut script, settings)?;
 		Ok((script, history))
 	})
 }
@@ -1,8 +1,9 @@
-use std::{collections::HashMap, fmt::Debug};
+use std::{collections::{HashMap, HashSet}, fmt::Debug};
 
 use ggez::{self, graphics::Image, timer};
+use serde::{Deserialize, Serialize};
 
-type TransitionMap<T> = HashMap<String, Box<dyn AnimationProducer<T, Parameter=InstanceParameter>>>;
+type TransitionMap<T> = HashMap<String, Box<dyn ErasedAnimationProducer<T>>>;
 
 /// An animation that acts on a struct to provide a visual effect.
 pub trait Animation<A>: Debug {
@@ -20,6 +21,42 @@ pub trait AnimationProducer<A>: Debug {
 	fn initialise(&self, _: A) -> Box<dyn Animation<Self::Parameter>>;
 }
 
+/// An [`AnimationProducer`] that produces animations acting on an [`InstanceParameter`].
+///
+/// This trait is implemented for every such producer and removes the
+/// `Parameter` type so that producers can be stored as trait objects.
+pub trait ErasedAnimationProducer<A>: Debug {
+	fn initialise(&self, _: A) -> Box<dyn Animation<InstanceParameter>>;
+}
+
+impl<A, P> ErasedAnimationProducer<A> for P
+	where P: AnimationProducer<A, Parameter=InstanceParameter> {
+	fn initialise(&self, animation: A) -> Box<dyn Animation<InstanceParameter>> {
+		AnimationProducer::initialise(self, animation)
+	}
+}
+
+/// Errors that stop an animation from being applied.
+#[derive(Debug, PartialEq)]
+pub enum AnimationError {
+	/// There is no animation with the name for the type of command.
+	Unknown(String),
+}
+
+impl std::fmt::Display for AnimationError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			AnimationError::Unknown(name) => write!(f, "Error finding animation named: {}", name),
+		}
+	}
+}
+
+/// A type of command that has its own set of animations in an [`AnimationMap`].
+pub trait AnimationType: Sized {
+	/// Gets the animations that can be used for this type of command.
+	fn transitions(animations: &mut AnimationMap) -> &mut TransitionMap<Self>;
+}
+
 /// Stores all the [`AnimationProducer`]s to be used at runtime.
 #[derive(Debug)]
 pub struct AnimationMap {
@@ -35,32 +72,105 @@ pub struct AnimationMap {
 	pub spawn: TransitionMap<SpawnAnimation>,
 	/// Transitions that can be used for a `Kill` Command.
 	pub kill: TransitionMap<KillAnimation>,
+	/// Transitions that can be used for a `Flip` Command.
+	pub flip: TransitionMap<FlipAnimation>,
+	/// Transitions that can be used for an `Opacity` Command.
+	pub opacity: TransitionMap<OpacityAnimation>,
 }
 
 impl Default for AnimationMap {
 	fn default() -> Self {
 		let mut change = HashMap::with_capacity(1);
+		let mut flip = HashMap::with_capacity(1);
 		let mut hide = HashMap::with_capacity(2);
 		let mut kill = HashMap::with_capacity(2);
+		let mut opacity = HashMap::with_capacity(1);
 		let mut position = HashMap::with_capacity(1);
 		let mut show = HashMap::with_capacity(2);
 		let mut spawn = HashMap::with_capacity(2);
 		change.insert("flip".into(), Box::new(Flip) as Box<_>);
+		flip.insert("mirror".into(), Box::new(Mirror) as Box<_>);
 		hide.insert("fade".into(), Box::new(Fade) as Box<_>);
 		hide.insert("glide".into(), Box::new(Glide) as Box<_>);
 		kill.insert("fade".into(), Box::new(Fade) as Box<_>);
 		kill.insert("glide".into(), Box::new(Glide) as Box<_>);
+		opacity.insert("fade".into(), Box::new(Fade) as Box<_>);
 		position.insert("glide".into(), Box::new(Glide) as Box<_>);
 		show.insert("fade".into(), Box::new(Fade) as Box<_>);
 		show.insert("glide".into(), Box::new(Glide) as Box<_>);
 		spawn.insert("fade".into(), Box::new(Fade) as Box<_>);
 		spawn.insert("glide".into(), Box::new(Glide) as Box<_>);
-		Self { change, hide, kill, position, show, spawn }
+		Self { change, flip, hide, kill, opacity, position, show, spawn }
+	}
+}
+
+impl AnimationMap {
+	/// Registers an animation under a name for a type of command.
+	/// Any animation previously registered with the same name is replaced.
+	pub fn register<A, P>(&mut self, name: impl Into<String>, producer: P)
+		where A: AnimationType, P: AnimationProducer<A, Parameter=InstanceParameter> + 'static {
+		A::transitions(self).insert(name.into(), Box::new(producer));
+	}
+
+	/// Names of the animations that can be used for a `Change` Command.
+	pub fn change_names(&self) -> impl Iterator<Item = &str> {
+		self.change.keys().map(String::as_str)
+	}
+
+	/// Names of the animations that can be used for a `Position` Command.
+	pub fn position_names(&self) -> impl Iterator<Item = &str> {
+		self.position.keys().map(String::as_str)
+	}
+
+	/// Names of the animations that can be used for a `Show` Command.
+	pub fn show_names(&self) -> impl Iterator<Item = &str> {
+		self.show.keys().map(String::as_str)
+	}
+
+	/// Names of the animations that can be used for a `Hide` Command.
+	pub fn hide_names(&self) -> impl Iterator<Item = &str> {
+		self.hide.keys().map(String::as_str)
+	}
+
+	/// Names of the animations that can be used for a `Spawn` Command.
+	pub fn spawn_names(&self) -> impl Iterator<Item = &str> {
+		self.spawn.keys().map(String::as_str)
+	}
+
+	/// Names of the animations that can be used for a `Kill` Command.
+	pub fn kill_names(&self) -> impl Iterator<Item = &str> {
+		self.kill.keys().map(String::as_str)
+	}
+
+	/// Names of the animations that can be used for a `Flip` Command.
+	pub fn flip_names(&self) -> impl Iterator<Item = &str> {
+		self.flip.keys().map(String::as_str)
+	}
+
+	/// Names of the animations that can be used for an `Opacity` Command.
+	pub fn opacity_names(&self) -> impl Iterator<Item = &str> {
+		self.opacity.keys().map(String::as_str)
+	}
+
+	/// Names of every animation that is registered for any type of command.
+	/// This, along with the names for each type of command, is how the available animations are queried.
+	pub fn all_names(&self) -> HashSet<&str> {
+		self.change_names().chain(self.position_names()).chain(self.show_names())
+			.chain(self.hide_names()).chain(self.spawn_names()).chain(self.kill_names())
+			.chain(self.flip_names()).chain(self.opacity_names()).collect()
+	}
+
+	/// Whether an animation is registered under a name for any type of command.
+	pub fn contains(&self, name: &str) -> bool {
+		self.change.contains_key(name) || self.position.contains_key(name)
+			|| self.show.contains_key(name) || self.hide.contains_key(name)
+			|| self.spawn.contains_key(name) || self.kill.contains_key(name)
+			|| self.flip.contains_key(name) || self.opacity.contains_key(name)
 	}
 }
 
 /// Declares what animation is to be used and the variable number of arguments it should be passed in.
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct AnimationDeclaration {
 	/// The name of the animation.
 	pub name: String,
@@ -84,16 +194,36 @@ pub struct InstanceParameter {
 	pub centre_position: (f32, f32),
 	/// Image that this instance draws to the screen.
 	pub image: Image,
+	/// Image drawn on top of the image and the position of its top left corner on the image.
+	pub overlay: Option<(Image, (f32, f32))>,
 	/// Position on the screen in pixels.
 	pub position: (f32, f32),
 	/// Amount the image is scaled by.
 	pub scale: (f32, f32),
+	/// Rotation of the image in radians.
+	pub rotation: f32,
+	/// Whether the image is mirrored horizontally.
+	pub flip_h: bool,
+	/// Whether the image is mirrored vertically.
+	pub flip_v: bool,
 	/// Whether the instance is visible.
 	pub visible: bool,
 	/// The colour of the instance.
 	pub colour: [f32; 4],
 }
 
+impl InstanceParameter {
+	/// The opacity of the instance.
+	pub fn alpha(&self) -> f32 {
+		self.colour[3]
+	}
+
+	/// Sets the opacity of the instance.
+	pub fn set_alpha(&mut self, alpha: f32) {
+		self.colour[3] = alpha;
+	}
+}
+
 /// An animation that is used on the `Position` Command will take in this struct.
 ///
 /// When the animation finishes, the position of the [`Instance`]
@@ -147,15 +277,21 @@ pub struct KillAnimation {
 ///
 /// When the ``finish`` method of the Animation is called,
 /// the `centre_position` of the `Instance` must have the value of `new_centre_position`,
-/// the `image` of the `Instance` must have the value of `new_image`, and
-/// the `scale` of the `Instance` must have the value of `new_scale`.
+/// the `image` of the `Instance` must have the value of `new_image`,
+/// the `overlay` of the `Instance` must have the value of `new_overlay`,
+/// the `scale` of the `Instance` must have the value of `new_scale`, and
+/// the `rotation` of the `Instance` must have the value of `new_rotation`.
 pub struct ChangeAnimation {
 	/// The new centre position that the instance is supposed to switch to by the end.
 	pub new_centre_position: (f32, f32),
 	/// The new image that the instance is supposed to switch to by the end.
 	pub new_image: Image,
+	/// The new overlay that the instance is supposed to switch to by the end.
+	pub new_overlay: Option<(Image, (f32, f32))>,
 	/// The new scale that the instance is supposed to change to by the end.
 	pub new_scale: (f32, f32),
+	/// The new rotation that the instance is supposed to change to by the end.
+	pub new_rotation: f32,
 	/// Extra arguments provided to the Animation.
 	pub arguments: Vec<Option<f32>>,
 }
@@ -163,12 +299,84 @@ pub struct ChangeAnimation {
 impl ChangeAnimation {
 	pub fn new(arguments: Vec<Option<f32>>, character: &super::CharacterName,
 	           script: &super::Script, state: &super::StateName) -> Self {
-		let state = &script.characters[(character, state)];
-		let new_image = script.images.get(&state.image).unwrap_or_else(||
-			panic!("Image at path: {:?}, is not loaded", &state.image)).clone();
-		let new_centre_position = state.centre_position.map(|(x, y)| (x as f32, y as f32))
-			.unwrap_or_else(|| (new_image.width() as f32 / 2.0, new_image.height() as f32 / 2.0));
-		Self { new_centre_position, new_image, new_scale: state.scale, arguments }
+		Self::from_state(arguments, script, &script.characters[(character, state)])
+	}
+
+	/// Creates the animation from a state that does not need to belong to a character.
+	pub fn from_state(arguments: Vec<Option<f32>>, script: &super::Script,
+	                  state: &super::CharacterState) -> Self {
+		let new_image = script.image(&state.image).clone();
+		let new_centre_position = state.centre((new_image.width(), new_image.height()));
+		let new_overlay = script.overlay(state);
+		Self { new_centre_position, new_image, new_overlay, new_scale: state.scale, new_rotation: state.rotation, arguments }
+	}
+}
+
+/// An animation that is used on the `Flip` Command will take in this struct.
+///
+/// When the animation finishes, the `flip_h` of the [`Instance`]
+/// **MUST** be the opposite of its value when the animation started.
+pub struct FlipAnimation {
+	/// Extra arguments provided to the Animation.
+	pub arguments: Vec<Option<f32>>,
+}
+
+/// An animation that is used on the `Opacity` Command will take in this struct.
+///
+/// When the animation finishes, the opacity of the [`Instance`]
+/// **MUST** be the same value as the ``opacity`` field.
+pub struct OpacityAnimation {
+	/// The opacity that the [`Instance`] will eventually have.
+	pub opacity: f32,
+	/// Extra arguments provided to the Animation.
+	pub arguments: Vec<Option<f32>>,
+}
+
+impl AnimationType for PositionAnimation {
+	fn transitions(animations: &mut AnimationMap) -> &mut TransitionMap<Self> {
+		&mut animations.position
+	}
+}
+
+impl AnimationType for ShowAnimation {
+	fn transitions(animations: &mut AnimationMap) -> &mut TransitionMap<Self> {
+		&mut animations.show
+	}
+}
+
+impl AnimationType for HideAnimation {
+	fn transitions(animations: &mut AnimationMap) -> &mut TransitionMap<Self> {
+		&mut animations.hide
+	}
+}
+
+impl AnimationType for SpawnAnimation {
+	fn transitions(animations: &mut AnimationMap) -> &mut TransitionMap<Self> {
+		&mut animations.spawn
+	}
+}
+
+impl AnimationType for KillAnimation {
+	fn transitions(animations: &mut AnimationMap) -> &mut TransitionMap<Self> {
+		&mut animations.kill
+	}
+}
+
+impl AnimationType for ChangeAnimation {
+	fn transitions(animations: &mut AnimationMap) -> &mut TransitionMap<Self> {
+		&mut animations.change
+	}
+}
+
+impl AnimationType for OpacityAnimation {
+	fn transitions(animations: &mut AnimationMap) -> &mut TransitionMap<Self> {
+		&mut animations.opacity
+	}
+}
+
+impl AnimationType for FlipAnimation {
+	fn transitions(animations: &mut AnimationMap) -> &mut TransitionMap<Self> {
+		&mut animations.flip
 	}
 }
 
@@ -221,7 +429,7 @@ fn glide_visibility(arguments: &[Option<f32>], visible: bool,
                     view_dimensions: (f32, f32)) -> Box<GlideVisibility> {
 	let time_period = arguments.first().and_then(|period| *period).unwrap_or(10000.0);
 	let direction = match arguments.get(1).and_then(|direction| *direction).unwrap_or(0.0) {
-		d if d == 1.0 => GlideVisibilityDirection::Right,
+		1.0 => GlideVisibilityDirection::Right,
 		_ => GlideVisibilityDirection::Left,
 	};
 
@@ -263,6 +471,54 @@ impl Animation<InstanceParameter> for GlideMove {
 	}
 }
 
+/// Creates an animation that moves an instance between two positions at a constant speed.
+pub fn tween(start: (f32, f32), end: (f32, f32), time_period: f32) -> Box<dyn Animation<InstanceParameter>> {
+	Box::new(Tween { start, end, time_period, elapsed: 0.0 })
+}
+
+/// An animation that linearly interpolates the position of an instance over time.
+#[derive(Debug)]
+struct Tween {
+	/// The position at the start of the transition.
+	start: (f32, f32),
+	/// The position at the end of the transition.
+	end: (f32, f32),
+	/// How long this animation will last in ms.
+	time_period: f32,
+	/// How long this animation has been running in ms.
+	elapsed: f32,
+}
+
+impl Tween {
+	/// Advances the tween by an amount of milliseconds and finds the position of the instance.
+	/// Returns `None` once the tween has finished.
+	fn step(&mut self, delta: f32) -> Option<(f32, f32)> {
+		self.elapsed += delta;
+		let progress = self.elapsed / self.time_period;
+		match self.elapsed < self.time_period {
+			true => Some((self.start.0 + (self.end.0 - self.start.0) * progress,
+				self.start.1 + (self.end.1 - self.start.1) * progress)),
+			false => None,
+		}
+	}
+}
+
+impl Animation<InstanceParameter> for Tween {
+	fn update(&mut self, parameter: &mut InstanceParameter, ctx: &mut ggez::Context) -> AnimationState {
+		let delta_time = (timer::duration_to_f64(timer::delta(ctx)) * 1_000.0) as f32;
+		match self.step(delta_time) {
+			Some(position) => {
+				parameter.position = position;
+				AnimationState::Continue
+			}
+			None => AnimationState::Finished,
+		}
+	}
+	fn finish(&self, parameter: &mut InstanceParameter) {
+		parameter.position = self.end;
+	}
+}
+
 #[derive(Debug)]
 enum GlideVisibility {
 	Uninitialised { visible: bool, time_period: f32, direction: GlideVisibilityDirection, view_dimensions: (f32, f32) },
@@ -332,7 +588,7 @@ impl AnimationProducer<ShowAnimation> for Fade {
 	type Parameter = InstanceParameter;
 
 	fn initialise(&self, parameters: ShowAnimation) -> Box<dyn Animation<Self::Parameter>> {
-		fade_animation(&parameters.arguments, 0.0, true)
+		fade_animation(&parameters.arguments, true, false)
 	}
 }
 
@@ -340,7 +596,7 @@ impl AnimationProducer<HideAnimation> for Fade {
 	type Parameter = InstanceParameter;
 
 	fn initialise(&self, parameters: HideAnimation) -> Box<dyn Animation<Self::Parameter>> {
-		fade_animation(&parameters.arguments, 1.0, false)
+		fade_animation(&parameters.arguments, false, false)
 	}
 }
 
@@ -348,7 +604,7 @@ impl AnimationProducer<SpawnAnimation> for Fade {
 	type Parameter = InstanceParameter;
 
 	fn initialise(&self, parameters: SpawnAnimation) -> Box<dyn Animation<Self::Parameter>> {
-		fade_animation(&parameters.arguments, 0.0, true)
+		fade_animation(&parameters.arguments, true, true)
 	}
 }
 
@@ -356,59 +612,133 @@ impl AnimationProducer<KillAnimation> for Fade {
 	type Parameter = InstanceParameter;
 
 	fn initialise(&self, parameters: KillAnimation) -> Box<dyn Animation<Self::Parameter>> {
-		fade_animation(&parameters.arguments, 1.0, false)
+		fade_animation(&parameters.arguments, false, false)
+	}
+}
+
+impl AnimationProducer<OpacityAnimation> for Fade {
+	type Parameter = InstanceParameter;
+
+	fn initialise(&self, parameters: OpacityAnimation) -> Box<dyn Animation<Self::Parameter>> {
+		let time_period = fade_period(&parameters.arguments);
+		Box::new(FadeOpacity { time_period, elapsed: 0.0, opacity: parameters.opacity, start: None })
 	}
 }
 
-fn fade_animation(arguments: &[Option<f32>], alpha: f32, visibility: bool) -> Box<FadeVisibility> {
-	let time_period = arguments.first()
-		.and_then(|period| *period).unwrap_or(250.0);
-	let rate = -time_period.recip();
-	Box::new(FadeVisibility { alpha, time_period, rate, visibility })
+fn fade_period(arguments: &[Option<f32>]) -> f32 {
+	arguments.first().and_then(|period| *period).unwrap_or(250.0)
+}
+
+fn fade_animation(arguments: &[Option<f32>], visibility: bool, appear: bool) -> Box<FadeVisibility> {
+	let time_period = fade_period(arguments);
+	Box::new(FadeVisibility { time_period, elapsed: 0.0, visibility, appear, fade: None })
 }
 
 /// An animation that works for both the Show and Hide command.
+/// The instance fades between being invisible and the opacity it had when the fade started,
+/// and keeps that opacity once the fade finishes so that it is restored when it is next shown.
 #[derive(Debug)]
 struct FadeVisibility {
 	/// How long this animation will last in ms.
 	time_period: f32,
-	/// Rate of opacity change per ms.
-	rate: f32,
+	/// How long this animation has been running in ms.
+	elapsed: f32,
 	/// The *intended* visibility at the end of the transition.
 	visibility: bool,
-	/// The current alpha value of the instance.
-	alpha: f32,
+	/// Whether the fade starts from invisible even if the instance is visible.
+	appear: bool,
+	/// The opacity of the instance and the opacity the fade starts from,
+	/// which are taken from the instance when the fade first updates.
+	fade: Option<(f32, f32)>,
+}
+
+impl FadeVisibility {
+	/// Advances the fade by an amount of milliseconds and finds the opacity of the instance.
+	/// Returns `None` once the fade has finished.
+	fn step(&mut self, alpha: f32, visible: bool, delta: f32) -> Option<f32> {
+		let start = if visible && !self.appear { alpha } else { 0.0 };
+		let (opacity, start) = *self.fade.get_or_insert((alpha, start));
+		let end = if self.visibility { opacity } else { 0.0 };
+		self.elapsed += delta;
+		match self.elapsed < self.time_period {
+			true => Some(start + (end - start) * self.elapsed / self.time_period),
+			false => None,
+		}
+	}
 }
 
 impl Animation<InstanceParameter> for FadeVisibility {
 	fn update(&mut self, parameter: &mut InstanceParameter, ctx: &mut ggez::Context) -> AnimationState {
 		let delta_time = (timer::duration_to_f64(timer::delta(ctx)) * 1_000.0) as f32;
-		self.time_period -= delta_time;
-		if self.time_period > 0.0 {
-			self.alpha += self.rate * delta_time;
-			parameter.colour[3] = self.alpha;
-			parameter.visible = true;
-			AnimationState::Continue
-		} else {
-			AnimationState::Finished
+		match self.step(parameter.alpha(), parameter.visible, delta_time) {
+			Some(alpha) => {
+				parameter.set_alpha(alpha);
+				parameter.visible = true;
+				AnimationState::Continue
+			}
+			None => AnimationState::Finished,
 		}
 	}
 	fn finish(&self, parameter: &mut InstanceParameter) {
-		parameter.colour[3] = 1.0;
+		if let Some((opacity, _)) = self.fade {
+			parameter.set_alpha(opacity);
+		}
 		parameter.visible = self.visibility;
 	}
 }
 
+/// An animation that changes the opacity of an instance without changing whether it is visible.
+#[derive(Debug)]
+struct FadeOpacity {
+	/// How long this animation will last in ms.
+	time_period: f32,
+	/// How long this animation has been running in ms.
+	elapsed: f32,
+	/// The opacity at the end of the transition.
+	opacity: f32,
+	/// The opacity of the instance when the fade first updates.
+	start: Option<f32>,
+}
+
+impl FadeOpacity {
+	/// Advances the fade by an amount of milliseconds and finds the opacity of the instance.
+	/// Returns `None` once the fade has finished.
+	fn step(&mut self, alpha: f32, delta: f32) -> Option<f32> {
+		let start = *self.start.get_or_insert(alpha);
+		self.elapsed += delta;
+		match self.elapsed < self.time_period {
+			true => Some(start + (self.opacity - start) * self.elapsed / self.time_period),
+			false => None,
+		}
+	}
+}
+
+impl Animation<InstanceParameter> for FadeOpacity {
+	fn update(&mut self, parameter: &mut InstanceParameter, ctx: &mut ggez::Context) -> AnimationState {
+		let delta_time = (timer::duration_to_f64(timer::delta(ctx)) * 1_000.0) as f32;
+		match self.step(parameter.alpha(), delta_time) {
+			Some(alpha) => {
+				parameter.set_alpha(alpha);
+				AnimationState::Continue
+			}
+			None => AnimationState::Finished,
+		}
+	}
+	fn finish(&self, parameter: &mut InstanceParameter) {
+		parameter.set_alpha(self.opacity);
+	}
+}
+
 #[derive(Debug)]
 pub struct Flip;
 
 impl AnimationProducer<ChangeAnimation> for Flip {
 	type Parameter = InstanceParameter;
 	fn initialise(&self, parameter: ChangeAnimation) -> Box<dyn Animation<Self::Parameter>> {
-		let ChangeAnimation { new_centre_position, new_image, new_scale, arguments } = parameter;
+		let ChangeAnimation { new_centre_position, new_image, new_overlay, new_scale, new_rotation, arguments } = parameter;
 		let time_period = arguments.first().and_then(|o| *o).unwrap_or(100.0);
 		let (time_left, original_scale) = (time_period, None);
-		Box::new(FlipChange { time_period, time_left, new_centre_position, new_image, new_scale, original_scale })
+		Box::new(FlipChange { time_period, time_left, new_centre_position, new_image, new_overlay, new_scale, new_rotation, original_scale })
 	}
 }
 
@@ -418,7 +748,9 @@ struct FlipChange {
 	time_left: f32,
 	new_centre_position: (f32, f32),
 	new_image: Image,
+	new_overlay: Option<(Image, (f32, f32))>,
 	new_scale: (f32, f32),
+	new_rotation: f32,
 	original_scale: Option<(f32, f32)>,
 }
 
@@ -433,7 +765,9 @@ impl Animation<InstanceParameter> for FlipChange {
 			self.time_left -= delta_time;
 			if self.time_left <= 0.0 {
 				parameter.image = self.new_image.clone();
+				parameter.overlay = self.new_overlay.clone();
 				parameter.centre_position = self.new_centre_position;
+				parameter.rotation = self.new_rotation;
 				self.original_scale = Some(self.new_scale);
 			}
 		} else if self.time_left <= -self.time_period {
@@ -448,7 +782,101 @@ impl Animation<InstanceParameter> for FlipChange {
 
 	fn finish(&self, parameter: &mut InstanceParameter) {
 		parameter.image = self.new_image.clone();
+		parameter.overlay = self.new_overlay.clone();
 		parameter.centre_position = self.new_centre_position;
 		parameter.scale = self.new_scale;
+		parameter.rotation = self.new_rotation;
+	}
+}
+
+/// Turns an instance around by squashing it horizontally and stretching it back out mirrored.
+#[derive(Debug)]
+pub struct Mirror;
+
+impl AnimationProducer<FlipAnimation> for Mirror {
+	type Parameter = InstanceParameter;
+	fn initialise(&self, parameter: FlipAnimation) -> Box<dyn Animation<Self::Parameter>> {
+		let time_period = parameter.arguments.first().and_then(|o| *o).unwrap_or(100.0);
+		Box::new(MirrorAnimation { time_period, time_left: time_period, original_scale: None, flipped: false })
+	}
+}
+
+#[derive(Debug)]
+struct MirrorAnimation {
+	time_period: f32,
+	time_left: f32,
+	original_scale: Option<(f32, f32)>,
+	/// Whether the instance has been mirrored yet.
+	flipped: bool,
+}
+
+impl Animation<InstanceParameter> for MirrorAnimation {
+	fn update(&mut self, parameter: &mut InstanceParameter, ctx: &mut ggez::Context) -> AnimationState {
+		let original_scale = *self.original_scale.get_or_insert(parameter.scale);
+		let delta_time = (timer::duration_to_f64(timer::delta(ctx)) * 1_000.0) as f32;
+		if self.time_left <= -self.time_period {
+			return AnimationState::Finished;
+		}
+
+		self.time_left -= delta_time;
+		if self.time_left <= 0.0 && !self.flipped {
+			parameter.flip_h = !parameter.flip_h;
+			self.flipped = true;
+		}
+
+		let squash = (self.time_left.abs() / self.time_period).min(1.0);
+		parameter.scale.0 = original_scale.0 * squash;
+		AnimationState::Continue
+	}
+
+	fn finish(&self, parameter: &mut InstanceParameter) {
+		if let Some(original_scale) = self.original_scale {
+			parameter.scale = original_scale;
+		}
+
+		if !self.flipped {
+			parameter.flip_h = !parameter.flip_h;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn fade_keeps_opacity() {
+		let mut opacity = FadeOpacity { time_period: 100.0, elapsed: 0.0, opacity: 0.5, start: None };
+		assert_eq!(opacity.step(1.0, 50.0), Some(0.75));
+		assert_eq!(opacity.step(0.75, 50.0), None);
+
+		let mut hide = fade_animation(&[Some(100.0)], false, false);
+		assert_eq!(hide.step(0.5, true, 50.0), Some(0.25));
+		assert_eq!(hide.step(0.25, true, 50.0), None);
+		assert_eq!(hide.fade, Some((0.5, 0.5)));
+
+		let mut show = fade_animation(&[Some(100.0)], true, false);
+		assert_eq!(show.step(0.5, false, 50.0), Some(0.25));
+		let mut spawn = fade_animation(&[], true, true);
+		assert_eq!(spawn.step(0.5, true, 125.0), Some(0.25));
+	}
+
+	#[test]
+	fn register_animations() {
+		let mut animations = AnimationMap::default();
+		animations.register::<ShowAnimation, _>("drift", Glide);
+		animations.register::<HideAnimation, _>("fade", Glide);
+		assert!(animations.show_names().any(|name| name == "drift"));
+		assert!(!animations.hide_names().any(|name| name == "drift"));
+		assert_eq!(format!("{:?}", animations.hide["fade"]), "Glide");
+		assert_eq!(format!("{:?}", animations.show["fade"]), "Fade");
+	}
+
+	#[test]
+	fn tween_interpolates() {
+		let mut tween = Tween { start: (0.0, 10.0), end: (100.0, 20.0), time_period: 200.0, elapsed: 0.0 };
+		assert_eq!(tween.step(50.0), Some((25.0, 12.5)));
+		assert_eq!(tween.step(100.0), Some((75.0, 17.5)));
+		assert_eq!(tween.step(50.0), None);
 	}
 }
@@ -0,0 +1,252 @@
+use std::io::Cursor;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use ggez::audio::{self, SoundData, SoundSource};
+use rodio::{Decoder, Sink, Source};
+
+/// Music that is currently playing.
+pub enum Music {
+	/// Music that repeats from the beginning.
+	Repeat(audio::Source),
+	/// Music that repeats a section after playing up to its end.
+	Loop(Sink, LoopPosition),
+}
+
+/// Amount of samples that looped music has played.
+#[derive(Debug, Clone)]
+pub struct LoopPosition {
+	samples: Arc<AtomicUsize>,
+	/// Samples played each second across all channels.
+	rate: usize,
+}
+
+impl Music {
+	/// Plays music that repeats from the beginning.
+	pub fn repeat(ctx: &mut ggez::Context, data: SoundData, volume: f32) -> ggez::GameResult<Self> {
+		let mut source = audio::Source::from_data(ctx, data)?;
+		source.set_volume(volume);
+		source.set_repeat(true);
+		source.play()?;
+		Ok(Music::Repeat(source))
+	}
+
+	/// Plays music from the beginning until the end of the loop
+	/// and then repeats the music between the start and end of the loop.
+	pub fn looped(ctx: &mut ggez::Context, data: SoundData, (start, end): (f32, f32),
+	              volume: f32) -> ggez::GameResult<Self> {
+		let source = LoopSource::new(data, start, end)?;
+		Ok(Music::play_loop(ctx, source, volume))
+	}
+
+	fn play_loop(ctx: &mut ggez::Context, source: LoopSource, volume: f32) -> Self {
+		let position = LoopPosition {
+			samples: source.played.clone(),
+			rate: source.sample_rate() as usize * source.channels() as usize,
+		};
+		let sink = Sink::new(ctx.audio_context.device());
+		sink.set_volume(volume);
+		sink.append(source);
+		Music::Loop(sink, position)
+	}
+
+	/// Plays the music again from a position in seconds, keeping its volume and whether it is paused.
+	/// Sources that repeat from the beginning cannot be sought, so that music is replaced
+	/// by looped music that repeats the whole sound. The music is left as it was if
+	/// the sound cannot be decoded, in which case it keeps playing from where it was.
+	pub fn seek(&mut self, ctx: &mut ggez::Context, data: SoundData,
+	            music_loop: Option<(f32, f32)>, position: f32) -> ggez::GameResult {
+		let (start, end) = music_loop.unwrap_or((0.0, 0.0));
+		let mut source = LoopSource::new(data, start, end)?;
+		source.seek((position.max(0.0) * source.sample_rate() as f32) as usize * source.channels() as usize);
+		let (volume, paused) = match self {
+			Music::Repeat(source) => (source.volume(), source.paused()),
+			Music::Loop(sink, _) => (sink.volume(), sink.is_paused()),
+		};
+
+		*self = Music::play_loop(ctx, source, volume);
+		if paused { self.pause(); }
+		Ok(())
+	}
+
+	/// Pauses the music so that it can be resumed.
+	pub fn pause(&mut self) {
+		match self {
+			Music::Repeat(source) => source.pause(),
+			Music::Loop(sink, _) => sink.pause(),
+		}
+	}
+
+	/// Continues music that was paused.
+	pub fn resume(&mut self) {
+		match self {
+			Music::Repeat(source) => source.resume(),
+			Music::Loop(sink, _) => sink.play(),
+		}
+	}
+
+	/// Time in seconds that the music has been playing for.
+	/// Looped music counts the samples that have been taken from it,
+	/// which may be slightly ahead of what has been heard.
+	pub fn position(&self) -> f32 {
+		match self {
+			Music::Repeat(source) => source.elapsed().as_secs_f32(),
+			Music::Loop(_, LoopPosition { samples, rate }) =>
+				samples.load(Ordering::Relaxed) as f32 / (*rate).max(1) as f32,
+		}
+	}
+}
+
+impl std::fmt::Debug for Music {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			Music::Repeat(source) => f.debug_tuple("Repeat").field(source).finish(),
+			Music::Loop(_, position) => f.debug_tuple("Loop").field(position).finish(),
+		}
+	}
+}
+
+/// Source that plays its samples up to the end of the loop and then repeats the loop.
+/// The samples of the loop are kept as they are decoded so that the loop is only decoded once.
+struct LoopSource<I = Decoder<Cursor<SoundData>>> {
+	samples: I,
+	channels: u16,
+	sample_rate: u32,
+	/// Index of the sample that the loop starts at.
+	start: usize,
+	/// Index of the sample that the loop ends at.
+	end: usize,
+	/// Index of the next sample.
+	position: usize,
+	/// Samples of the loop that have been decoded so far.
+	cache: Vec<i16>,
+	/// Whether the end of the loop has been reached and the cached samples are being repeated.
+	repeating: bool,
+	/// Amount of samples that have been played, including those that were sought past.
+	played: Arc<AtomicUsize>,
+}
+
+impl LoopSource {
+	fn new(data: SoundData, start: f32, end: f32) -> ggez::GameResult<Self> {
+		let decoder = Decoder::new(Cursor::new(data))?;
+		let (channels, sample_rate) = (decoder.channels(), decoder.sample_rate());
+		let (start, end) = loop_samples(sample_rate, channels, start, end);
+		Ok(LoopSource::from_samples(decoder, channels, sample_rate, start, end))
+	}
+}
+
+impl<I: Iterator<Item=i16>> LoopSource<I> {
+	fn from_samples(samples: I, channels: u16, sample_rate: u32, start: usize, end: usize) -> Self {
+		LoopSource { samples, channels, sample_rate, start, end, position: 0,
+			cache: Vec::new(), repeating: false, played: Arc::default() }
+	}
+
+	/// Skips samples as if an amount of samples had already been played.
+	/// Played samples past the end of the loop are wrapped back into the loop.
+	fn seek(&mut self, played: usize) {
+		for _ in 0..loop_sample(played, self.start, self.end) {
+			if self.next().is_none() { break; }
+		}
+		self.played.store(played, Ordering::Relaxed);
+	}
+}
+
+/// Finds the sample indices of the start and end of a loop in seconds.
+/// The indices are aligned to the start of a frame so the channels are not swapped.
+fn loop_samples(sample_rate: u32, channels: u16, start: f32, end: f32) -> (usize, usize) {
+	let sample = |seconds: f32| (seconds.max(0.0) * sample_rate as f32) as usize * channels as usize;
+	(sample(start), sample(end))
+}
+
+/// Finds the sample index that playback reaches after an amount of samples has been played.
+/// Loops without an end repeat the whole sound, whose length is unknown, so the index is not wrapped.
+fn loop_sample(played: usize, start: usize, end: usize) -> usize {
+	match played >= end && end > start {
+		true => start + (played - start) % (end - start),
+		false => played,
+	}
+}
+
+impl<I: Iterator<Item=i16>> Iterator for LoopSource<I> {
+	type Item = i16;
+
+	fn next(&mut self) -> Option<i16> {
+		self.played.fetch_add(1, Ordering::Relaxed);
+		if !self.repeating {
+			let sample = match self.position >= self.end && self.end > self.start {
+				true => None,
+				false => self.samples.next(),
+			};
+
+			match sample {
+				Some(sample) => {
+					if self.position >= self.start { self.cache.push(sample); }
+					self.position += 1;
+					return Some(sample);
+				}
+				None => {
+					self.repeating = true;
+					self.position = self.start;
+				}
+			}
+		}
+
+		let sample = *self.cache.get(self.position - self.start)?;
+		self.position += 1;
+		if self.position - self.start >= self.cache.len() {
+			self.position = self.start;
+		}
+		Some(sample)
+	}
+}
+
+impl<I: Iterator<Item=i16>> Source for LoopSource<I> {
+	fn current_frame_len(&self) -> Option<usize> {
+		None
+	}
+
+	fn channels(&self) -> u16 {
+		self.channels
+	}
+
+	fn sample_rate(&self) -> u32 {
+		self.sample_rate
+	}
+
+	fn total_duration(&self) -> Option<Duration> {
+		None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn loop_sample_alignment() {
+		assert_eq!(loop_samples(44_100, 2, 1.0, 2.5), (88_200, 220_500));
+		assert_eq!(loop_samples(48_000, 1, -1.0, 0.5), (0, 24_000));
+	}
+
+	#[test]
+	fn loop_seek_wraps() {
+		assert_eq!(loop_sample(50, 100, 200), 50);
+		assert_eq!(loop_sample(150, 100, 200), 150);
+		assert_eq!(loop_sample(200, 100, 200), 100);
+		assert_eq!(loop_sample(450, 100, 200), 150);
+		assert_eq!(loop_sample(450, 0, 0), 450);
+	}
+
+	#[test]
+	fn loop_repeats_cached_samples() {
+		let samples = |start, end| LoopSource::from_samples(1..=6, 1, 1, start, end);
+		assert_eq!(samples(2, 4).take(8).collect::<Vec<_>>(), vec![1, 2, 3, 4, 3, 4, 3, 4]);
+		assert_eq!(samples(2, 0).take(10).collect::<Vec<_>>(), vec![1, 2, 3, 4, 5, 6, 3, 4, 5, 6]);
+		assert_eq!(samples(8, 10).collect::<Vec<_>>(), vec![1, 2, 3, 4, 5, 6]);
+
+		let mut source = samples(2, 4);
+		source.seek(7);
+		assert_eq!(source.take(3).collect::<Vec<_>>(), vec![4, 3, 4]);
+	}
+}
@@ -1,35 +1,160 @@
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::ops::{Index, IndexMut};
 use std::path::PathBuf;
+use std::sync::Arc;
 
-use ggez::graphics;
-use serde::Deserialize;
+use ggez::graphics::{self, spritebatch::SpriteBatch};
+use serde::{Deserialize, Serialize};
 
-use crate::{animation::{Animation, AnimationState, InstanceParameter}, Script};
+use crate::{Script, Settings};
+use crate::animation::{Animation, AnimationError, AnimationMap, AnimationState, HideAnimation, InstanceParameter,
+	KillAnimation, ShowAnimation};
 
-#[derive(Debug, Deserialize, Clone, Hash, Eq, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, Hash, Eq, PartialEq)]
 #[serde(transparent)]
-pub struct CharacterName(pub String);
+pub struct CharacterName(pub Arc<str>);
 
-#[derive(Debug, Clone, Hash, Eq, PartialEq)]
-pub struct InstanceName(pub String);
+#[derive(Debug, Serialize, Deserialize, Clone, Hash, Eq, PartialEq)]
+#[serde(transparent)]
+pub struct InstanceName(pub Arc<str>);
 
-#[derive(Debug, Deserialize, Hash, Eq, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, Hash, Eq, PartialEq)]
 #[serde(transparent)]
-pub struct StateName(pub String);
+pub struct StateName(pub Arc<str>);
+
+name!(CharacterName);
+name!(InstanceName);
+name!(StateName);
 
 /// A state represents a possible character image.
-#[derive(Debug, Deserialize, Clone)]
+///
+/// In a characters file each state is a table under its character, such as `[Girl.Happy]`.
+/// Only `image` is required and every other field falls back to its default when it is left out.
+/// Pairs of numbers are written as arrays.
+///
+/// ```toml
+/// [Girl.Happy]
+/// image = "/girl-happy.png"      # required
+/// centre_position = [160, 300]   # default: none, the anchor is used
+/// anchor = "BottomCentre"        # default: "Centre", or an array of two pixel coordinates
+/// scale = [0.5, 0.5]             # default: [1.0, 1.0]
+/// rotation = 0.05                # default: 0.0
+/// spawn_sound = "/giggle.ogg"    # default: none
+/// overlay = "/blush.png"         # default: none
+/// overlay_offset = [120, 80]     # default: [0.0, 0.0]
+/// ```
+///
+/// A state with a `base` is a composite state instead, which only accepts
+/// `base`, `overlay`, `overlay_offset` and `spawn_sound`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct CharacterState {
 	/// Path to the image.
 	pub image: PathBuf,
 	/// Centre of the image in pixels.
 	/// This is used when the image sets its position, is scaled, or is rotated.
 	/// If no position is specified then the pixel centre of the image is used.
+	#[serde(default)]
 	pub centre_position: Option<(u16, u16)>,
 	/// Amount this image is to be scaled by.
 	/// Default is `(1.0, 1.0)` (normal size).
+	#[serde(default = "CharacterState::default_scale")]
 	pub scale: (f32, f32),
+	/// Rotation of the image in radians, positive values rotate clockwise.
+	/// Default is `0.0` (no rotation).
+	#[serde(default)]
+	pub rotation: f32,
+	/// Path to a sound that is played when an instance enters this state.
+	#[serde(default)]
+	pub spawn_sound: Option<PathBuf>,
+	/// Path to an image that is drawn on top of the image.
+	#[serde(default)]
+	pub overlay: Option<PathBuf>,
+	/// Position of the top left corner of the overlay on the image in pixels.
+	#[serde(default)]
+	pub overlay_offset: (f32, f32),
+	/// Point of the image that is placed at the position of the instance.
+	/// This is only used if no centre position is specified.
+	#[serde(default)]
+	pub anchor: AnchorPoint,
+}
+
+/// A point of an image that is used as its centre.
+/// Named points are written as strings such as `"BottomCentre"`
+/// and custom points are written as an array of two pixel coordinates.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(try_from = "AnchorDefinition", into = "AnchorDefinition")]
+pub enum AnchorPoint {
+	#[default]
+	Centre,
+	TopLeft,
+	TopCentre,
+	TopRight,
+	BottomLeft,
+	BottomCentre,
+	BottomRight,
+	/// A point in pixels from the top left corner of the image.
+	Custom(u16, u16),
+}
+
+impl AnchorPoint {
+	/// Finds the position of the point in pixels on an image of the given size.
+	pub fn position(&self, (width, height): (u16, u16)) -> (f32, f32) {
+		let (width, height) = (width as f32, height as f32);
+		match *self {
+			AnchorPoint::Centre => (width / 2.0, height / 2.0),
+			AnchorPoint::TopLeft => (0.0, 0.0),
+			AnchorPoint::TopCentre => (width / 2.0, 0.0),
+			AnchorPoint::TopRight => (width, 0.0),
+			AnchorPoint::BottomLeft => (0.0, height),
+			AnchorPoint::BottomCentre => (width / 2.0, height),
+			AnchorPoint::BottomRight => (width, height),
+			AnchorPoint::Custom(x, y) => (x as f32, y as f32),
+		}
+	}
+}
+
+/// An anchor point as it is written in a character set.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum AnchorDefinition {
+	Named(String),
+	Custom(u16, u16),
+}
+
+impl AnchorPoint {
+	const NAMED: [(&'static str, AnchorPoint); 7] = [
+		("Centre", AnchorPoint::Centre),
+		("TopLeft", AnchorPoint::TopLeft),
+		("TopCentre", AnchorPoint::TopCentre),
+		("TopRight", AnchorPoint::TopRight),
+		("BottomLeft", AnchorPoint::BottomLeft),
+		("BottomCentre", AnchorPoint::BottomCentre),
+		("BottomRight", AnchorPoint::BottomRight),
+	];
+}
+
+impl TryFrom<AnchorDefinition> for AnchorPoint {
+	type Error = String;
+
+	fn try_from(definition: AnchorDefinition) -> Result<Self, Self::Error> {
+		match definition {
+			AnchorDefinition::Custom(x, y) => Ok(AnchorPoint::Custom(x, y)),
+			AnchorDefinition::Named(name) => AnchorPoint::NAMED.iter()
+				.find(|(anchor, _)| *anchor == name).map(|(_, anchor)| *anchor)
+				.ok_or_else(|| format!("Anchor: {}, is not a known anchor point", name)),
+		}
+	}
+}
+
+impl From<AnchorPoint> for AnchorDefinition {
+	fn from(anchor: AnchorPoint) -> Self {
+		match anchor {
+			AnchorPoint::Custom(x, y) => AnchorDefinition::Custom(x, y),
+			_ => AnchorPoint::NAMED.iter().find(|(_, named)| *named == anchor)
+				.map(|(name, _)| AnchorDefinition::Named(name.to_string())).unwrap(),
+		}
+	}
 }
 
 impl CharacterState {
@@ -38,21 +163,120 @@ impl CharacterState {
 		Self {
 			image: path.into(),
 			centre_position: None,
-			scale: (1.0, 1.0),
+			scale: Self::default_scale(),
+			rotation: 0.0,
+			spawn_sound: None,
+			overlay: None,
+			overlay_offset: (0.0, 0.0),
+			anchor: AnchorPoint::Centre,
 		}
 	}
 
+	/// Scale of states that do not specify one, which is the normal size.
+	fn default_scale() -> (f32, f32) {
+		(1.0, 1.0)
+	}
+
 	/// Sets the centre of the image in pixels.
 	pub fn centre_position(mut self, (x, y): (u16, u16)) -> Self {
 		self.centre_position = Some((x, y));
 		self
 	}
 
+	/// Sets the point of the image that is used as its centre.
+	pub fn anchor(mut self, anchor: AnchorPoint) -> Self {
+		self.anchor = anchor;
+		self
+	}
+
+	/// Finds the centre of an image of the given size in pixels.
+	/// The centre position is used if it is specified and the anchor otherwise.
+	pub fn centre(&self, size: (u16, u16)) -> (f32, f32) {
+		self.centre_position.map(|(x, y)| (x as f32, y as f32))
+			.unwrap_or_else(|| self.anchor.position(size))
+	}
+
+	/// Finds the centre, scale and rotation that an instance displaying the state
+	/// with an image of the given size in pixels starts with.
+	pub fn transform(&self, size: (u16, u16)) -> ((f32, f32), (f32, f32), f32) {
+		(self.centre(size), self.scale, self.rotation)
+	}
+
 	/// Sets the scaling of the image.
 	pub fn scale(mut self, (x, y): (f32, f32)) -> Self {
 		self.scale = (x, y);
 		self
 	}
+
+	/// Checks that the scale has two positive components.
+	pub fn validate(&self) -> Result<(), String> {
+		let (x, y) = self.scale;
+		match x > 0.0 && y > 0.0 {
+			false => Err(format!("Scale: {:?}, must have two positive components", self.scale)),
+			true => Ok(()),
+		}
+	}
+
+	/// Checks that the centre position or custom anchor lies within an image of the given size.
+	pub fn validate_centre(&self, (width, height): (u16, u16)) -> Result<(), String> {
+		let centre = self.centre_position.or(match self.anchor {
+			AnchorPoint::Custom(x, y) => Some((x, y)),
+			_ => None,
+		});
+
+		match centre {
+			Some((x, y)) if x > width || y > height => Err(format!("Centre position: {:?}, \
+				is outside of the image at: {}, with size: {:?}", (x, y), self.image.display(), (width, height))),
+			_ => Ok(()),
+		}
+	}
+
+	/// Sets the rotation of the image in radians.
+	pub fn rotation(mut self, rotation: f32) -> Self {
+		self.rotation = rotation;
+		self
+	}
+
+	/// Sets the sound played when an instance enters this state.
+	pub fn spawn_sound<P: Into<PathBuf>>(mut self, path: P) -> Self {
+		self.spawn_sound = Some(path.into());
+		self
+	}
+
+	/// Sets the image drawn on top of the image and the position of its top left corner.
+	pub fn overlay<P: Into<PathBuf>>(mut self, path: P, offset: (f32, f32)) -> Self {
+		self.overlay = Some(path.into());
+		self.overlay_offset = offset;
+		self
+	}
+}
+
+/// A state that draws an overlay on top of another state of the same character.
+#[derive(Debug, Deserialize)]
+struct CompositeState {
+	/// State that provides the image, centre position, scale and rotation.
+	base: StateName,
+	/// Path to the image that is drawn on top of the base.
+	overlay: PathBuf,
+	/// Position of the top left corner of the overlay on the base image in pixels.
+	#[serde(default)]
+	overlay_offset: (f32, f32),
+	/// Path to a sound that is played when an instance enters this state.
+	#[serde(default)]
+	spawn_sound: Option<PathBuf>,
+}
+
+/// The fields of a state that determine whether it is a composite state.
+#[derive(Deserialize)]
+struct StateBase {
+	#[serde(default)]
+	base: Option<StateName>,
+}
+
+/// A state before any base states have been resolved.
+enum StateDefinition {
+	State(CharacterState),
+	Composite(CompositeState),
 }
 
 /// A character that has been spawned onto the screen.
@@ -62,36 +286,79 @@ pub struct Instance {
 	pub animation: Option<Box<dyn Animation<InstanceParameter>>>,
 	/// Character which this instance belongs to.
 	pub character: CharacterName,
+	/// State of the character that this instance displays.
+	pub state: StateName,
+	/// Path of an image that is displayed instead of the image of the state.
+	pub sprite: Option<PathBuf>,
 	/// Position of the image centre in pixels.
 	/// This determines the centre of rotation and scaling.
 	pub centre_position: (f32, f32),
 	/// Image that this instance draws to the screen.
 	pub image: graphics::Image,
+	/// Image drawn on top of the image and the position of its top left corner on the image.
+	pub overlay: Option<(graphics::Image, (f32, f32))>,
 	/// Position on the screen in pixels.
 	pub position: (f32, f32),
 	/// Amount the image is scaled by.
 	pub scale: (f32, f32),
+	/// Rotation of the image in radians, positive values rotate clockwise.
+	pub rotation: f32,
+	/// Whether the image is mirrored horizontally.
+	pub flip_h: bool,
+	/// Whether the image is mirrored vertically.
+	pub flip_v: bool,
 	/// Whether the instance is visible.
 	pub visible: bool,
 	/// The colour of the image.
 	pub colour: [f32; 4],
+	/// Tint from speaker highlighting that the colour is multiplied by.
+	/// This is kept apart from the colour so that it never overrides colours set by the script.
+	pub highlight: [f32; 3],
+	/// Tint that the highlight is moving towards.
+	pub highlight_target: [f32; 3],
 	/// 'To Be Killed' - Whether this instance should be removed after the animation finished.
 	pub tbk: bool,
 }
 
 impl Instance {
 	/// Creates a new instance.
-	pub fn new(script: &Script, character: CharacterName, state: &StateName, position: (f32, f32)) -> Self {
-		let state = &script.characters[(&character, state)];
-		let image = script.images.get(&state.image).unwrap_or_else(||
-			panic!("Image at path: {:?}, is not loaded", &state.image)).clone();
-		let centre_position = state.centre_position.map(|(x, y)| (x as f32, y as f32))
-			.unwrap_or_else(|| (image.width() as f32 / 2.0, image.height() as f32 / 2.0));
-		Instance { animation: None, character, centre_position, colour: [1.0; 4], image, position, scale: state.scale, visible: true, tbk: false }
+	pub fn new(script: &Script, character: CharacterName, state_name: &StateName, position: (f32, f32)) -> Self {
+		let state = &script.characters[(&character, state_name)];
+		let image = script.image(&state.image).clone();
+		let (centre_position, scale, rotation) = state.transform((image.width(), image.height()));
+		let overlay = script.overlay(state);
+		Instance { animation: None, character, state: state_name.clone(), sprite: None, centre_position, colour: [1.0; 4], highlight: [1.0; 3], highlight_target: [1.0; 3], image, overlay, position, scale, rotation, flip_h: false, flip_v: false, visible: true, tbk: false }
+	}
+
+	/// Displays an image that does not belong to the character of the instance.
+	pub fn set_sprite(&mut self, script: &Script, sprite: &CharacterState) {
+		self.apply_state(script, sprite);
+		self.sprite = Some(sprite.image.clone());
+	}
+
+	/// Applies the current definition of the state of the instance again.
+	/// This is used after the characters have been reloaded.
+	/// Instances that display a sprite are unchanged.
+	pub fn refresh(&mut self, script: &Script) {
+		if self.sprite.is_none() {
+			self.apply_state(script, &script.characters[(&self.character, &self.state)]);
+		}
+	}
+
+	/// Sets the image, centre, scale and rotation of the instance from a state.
+	fn apply_state(&mut self, script: &Script, state: &CharacterState) {
+		self.image = script.image(&state.image).clone();
+		self.overlay = script.overlay(state);
+		let (centre_position, scale, rotation) = state.transform((self.image.width(), self.image.height()));
+		self.centre_position = centre_position;
+		self.scale = scale;
+		self.rotation = rotation;
 	}
 
 	/// The instance progresses any animation it contains.
 	fn update(&mut self, ctx: &mut ggez::Context) {
+		let step = ggez::timer::delta(ctx).as_secs_f32() * 1_000.0 / HIGHLIGHT_DURATION;
+		self.highlight = step_tint(self.highlight, self.highlight_target, step);
 		if self.animation.is_some() {
 			let mut parameters = self.create_parameter();
 			match self.animation.as_mut().unwrap().update(&mut parameters, ctx) {
@@ -104,20 +371,64 @@ impl Instance {
 		}
 	}
 
-	/// Draws the instance to the screen.
-	pub fn draw(&self, ctx: &mut ggez::Context) -> ggez::GameResult {
+	/// Draws the instance to the screen with the camera offset subtracted from its position.
+	pub fn draw(&self, ctx: &mut ggez::Context, camera: (f32, f32)) -> ggez::GameResult {
+		self.layers().try_for_each(|(image, param)| graphics::draw(ctx, image, offset(param, camera)))
+	}
+
+	/// The images of the instance and the parameters used to draw them in order.
+	pub fn layers(&self) -> impl Iterator<Item=(&graphics::Image, graphics::DrawParam)> {
+		std::iter::once((&self.image, self.draw_param())).chain(self.overlay_param())
+	}
+
+	/// Creates the parameters used to draw the overlay with the same transform as the image.
+	/// The overlay is offset so that it is rotated and scaled around the centre of the image.
+	pub fn overlay_param(&self) -> Option<(&graphics::Image, graphics::DrawParam)> {
+		let (image, (overlay_x, overlay_y)) = self.overlay.as_ref()?;
+		let (centre_x, centre_y) = self.centre_position;
+		let offset_x = (centre_x - overlay_x) / image.width() as f32;
+		let offset_y = (centre_y - overlay_y) / image.height() as f32;
+		Some((image, self.draw_param().offset([offset_x, offset_y])))
+	}
+
+	/// Creates the parameters used to draw the image of the instance.
+	pub fn draw_param(&self) -> graphics::DrawParam {
 		let (centre_x, centre_y) = self.centre_position;
 		let offset_x = centre_x / self.image.width() as f32;
 		let offset_y = centre_y / self.image.height() as f32;
 
 		let (scale_x, scale_y) = self.scale;
+		let scale_x = if self.flip_h { -scale_x } else { scale_x };
+		let scale_y = if self.flip_v { -scale_y } else { scale_y };
 		let (position_x, position_y) = self.position;
-		let draw_params = graphics::DrawParam::new()
+		graphics::DrawParam::new()
 			.dest([position_x, position_y])
 			.offset([offset_x, offset_y])
 			.scale([scale_x, scale_y])
-			.color(self.colour.into());
-		graphics::draw(ctx, &self.image, draw_params)
+			.rotation(self.rotation)
+			.color(self.tinted_colour().into())
+	}
+
+	/// The colour of the image multiplied by the speaker highlight.
+	fn tinted_colour(&self) -> [f32; 4] {
+		let [red, green, blue, alpha] = self.colour;
+		let [tint_red, tint_green, tint_blue] = self.highlight;
+		[red * tint_red, green * tint_green, blue * tint_blue, alpha]
+	}
+
+	/// The opacity of the image.
+	pub fn alpha(&self) -> f32 {
+		self.colour[3]
+	}
+
+	/// Sets the opacity of the image.
+	pub fn set_alpha(&mut self, alpha: f32) {
+		self.colour[3] = alpha;
+	}
+
+	/// Whether the image is fully transparent and does not need to be drawn.
+	pub fn is_transparent(&self) -> bool {
+		self.alpha() < f32::EPSILON
 	}
 
 	/// Adds an animation onto the Instance.
@@ -130,6 +441,11 @@ impl Instance {
 		}
 	}
 
+	/// Checks whether the instance has an animation that is running.
+	pub fn is_animating(&self) -> bool {
+		self.animation.is_some()
+	}
+
 	/// Finish any animation the Instance has.
 	pub fn finish_animation(&mut self) {
 		if let Some(animation) = self.animation.take() {
@@ -139,13 +455,72 @@ impl Instance {
 		}
 	}
 
+	/// Creates a copy of the instance at a different position.
+	/// Any animation is not copied, the copy has the values the instance will have once it finishes.
+	pub fn duplicate(&self, position: (f32, f32)) -> Self {
+		let mut parameters = self.create_parameter();
+		if let Some(animation) = &self.animation {
+			animation.finish(&mut parameters);
+		}
+
+		let InstanceParameter { centre_position, image, overlay, scale, rotation, flip_h, flip_v, visible, colour, .. } = parameters;
+		Instance {
+			animation: None,
+			character: self.character.clone(),
+			state: self.state.clone(),
+			sprite: self.sprite.clone(),
+			centre_position, image, overlay, position, scale, rotation, flip_h, flip_v, visible, colour,
+			highlight: self.highlight,
+			highlight_target: self.highlight_target,
+			tbk: false,
+		}
+	}
+
+	/// Creates a snapshot of the instance that can be used to recreate it.
+	pub fn snapshot(&self) -> InstanceSnapshot {
+		InstanceSnapshot {
+			character: self.character.clone(),
+			state: self.state.clone(),
+			sprite: self.sprite.clone(),
+			position: self.position,
+			scale: self.scale,
+			rotation: self.rotation,
+			flip_h: self.flip_h,
+			flip_v: self.flip_v,
+			colour: self.colour,
+			visible: self.visible,
+		}
+	}
+
+	/// Recreates an instance from a snapshot.
+	/// The images of the state and sprite must already be loaded.
+	pub fn from_snapshot(script: &Script, snapshot: InstanceSnapshot) -> Self {
+		let InstanceSnapshot { character, state, sprite, position, scale, rotation, flip_h, flip_v, colour, visible } = snapshot;
+		let mut instance = Instance::new(script, character, &state, position);
+		if let Some(path) = sprite {
+			instance.set_sprite(script, &CharacterState::new(path));
+		}
+
+		instance.scale = scale;
+		instance.rotation = rotation;
+		instance.flip_h = flip_h;
+		instance.flip_v = flip_v;
+		instance.colour = colour;
+		instance.visible = visible;
+		instance
+	}
+
 	/// Creates a parameter struct that will be given to the animation.
 	fn create_parameter(&self) -> InstanceParameter {
 		InstanceParameter {
 			centre_position: self.centre_position,
 			image: self.image.clone(),
+			overlay: self.overlay.clone(),
 			position: self.position,
 			scale: self.scale,
+			rotation: self.rotation,
+			flip_h: self.flip_h,
+			flip_v: self.flip_v,
 			visible: self.visible,
 			colour: self.colour,
 		}
@@ -155,41 +530,402 @@ impl Instance {
 	fn update_with_parameter(&mut self, parameters: InstanceParameter) {
 		self.centre_position = parameters.centre_position;
 		self.image = parameters.image;
+		self.overlay = parameters.overlay;
 		self.position = parameters.position;
 		self.scale = parameters.scale;
+		self.rotation = parameters.rotation;
+		self.flip_h = parameters.flip_h;
+		self.flip_v = parameters.flip_v;
 		self.visible = parameters.visible;
 		self.colour = parameters.colour;
 	}
 }
 
+/// The values of an instance that are needed to recreate it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct InstanceSnapshot {
+	/// Character which the instance belongs to.
+	pub character: CharacterName,
+	/// State of the character that the instance displays.
+	pub state: StateName,
+	/// Path of the image displayed instead of the image of the state.
+	#[serde(default)]
+	pub sprite: Option<PathBuf>,
+	/// Position on the screen in pixels.
+	pub position: (f32, f32),
+	/// Amount the image is scaled by.
+	pub scale: (f32, f32),
+	/// Rotation of the image in radians.
+	#[serde(default)]
+	pub rotation: f32,
+	/// Whether the image is mirrored horizontally.
+	#[serde(default)]
+	pub flip_h: bool,
+	/// Whether the image is mirrored vertically.
+	#[serde(default)]
+	pub flip_v: bool,
+	/// The colour of the image.
+	pub colour: [f32; 4],
+	/// Whether the instance is visible.
+	pub visible: bool,
+}
+
+/// Time in milliseconds taken for the speaker highlight to change fully between black and white.
+const HIGHLIGHT_DURATION: f32 = 150.0;
+
+/// Moves each channel of a tint towards a target by at most a step.
+fn step_tint(mut tint: [f32; 3], target: [f32; 3], step: f32) -> [f32; 3] {
+	tint.iter_mut().zip(&target).for_each(|(channel, target)|
+		*channel += (target - *channel).max(-step).min(step));
+	tint
+}
+
+/// The instances on the stage without any of their images or animations.
+/// Each instance is saved as a table with its name so that the order is kept.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(from = "Vec<NamedInstanceSnapshot>", into = "Vec<NamedInstanceSnapshot>")]
+pub struct StageSnapshot {
+	/// Instances in the order they are drawn.
+	pub instances: Vec<(InstanceName, InstanceSnapshot)>,
+}
+
+/// An instance snapshot that is saved with the name of the instance.
+#[derive(Serialize, Deserialize)]
+struct NamedInstanceSnapshot {
+	name: InstanceName,
+	#[serde(flatten)]
+	instance: InstanceSnapshot,
+}
+
+impl From<Vec<NamedInstanceSnapshot>> for StageSnapshot {
+	fn from(instances: Vec<NamedInstanceSnapshot>) -> Self {
+		let instances = instances.into_iter().map(|NamedInstanceSnapshot { name, instance }| (name, instance));
+		StageSnapshot { instances: instances.collect() }
+	}
+}
+
+impl From<StageSnapshot> for Vec<NamedInstanceSnapshot> {
+	fn from(snapshot: StageSnapshot) -> Self {
+		snapshot.instances.into_iter().map(|(name, instance)| NamedInstanceSnapshot { name, instance }).collect()
+	}
+}
+
+/// Orders the entries of a stage by a draw order.
+/// Listed entries come first in the order they are listed, skipping any that do not exist,
+/// followed by the remaining entries sorted by name.
+fn draw_ordered<'a, T>(stage: &'a HashMap<InstanceName, T>,
+                       draw_order: Option<&[InstanceName]>) -> Vec<(&'a InstanceName, &'a T)> {
+	let draw_order = draw_order.unwrap_or(&[]);
+	let listed = draw_order.iter().filter_map(|name| stage.get_key_value(name));
+	let mut remaining: Vec<_> = stage.iter().filter(|(name, _)| !draw_order.contains(name)).collect();
+	remaining.sort_by(|(InstanceName(a), _), (InstanceName(b), _)| a.cmp(b));
+	listed.chain(remaining).collect()
+}
+
+/// Groups consecutive layers that share the same image so that each group can be drawn in one call.
+/// Layers are only grouped with their neighbours so that the draw order is unchanged.
+fn batched<K: PartialEq, T>(layers: impl IntoIterator<Item=(K, T)>) -> Vec<(K, Vec<T>)> {
+	let mut batches: Vec<(K, Vec<T>)> = Vec::new();
+	for (key, layer) in layers {
+		match batches.last_mut() {
+			Some((last, batch)) if *last == key => batch.push(layer),
+			_ => batches.push((key, vec![layer])),
+		}
+	}
+	batches
+}
+
+/// Offsets the layers by the camera and groups consecutive layers that share the same image.
+fn draw_calls<K: PartialEq>(layers: impl IntoIterator<Item=(K, graphics::DrawParam)>,
+                            camera: (f32, f32)) -> Vec<(K, Vec<graphics::DrawParam>)> {
+	batched(layers.into_iter().map(|(image, param)| (image, offset(param, camera))))
+}
+
+/// Sprite batches that are kept between frames, one for each image drawn by the stage.
+#[derive(Debug, Default)]
+pub struct SpriteBatches(Vec<(graphics::Image, SpriteBatch)>);
+
+impl SpriteBatches {
+	/// Draws an image with each of the parameters.
+	/// A single image is drawn directly, otherwise the batch for the image is cleared and refilled.
+	fn draw(&mut self, ctx: &mut ggez::Context, image: &graphics::Image,
+	        params: Vec<graphics::DrawParam>) -> ggez::GameResult {
+		if let [param] = params[..] {
+			return graphics::draw(ctx, image, param);
+		}
+
+		let SpriteBatches(batches) = self;
+		let index = batches.iter().position(|(other, _)| other == image).unwrap_or_else(|| {
+			batches.push((image.clone(), SpriteBatch::new(image.clone())));
+			batches.len() - 1
+		});
+
+		let (_, batch) = &mut batches[index];
+		batch.clear();
+		params.into_iter().for_each(|param| { batch.add(param); });
+		graphics::draw(ctx, batch, graphics::DrawParam::new())
+	}
+
+	/// Removes the batches of images that are no longer drawn.
+	fn retain(&mut self, images: &[&graphics::Image]) {
+		let SpriteBatches(batches) = self;
+		batches.retain(|(image, _)| images.contains(&image));
+	}
+}
+
+/// Creates a draw order of every entry on a stage with an entry moved to the end or the start.
+fn reorder<T>(stage: &HashMap<InstanceName, T>, draw_order: Option<&[InstanceName]>,
+              name: &InstanceName, last: bool) -> Vec<InstanceName> {
+	let (moved, order): (Vec<_>, Vec<_>) = draw_ordered(stage, draw_order).into_iter()
+		.map(|(name, _)| name.clone()).partition(|other| other == name);
+	match last {
+		true => order.into_iter().chain(moved).collect(),
+		false => moved.into_iter().chain(order).collect(),
+	}
+}
+
+/// Updates a draw order after an instance has been renamed.
+/// Any entry for an instance that was replaced by the rename is removed.
+pub(crate) fn rename_in_order(draw_order: &mut Vec<InstanceName>, name: &InstanceName, new_name: &InstanceName) {
+	draw_order.retain(|other| other != new_name);
+	draw_order.iter_mut().filter(|other| *other == name).for_each(|other| *other = new_name.clone());
+}
+
+/// Updates a draw order after an instance has been copied.
+/// The copy is drawn directly above its source if the source is listed.
+pub(crate) fn copy_in_order(draw_order: &mut Vec<InstanceName>, name: &InstanceName, new_name: &InstanceName) {
+	draw_order.retain(|other| other != new_name);
+	if let Some(index) = draw_order.iter().position(|other| other == name) {
+		draw_order.insert(index + 1, new_name.clone());
+	}
+}
+
 /// Holds all the current instances.
 #[derive(Debug, Default)]
 pub struct Stage(pub HashMap<InstanceName, Instance>);
 
 impl Stage {
+	/// Applies the current definitions of the characters to all the instances.
+	pub fn refresh(&mut self, script: &Script) {
+		let Stage(stage) = self;
+		stage.values_mut().for_each(|instance| instance.refresh(script))
+	}
+
+	/// Brightens the instances of the speaking character and dims every other instance.
+	/// Every instance is brightened if there is no speaker.
+	pub fn highlight_speaker(&mut self, speaker: Option<&CharacterName>, inactive_tint: [f32; 3]) {
+		let Stage(stage) = self;
+		stage.values_mut().for_each(|instance| instance.highlight_target =
+			match speaker.map(|speaker| speaker == &instance.character).unwrap_or(true) {
+				true => [1.0; 3],
+				false => inactive_tint,
+			});
+	}
+
+	/// Applies a show animation to every instance on the stage.
+	pub fn animate_all_show(&mut self, animations: &AnimationMap, name: &str, arguments: Vec<Option<f32>>,
+	                        view_dimensions: (f32, f32)) -> Result<(), AnimationError> {
+		let producer = animations.show.get(name).ok_or_else(|| AnimationError::Unknown(name.to_owned()))?;
+		self.living_mut().for_each(|instance| instance.add_animation(producer
+			.initialise(ShowAnimation { arguments: arguments.clone(), view_dimensions })));
+		Ok(())
+	}
+
+	/// Applies a hide animation to every instance on the stage.
+	pub fn animate_all_hide(&mut self, animations: &AnimationMap, name: &str, arguments: Vec<Option<f32>>,
+	                        view_dimensions: (f32, f32)) -> Result<(), AnimationError> {
+		let producer = animations.hide.get(name).ok_or_else(|| AnimationError::Unknown(name.to_owned()))?;
+		self.living_mut().for_each(|instance| instance.add_animation(producer
+			.initialise(HideAnimation { arguments: arguments.clone(), view_dimensions })));
+		Ok(())
+	}
+
+	/// Applies a kill animation to every instance on the stage.
+	/// The instances are removed once their animations finish.
+	pub fn animate_all_kill(&mut self, animations: &AnimationMap, name: &str, arguments: Vec<Option<f32>>,
+	                        view_dimensions: (f32, f32)) -> Result<(), AnimationError> {
+		let producer = animations.kill.get(name).ok_or_else(|| AnimationError::Unknown(name.to_owned()))?;
+		self.living_mut().for_each(|instance| {
+			instance.add_animation(producer.initialise(KillAnimation { arguments: arguments.clone(), view_dimensions }));
+			instance.tbk = true;
+		});
+		Ok(())
+	}
+
+	/// Finds the names of the instances of a character that are not about to be killed.
+	pub fn instances_of(&self, character: &CharacterName) -> Vec<InstanceName> {
+		let Stage(stage) = self;
+		stage.iter().filter(|(_, instance)| !instance.tbk && &instance.character == character)
+			.map(|(name, _)| name.clone()).collect()
+	}
+
+	/// Instances that are not about to be killed.
+	fn living_mut(&mut self) -> impl Iterator<Item=&mut Instance> {
+		let Stage(stage) = self;
+		stage.values_mut().filter(|instance| !instance.tbk)
+	}
+
+	/// Finds a visible instance of a speaking character.
+	/// The instance with the same name as the character is preferred.
+	pub fn speaker(&self, character: &CharacterName) -> Option<&Instance> {
+		let Stage(stage) = self;
+		let CharacterName(name) = character;
+		let visible = |instance: &&Instance| instance.visible && !instance.tbk && &instance.character == character;
+		stage.get(&InstanceName(name.clone())).filter(visible)
+			.or_else(|| stage.iter().filter(|(_, instance)| visible(instance))
+				.min_by(|(a, _), (b, _)| a.0.cmp(&b.0)).map(|(_, instance)| instance))
+	}
+
+	/// Creates a snapshot of all the instances that are not about to be killed in the order they are drawn.
+	pub fn snapshot(&self, draw_order: Option<&[InstanceName]>) -> StageSnapshot {
+		let Stage(stage) = self;
+		StageSnapshot {
+			instances: draw_ordered(stage, draw_order).into_iter().filter(|(_, instance)| !instance.tbk)
+				.map(|(name, instance)| (name.clone(), instance.snapshot())).collect(),
+		}
+	}
+
+	/// Replaces all the instances with the instances in a snapshot.
+	/// Returns the names of the instances in the order they are drawn.
+	/// The images used by the instances must already be loaded.
+	pub fn restore_from_snapshot(&mut self, script: &Script, snapshot: StageSnapshot) -> Vec<InstanceName> {
+		let Stage(stage) = self;
+		let draw_order = snapshot.instances.iter().map(|(name, _)| name.clone()).collect();
+		*stage = snapshot.instances.into_iter().map(|(name, instance)|
+			(name, Instance::from_snapshot(script, instance))).collect();
+		draw_order
+	}
+
 	/// Runs all the animations that have been applied onto the instances.
 	pub fn update(&mut self, ctx: &mut ggez::Context) {
 		let Stage(stage) = self;
 		stage.values_mut().for_each(|instance| instance.update(ctx))
 	}
 
-	/// Draws all the instances it contains.
-	pub fn draw(&self, ctx: &mut ggez::Context) -> ggez::GameResult {
+	/// Draws all the instances it contains offset by the camera.
+	/// If there is a draw order, the listed instances are drawn first in that order.
+	/// Consecutive instances that share the same image are drawn in a single call
+	/// if batched drawing is enabled, reusing the sprite batches from previous frames.
+	pub fn draw(&self, ctx: &mut ggez::Context, settings: &Settings, camera: (f32, f32),
+	            draw_order: Option<&[InstanceName]>, batches: &mut SpriteBatches) -> ggez::GameResult {
+		let mut instances = self.ordered(draw_order).into_iter()
+			.filter(|instance| instance.visible && !instance.is_transparent());
+		if !settings.batched_drawing {
+			return instances.try_for_each(|instance| instance.draw(ctx, camera));
+		}
+
+		let calls = draw_calls(instances.flat_map(Instance::layers), camera);
+		batches.retain(&calls.iter().map(|(image, _)| *image).collect::<Vec<_>>());
+		calls.into_iter().try_for_each(|(image, params)| batches.draw(ctx, image, params))
+	}
+
+	/// The instances in the order they are drawn.
+	/// Listed instances come first in the order they are listed, skipping any that do not exist,
+	/// followed by the remaining instances sorted by name.
+	pub fn ordered(&self, draw_order: Option<&[InstanceName]>) -> Vec<&Instance> {
+		let Stage(stage) = self;
+		draw_ordered(stage, draw_order).into_iter().map(|(_, instance)| instance).collect()
+	}
+
+	/// Creates a draw order where an instance is drawn after every other instance.
+	pub fn raise(&self, draw_order: Option<&[InstanceName]>, name: &InstanceName) -> Vec<InstanceName> {
 		let Stage(stage) = self;
-		stage.values().filter(|instance| instance.visible)
-			.map(|instance| instance.draw(ctx)).collect()
+		reorder(stage, draw_order, name, true)
+	}
+
+	/// Creates a draw order where an instance is drawn before every other instance.
+	pub fn lower(&self, draw_order: Option<&[InstanceName]>, name: &InstanceName) -> Vec<InstanceName> {
+		let Stage(stage) = self;
+		reorder(stage, draw_order, name, false)
 	}
 
 	/// Spawns a new instance onto the stage.
-	pub fn spawn(&mut self, name: InstanceName, instance: Instance) {
+	/// Returns the instance that was replaced if one already had the same name.
+	pub fn spawn(&mut self, name: InstanceName, instance: Instance) -> Option<Instance> {
 		let Stage(stage) = self;
-		stage.insert(name, instance);
+		stage.insert(name, instance)
 	}
 
 	/// Removes an instance from the stage.
-	pub fn remove(&mut self, name: &InstanceName) {
+	pub fn remove(&mut self, name: &InstanceName) -> Option<Instance> {
+		let Stage(stage) = self;
+		stage.remove(name)
+	}
+
+	/// Copies an instance to a new name at a different position.
+	/// Any instance that already has the new name is replaced.
+	/// Returns false if the instance does not exist.
+	pub fn duplicate(&mut self, name: &InstanceName, new_name: InstanceName, position: (f32, f32)) -> bool {
+		let Stage(stage) = self;
+		let instance = match stage.get(name) {
+			Some(instance) => instance.duplicate(position),
+			None => return false,
+		};
+
+		stage.insert(new_name, instance);
+		true
+	}
+
+	/// Changes the name of an instance.
+	/// Any instance that already has the new name is replaced.
+	/// Returns false if the instance does not exist.
+	pub fn rename(&mut self, name: &InstanceName, new_name: InstanceName) -> bool {
+		let Stage(stage) = self;
+		let instance = match stage.remove(name) {
+			Some(instance) => instance,
+			None => return false,
+		};
+
+		stage.insert(new_name, instance);
+		true
+	}
+
+	/// Gets an instance by its name if it is on the stage.
+	pub fn get(&self, name: &InstanceName) -> Option<&Instance> {
 		let Stage(stage) = self;
-		stage.remove(name);
+		stage.get(name)
+	}
+
+	/// Iterates over the names and instances on the stage.
+	pub fn iter(&self) -> impl Iterator<Item=(&InstanceName, &Instance)> {
+		let Stage(stage) = self;
+		stage.iter()
+	}
+
+	/// Checks whether an instance with the given name is on the stage.
+	/// This should be used before indexing the stage with a name that may not exist.
+	pub fn contains(&self, name: &InstanceName) -> bool {
+		let Stage(stage) = self;
+		stage.contains_key(name)
+	}
+
+	/// The number of instances on the stage.
+	pub fn instance_count(&self) -> usize {
+		let Stage(stage) = self;
+		stage.len()
+	}
+
+	/// The number of instances on the stage that are visible.
+	pub fn visible_count(&self) -> usize {
+		let Stage(stage) = self;
+		stage.values().filter(|instance| instance.visible).count()
+	}
+
+	/// Checks whether no instance has a running animation.
+	pub fn all_animations_finished(&self) -> bool {
+		!self.any_animating()
+	}
+
+	/// Checks whether any instance has a running animation.
+	pub fn any_animating(&self) -> bool {
+		let Stage(stage) = self;
+		stage.values().any(Instance::is_animating)
+	}
+
+	/// The number of instances on the stage that have a running animation.
+	pub fn animating_count(&self) -> usize {
+		let Stage(stage) = self;
+		stage.values().filter(|instance| instance.is_animating()).count()
 	}
 
 	/// Finishes any animations that are currently on the instances.
@@ -202,6 +938,8 @@ impl Stage {
 	}
 }
 
+/// Indexing panics if the instance does not exist.
+/// Use `Stage::contains` to check whether the instance exists first.
 impl Index<&InstanceName> for Stage {
 	type Output = Instance;
 
@@ -212,6 +950,12 @@ impl Index<&InstanceName> for Stage {
 	}
 }
 
+/// Subtracts the camera offset from the destination of the parameters.
+fn offset(param: graphics::DrawParam, (camera_x, camera_y): (f32, f32)) -> graphics::DrawParam {
+	let (destination_x, destination_y) = (param.dest.x, param.dest.y);
+	param.dest([destination_x - camera_x, destination_y - camera_y])
+}
+
 impl IndexMut<&InstanceName> for Stage {
 	fn index_mut(&mut self, index: &InstanceName) -> &mut Self::Output {
 		let Stage(stage) = self;
@@ -221,16 +965,192 @@ impl IndexMut<&InstanceName> for Stage {
 }
 
 /// Holds all the characters and their respective states.
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(transparent)]
 pub struct Characters(pub HashMap<CharacterName, HashMap<StateName, CharacterState>>);
 
+/// Holds the state and position that each character is spawned with
+/// when a spawn command does not give them.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(transparent)]
+pub struct SpawnDefaults(pub HashMap<CharacterName, SpawnDefault>);
+
+/// The default state and position of a character.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SpawnDefault {
+	/// The position the character is spawned at if a position is not given.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub position: Option<(f32, f32)>,
+	/// The state the character is spawned in if a state is not given.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub state: Option<StateName>,
+}
+
+/// A character before its states have been converted.
+#[derive(Deserialize)]
+struct CharacterDefinition<V> {
+	#[serde(default)]
+	default_position: Option<(f32, f32)>,
+	#[serde(default)]
+	default_state: Option<String>,
+	#[serde(flatten)]
+	states: HashMap<String, V>,
+}
+
 impl Characters {
 	/// Adds a character with a map of its states.
 	pub fn insert(&mut self, name: CharacterName, states: HashMap<StateName, CharacterState>) {
 		let Characters(characters) = self;
 		characters.insert(name, states);
 	}
+
+	/// Parses a set of characters in the TOML format.
+	/// Errors include the name of the character and state that caused them.
+	pub fn parse(string: &str) -> Result<Self, String> {
+		Self::parse_with_defaults(string).map(|(characters, _)| characters)
+	}
+
+	/// Parses a set of characters and their spawn defaults in the TOML format.
+	pub fn parse_with_defaults(string: &str) -> Result<(Self, SpawnDefaults), String> {
+		let characters: HashMap<String, CharacterDefinition<toml::Value>> =
+			toml::from_str(string).map_err(|error| error.to_string())?;
+		Self::from_states(characters)
+	}
+
+	/// Parses a set of characters in the JSON format.
+	/// Errors include the name of the character and state that caused them.
+	#[cfg(feature = "json")]
+	pub fn parse_json(string: &str) -> Result<Self, String> {
+		Self::parse_json_with_defaults(string).map(|(characters, _)| characters)
+	}
+
+	/// Parses a set of characters and their spawn defaults in the JSON format.
+	#[cfg(feature = "json")]
+	pub fn parse_json_with_defaults(string: &str) -> Result<(Self, SpawnDefaults), String> {
+		let characters: HashMap<String, CharacterDefinition<serde_json::Value>> =
+			serde_json::from_str(string).map_err(|error| error.to_string())?;
+		Self::from_states(characters)
+	}
+
+	/// Parses a set of characters and their spawn defaults in the RON format.
+	/// State names are bare identifiers and optional fields are written as `Some(...)`.
+	#[cfg(feature = "ron")]
+	pub fn parse_ron_with_defaults(string: &str) -> Result<(Self, SpawnDefaults), String> {
+		let characters: HashMap<String, CharacterDefinition<ron::Value>> =
+			ron::de::from_str(string).map_err(|error| error.to_string())?;
+		Self::from_states(characters)
+	}
+
+	/// Converts and validates each of the states of each character.
+	/// Composite states are resolved using their base states.
+	fn from_states<V: StateValue>(characters: HashMap<String, CharacterDefinition<V>>)
+	                              -> Result<(Self, SpawnDefaults), String> {
+		let (mut converted, mut defaults) = (HashMap::new(), HashMap::new());
+		for (character, definition) in characters {
+			let CharacterDefinition { default_position, default_state, states } = definition;
+			let definitions: HashMap<_, _> = states.into_iter().map(|(state, value)| {
+				let error = |error: String| format!("Character: {}, state: {}, {}", character, state, error);
+				let StateBase { base } = value.clone().convert().map_err(error)?;
+				let definition = match base {
+					Some(_) => StateDefinition::Composite(value.convert().map_err(error)?),
+					None => {
+						let value: CharacterState = value.convert().map_err(error)?;
+						value.validate().map_err(error)?;
+						StateDefinition::State(value)
+					}
+				};
+				Ok((StateName::new(state), definition))
+			}).collect::<Result<_, String>>()?;
+
+			let states: HashMap<_, _> = definitions.keys().map(|state| {
+				let StateName(name) = state;
+				let value = Self::resolve(&definitions, state, 0).map_err(|error|
+					format!("Character: {}, state: {}, {}", character, name, error))?;
+				Ok((state.clone(), value))
+			}).collect::<Result<_, String>>()?;
+
+			let default_state = default_state.map(StateName::new);
+			if let Some(StateName(state)) = default_state.as_ref().filter(|state| !states.contains_key(state)) {
+				return Err(format!("Character: {}, default state: {}, does not exist", character, state));
+			}
+
+			let character = CharacterName::new(character);
+			if default_position.is_some() || default_state.is_some() {
+				defaults.insert(character.clone(), SpawnDefault { position: default_position, state: default_state });
+			}
+			converted.insert(character, states);
+		}
+		Ok((Characters(converted), SpawnDefaults(defaults)))
+	}
+
+	/// Resolves a state by following the chain of base states of composite states.
+	/// The depth is used to detect base states that form a cycle.
+	fn resolve(definitions: &HashMap<StateName, StateDefinition>,
+	           state: &StateName, depth: usize) -> Result<CharacterState, String> {
+		if depth > definitions.len() {
+			return Err("Base states form a cycle".to_owned());
+		}
+
+		match &definitions[state] {
+			StateDefinition::State(value) => Ok(value.clone()),
+			StateDefinition::Composite(composite) => {
+				let CompositeState { base, overlay, overlay_offset, spawn_sound } = composite;
+				if !definitions.contains_key(base) {
+					let StateName(base) = base;
+					return Err(format!("Base state: {}, does not exist", base));
+				}
+
+				let base = Self::resolve(definitions, base, depth + 1)?;
+				Ok(CharacterState {
+					spawn_sound: spawn_sound.clone().or(base.spawn_sound),
+					overlay: Some(overlay.clone()),
+					overlay_offset: *overlay_offset,
+					..base
+				})
+			}
+		}
+	}
+}
+
+impl SpawnDefaults {
+	/// Finds the state a character is spawned in.
+	/// The default state of the character is used if a state is not given.
+	pub fn state<'a>(&'a self, character: &CharacterName, state: Option<&'a StateName>) -> Option<&'a StateName> {
+		let SpawnDefaults(defaults) = self;
+		state.or_else(|| defaults.get(character)?.state.as_ref())
+	}
+
+	/// Finds the position a character is spawned at.
+	/// The default position of the character is used if a position is not given.
+	pub fn position(&self, character: &CharacterName, position: Option<(f32, f32)>) -> Option<(f32, f32)> {
+		let SpawnDefaults(defaults) = self;
+		position.or_else(|| defaults.get(character)?.position)
+	}
+}
+
+/// A value from a characters file that can be converted into part of a character.
+trait StateValue: Clone {
+	fn convert<T: serde::de::DeserializeOwned>(self) -> Result<T, String>;
+}
+
+impl StateValue for toml::Value {
+	fn convert<T: serde::de::DeserializeOwned>(self) -> Result<T, String> {
+		self.try_into().map_err(|error| error.to_string())
+	}
+}
+
+#[cfg(feature = "json")]
+impl StateValue for serde_json::Value {
+	fn convert<T: serde::de::DeserializeOwned>(self) -> Result<T, String> {
+		serde_json::from_value(self).map_err(|error| error.to_string())
+	}
+}
+
+#[cfg(feature = "ron")]
+impl StateValue for ron::Value {
+	fn convert<T: serde::de::DeserializeOwned>(self) -> Result<T, String> {
+		self.into_rust().map_err(|error| error.to_string())
+	}
 }
 
 impl Index<(&CharacterName, &StateName)> for Characters {
@@ -242,3 +1162,209 @@ impl Index<(&CharacterName, &StateName)> for Characters {
 			.get(state).unwrap_or_else(|| panic!("State: {:?}, does not exist for character: {:?}", state, character))
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn characters_spawn_sound() {
+		let characters: Characters = toml::from_str("[Character.Happy]\n\
+			image = \"/character-happy.png\"\nscale = [0.5, 0.5]\nspawn_sound = \"/poof.ogg\"\n\n\
+			[Character.Sad]\nimage = \"/character-sad.png\"\nscale = [0.5, 0.5]").unwrap();
+		let character = CharacterName::from("Character");
+		let happy = &characters[(&character, &StateName::from("Happy"))];
+		let sad = &characters[(&character, &StateName::from("Sad"))];
+		assert_eq!(happy.spawn_sound, Some("/poof.ogg".into()));
+		assert_eq!(sad.spawn_sound, None);
+	}
+
+	#[test]
+	fn characters_rotation() {
+		let characters = Characters::parse("[Character.Happy]\nimage = \"/happy.png\"\n\
+			scale = [0.5, 0.5]\nrotation = 0.05\n\n[Character.Sad]\nimage = \"/sad.png\"\nscale = [0.5, 0.5]").unwrap();
+		let character = CharacterName::from("Character");
+		assert_eq!(characters[(&character, &StateName::from("Happy"))].rotation, 0.05);
+		assert_eq!(characters[(&character, &StateName::from("Sad"))].rotation, 0.0);
+
+		let happy = &characters[(&character, &StateName::from("Happy"))];
+		assert_eq!(happy.transform((200, 100)), ((100.0, 50.0), (0.5, 0.5), 0.05));
+		let sad = &characters[(&character, &StateName::from("Sad"))];
+		assert_eq!(sad.transform((200, 100)).2, 0.0);
+	}
+
+	#[test]
+	fn characters_defaults() {
+		let (characters, defaults) = Characters::parse_with_defaults("[Girl]\ndefault_position = [320, 400.0]\n\
+			default_state = \"Neutral\"\n\n[Girl.Neutral]\nimage = \"/neutral.png\"\nscale = [0.5, 0.5]").unwrap();
+		let character = CharacterName::from("Girl");
+		assert!(characters.0[&character].contains_key(&StateName::from("Neutral")));
+		assert_eq!(defaults.state(&character, None), Some(&StateName::from("Neutral")));
+		assert_eq!(defaults.position(&character, None), Some((320.0, 400.0)));
+		assert_eq!(defaults.position(&character, Some((1.0, 2.0))), Some((1.0, 2.0)));
+
+		let error = Characters::parse("[Girl]\ndefault_state = \"Happy\"\n\n\
+			[Girl.Neutral]\nimage = \"/neutral.png\"\nscale = [0.5, 0.5]").unwrap_err();
+		assert_eq!(error, "Character: Girl, default state: Happy, does not exist");
+	}
+
+	#[test]
+	fn characters_composite() {
+		let characters = Characters::parse("[Girl.Pose]\nimage = \"/pose.png\"\nscale = [0.5, 0.5]\n\
+			rotation = 0.05\n\n[Girl.Smile]\nbase = \"Pose\"\noverlay = \"/smile.png\"\noverlay_offset = [120, 80]\n\n\
+			[Girl.Grin]\nbase = \"Smile\"\noverlay = \"/grin.png\"").unwrap();
+		let character = CharacterName::from("Girl");
+		let pose = CharacterState::new("/pose.png").scale((0.5, 0.5)).rotation(0.05);
+		assert_eq!(characters[(&character, &StateName::from("Smile"))],
+			pose.clone().overlay("/smile.png", (120.0, 80.0)));
+		assert_eq!(characters[(&character, &StateName::from("Grin"))],
+			pose.overlay("/grin.png", (0.0, 0.0)));
+	}
+
+	#[test]
+	fn characters_missing_base() {
+		let error = Characters::parse("[Girl.Smile]\nbase = \"Pose\"\noverlay = \"/smile.png\"").unwrap_err();
+		assert_eq!(error, "Character: Girl, state: Smile, Base state: Pose, does not exist");
+
+		let error = Characters::parse("[Girl.Smile]\nbase = \"Grin\"\noverlay = \"/smile.png\"\n\n\
+			[Girl.Grin]\nbase = \"Smile\"\noverlay = \"/grin.png\"").unwrap_err();
+		assert!(error.ends_with("Base states form a cycle"), "{}", error);
+	}
+
+	#[test]
+	fn characters_minimal_state() {
+		let characters = Characters::parse("[Girl.Neutral]\nimage = \"/neutral.png\"").unwrap();
+		let state = &characters[(&CharacterName::from("Girl"), &StateName::from("Neutral"))];
+		assert_eq!(state, &CharacterState::new("/neutral.png"));
+
+		let characters = Characters::parse("[Girl.Neutral]\nimage = \"/neutral.png\"\n\
+			scale = [2, 2]\ncentre_position = [10, 20]\nanchor = [5, 6]").unwrap();
+		let state = &characters[(&CharacterName::from("Girl"), &StateName::from("Neutral"))];
+		assert_eq!(state, &CharacterState::new("/neutral.png").scale((2.0, 2.0))
+			.centre_position((10, 20)).anchor(AnchorPoint::Custom(5, 6)));
+	}
+
+	#[test]
+	fn characters_missing_field() {
+		let error = Characters::parse("[Character.Happy]\nscale = [0.5, 0.5]").unwrap_err();
+		assert!(error.starts_with("Character: Character, state: Happy, missing field `image`"), "{}", error);
+	}
+
+	#[test]
+	fn characters_invalid_scale() {
+		let error = Characters::parse("[Character.Sad]\nimage = \"/sad.png\"\nscale = [0.5, -1.0]").unwrap_err();
+		assert!(error.starts_with("Character: Character, state: Sad, Scale"), "{}", error);
+	}
+
+	#[test]
+	#[cfg(feature = "json")]
+	fn characters_json() {
+		let toml = Characters::parse(include_str!("fixtures/characters.toml")).unwrap();
+		let json = Characters::parse_json(include_str!("fixtures/characters.json")).unwrap();
+		assert_eq!(toml, json);
+	}
+
+	#[test]
+	#[cfg(feature = "ron")]
+	fn characters_ron() {
+		let toml = Characters::parse_with_defaults(include_str!("fixtures/characters.toml")).unwrap();
+		let ron = Characters::parse_ron_with_defaults(include_str!("fixtures/characters.ron")).unwrap();
+		assert_eq!(toml, ron);
+	}
+
+	#[test]
+	fn raise_and_lower() {
+		let names = |names: &[&str]| names.iter().map(|name| InstanceName::from(*name)).collect::<Vec<_>>();
+		let stage: HashMap<_, _> = names(&["alice", "bob", "carol"]).into_iter().map(|name| (name, ())).collect();
+		let order = names(&["alice", "bob", "carol"]);
+		let ordered = |order: &[InstanceName]| draw_ordered(&stage, Some(order)).into_iter()
+			.map(|(name, _)| name.clone()).collect::<Vec<_>>();
+
+		let raised = reorder(&stage, Some(&order), &InstanceName::from("alice"), true);
+		assert_eq!(ordered(&raised), names(&["bob", "carol", "alice"]));
+		let lowered = reorder(&stage, Some(&raised), &InstanceName::from("carol"), false);
+		assert_eq!(ordered(&lowered), names(&["carol", "bob", "alice"]));
+		assert_eq!(reorder(&stage, None, &InstanceName::from("bob"), true).last(), Some(&InstanceName::from("bob")));
+	}
+
+	#[test]
+	fn batched_draw_order() {
+		let stage: HashMap<_, _> = [("dave", "b"), ("alice", "a"), ("carol", "a"), ("bob", "b"), ("erin", "a")]
+			.iter().map(|(name, image)| (InstanceName::from(*name), *image)).collect();
+		let order = [InstanceName::from("bob"), InstanceName::from("dave")];
+		let layers: Vec<_> = draw_ordered(&stage, Some(&order)).into_iter()
+			.map(|(InstanceName(name), image)| (*image, &**name)).collect();
+		assert_eq!(layers, vec![("b", "bob"), ("b", "dave"), ("a", "alice"), ("a", "carol"), ("a", "erin")]);
+
+		let batches = batched(layers.clone());
+		assert_eq!(batches, vec![("b", vec!["bob", "dave"]), ("a", vec!["alice", "carol", "erin"])]);
+		let unbatched: Vec<_> = batches.into_iter()
+			.flat_map(|(image, names)| names.into_iter().map(move |name| (image, name))).collect();
+		assert_eq!(unbatched, layers);
+	}
+
+	#[test]
+	fn batched_draw_params() {
+		let param = |x: f32| graphics::DrawParam::new().dest([x, 2.0 * x]).scale([0.5, 0.5]);
+		let layers = vec![("a", param(10.0)), ("a", param(20.0)), ("b", param(30.0)), ("a", param(40.0))];
+		let camera = (5.0, 15.0);
+
+		let calls = draw_calls(layers.clone(), camera);
+		assert_eq!(calls.iter().map(|(image, params)| (*image, params.len())).collect::<Vec<_>>(),
+			vec![("a", 2), ("b", 1), ("a", 1)]);
+		let batched: Vec<_> = calls.into_iter()
+			.flat_map(|(image, params)| params.into_iter().map(move |param| (image, param))).collect();
+		let unbatched: Vec<_> = layers.into_iter().map(|(image, param)| (image, offset(param, camera))).collect();
+		assert_eq!(batched, unbatched);
+		assert_eq!(batched[0].1.dest, [5.0, 5.0].into());
+	}
+
+	#[test]
+	fn rename_and_copy_order() {
+		let names = |names: &[&str]| names.iter().map(|name| InstanceName::from(*name)).collect::<Vec<_>>();
+		let mut order = names(&["alice", "bob", "carol"]);
+		rename_in_order(&mut order, &InstanceName::from("alice"), &InstanceName::from("dave"));
+		assert_eq!(order, names(&["dave", "bob", "carol"]));
+		rename_in_order(&mut order, &InstanceName::from("bob"), &InstanceName::from("carol"));
+		assert_eq!(order, names(&["dave", "carol"]));
+		copy_in_order(&mut order, &InstanceName::from("dave"), &InstanceName::from("carol"));
+		assert_eq!(order, names(&["dave", "carol"]));
+		copy_in_order(&mut order, &InstanceName::from("dave"), &InstanceName::from("erin"));
+		assert_eq!(order, names(&["dave", "erin", "carol"]));
+	}
+
+	#[test]
+	fn highlight_step() {
+		assert_eq!(step_tint([1.0; 3], [0.6, 0.6, 1.0], 0.25), [0.75, 0.75, 1.0]);
+		assert_eq!(step_tint([0.75; 3], [0.6; 3], 0.25), [0.6; 3]);
+		assert_eq!(step_tint([0.6; 3], [1.0; 3], 0.0), [0.6; 3]);
+	}
+
+	#[test]
+	fn anchor_centre() {
+		let state = CharacterState::new("/happy.png");
+		assert_eq!(state.centre((100, 200)), (50.0, 100.0));
+		let state = state.anchor(AnchorPoint::BottomCentre);
+		assert_eq!(state.centre((100, 200)), (50.0, 200.0));
+		assert_eq!(state.clone().centre_position((10, 20)).centre((100, 200)), (10.0, 20.0));
+		assert!(state.anchor(AnchorPoint::Custom(10, 250)).validate_centre((100, 200)).is_err());
+
+		let characters = Characters::parse("[Girl.Happy]\nimage = \"/happy.png\"\nscale = [1.0, 1.0]\n\
+			anchor = \"BottomCentre\"\n[Girl.Sad]\nimage = \"/sad.png\"\nscale = [1.0, 1.0]\n\
+			anchor = [10, 20]\n").unwrap();
+		let state = |name| characters[(&CharacterName::from("Girl"), &StateName::from(name))].anchor;
+		assert_eq!(state("Happy"), AnchorPoint::BottomCentre);
+		assert_eq!(state("Sad"), AnchorPoint::Custom(10, 20));
+		#[cfg(feature = "json")]
+		assert_eq!(serde_json::to_string(&AnchorPoint::TopRight).unwrap(), "\"TopRight\"");
+		assert!(Characters::parse("[Girl.Happy]\nimage = \"/happy.png\"\nscale = [1.0, 1.0]\n\
+			anchor = \"Feet\"\n").is_err());
+	}
+
+	#[test]
+	fn centre_position_outside_image() {
+		let state = CharacterState::new("/happy.png").centre_position((50, 120));
+		assert!(state.validate_centre((100, 200)).is_ok());
+		assert!(state.validate_centre((100, 100)).is_err());
+	}
+}
@@ -1,11 +1,47 @@
-use std::io::{Read, Write};
-use std::path::Path;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 
 use ggez::{self, Context, event, graphics, input};
+use ggez::audio::SoundData;
 
-use crate::{Characters, Command, History, Label, Render, Script, ScriptState, Settings, Target};
+use crate::{Characters, ChoiceStats, ClickPolicy, Command, FlagName, History, Label, MusicState, Persistent,
+	Render, ResourceKind, ResourceSource, Script, ScriptState, Settings, Snapshot, Target, random_label};
+use crate::audio::Music;
+use crate::character::{CharacterName, CharacterState, SpawnDefaults, Stage, StateName};
+use crate::interface::{self, Button, Click, QuickAction, RenderText, TextBox, TransitionState};
+use crate::script_types::Placement;
 
+/// Reasons that the saved history could not be loaded.
+#[derive(Debug)]
+pub enum SaveError {
+	/// There is no save file.
+	Missing,
+	/// The save file could not be read or parsed.
+	/// A copy of the file is kept with a `.bak` extension.
+	Corrupt(String),
+	/// The save file could not be parsed and the copy with a `.bak` extension could not be written.
+	Backup(String),
+}
+
+impl std::fmt::Display for SaveError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			SaveError::Missing => write!(f, "There is no saved history"),
+			SaveError::Corrupt(error) | SaveError::Backup(error) => write!(f, "{}", error),
+		}
+	}
+}
+
+impl std::error::Error for SaveError {}
+
+/// The running game.
+///
+/// Embedders can inspect the game without modifying it through
+/// `current_target`, `current_command`, `last_label`, `is_awaiting_input`, `stage` and `flags`.
+/// None of these panic.
 #[derive(Debug)]
 pub struct GameState {
 	script: Script,
@@ -14,6 +50,40 @@ pub struct GameState {
 	state: ScriptState,
 	render: Render,
 	reload: bool,
+	/// When the characters file was last checked for changes and its modification time.
+	characters_modified: (Option<Instant>, Option<SystemTime>),
+	/// Notice shown because the characters could not be reloaded.
+	/// It is removed once the characters are reloaded if it is still shown.
+	characters_notice: Option<String>,
+	/// Time in milliseconds that finished dialogue has been displayed for in auto mode.
+	auto_elapsed: f32,
+	/// Sounds played when hovering over a button and when clicking to advance.
+	ui_sounds: (Option<SoundData>, Option<SoundData>),
+	/// Image drawn in place of the mouse cursor.
+	cursor: Option<graphics::Image>,
+	/// Called every frame while the script is stopped by a yield.
+	yield_callback: Option<YieldCallback>,
+	/// Divergences of a loaded save that have not been made again yet, the next is last.
+	replay: Vec<Label>,
+	/// Keyboard index of the branch that is selected with the keyboard.
+	selected_branch: Option<usize>,
+	/// Number of times each branch has been chosen, which is only recorded if statistics are collected.
+	choice_stats: ChoiceStats,
+	/// Number of choices that have been recorded since the statistics were last written.
+	unsaved_choices: u32,
+	/// Whether quitting has failed to save, so that quitting again closes the game without saving.
+	quit_requested: bool,
+}
+
+/// Function that runs code outside of the script while it is stopped by a yield.
+pub type YieldFn = dyn FnMut(&mut GameState, &mut ggez::Context);
+
+struct YieldCallback(Box<YieldFn>);
+
+impl std::fmt::Debug for YieldCallback {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		f.write_str("YieldCallback")
+	}
 }
 
 impl GameState {
@@ -21,153 +91,977 @@ impl GameState {
 	            settings: Settings, mut load_history: History) -> Self {
 		let history = History::default();
 		let (state, render) = (ScriptState::default(), Render::default());
-		let mut state = GameState { script, settings, history, state, render, reload: false };
+		let characters_modified = (None, None);
+		let ui_sound = |ctx: &mut ggez::Context, path: &Option<String>| path.as_ref()
+			.and_then(|path| SoundData::new(ctx, path).ok());
+		let ui_sounds = (ui_sound(ctx, &settings.ui_hover_sound), ui_sound(ctx, &settings.ui_click_sound));
+		let cursor = settings.cursor_image.as_ref().and_then(|path| graphics::Image::new(ctx, path)
+			.map_err(|error| eprintln!("Warning: Failed to load cursor image at: {}, because: {}", path.display(), error)).ok());
+		input::mouse::set_cursor_hidden(ctx, cursor.is_some());
+		let mut state = GameState { script, settings, history, state, render, reload: false,
+			characters_modified, characters_notice: None, auto_elapsed: 0.0, ui_sounds, cursor, yield_callback: None, replay: Vec::new(),
+			selected_branch: None, choice_stats: ChoiceStats::default(), unsaved_choices: 0,
+			quit_requested: false };
+		state.state.persistent = load_persistent(ctx, &state.settings)
+			.unwrap_or_else(|_| Persistent::default());
+		if state.settings.collect_stats {
+			state.choice_stats = load_choice_stats(ctx, &state.settings).unwrap_or_default();
+		}
+		if let Some(notice) = load_history.notice.take() {
+			state.render.notify(notice, &state.settings);
+		}
+
+		let warnings = state.script.lint();
+		if state.settings.developer && !warnings.is_empty() {
+			state.render.notify(warnings.join("\n"), &state.settings);
+		}
+
+		state.resume(ctx, load_history);
+		state
+	}
+
+	/// Brings the game to the point that a history was saved at.
+	/// The saved state is restored if there is one and otherwise the script is replayed.
+	fn resume(&mut self, ctx: &mut ggez::Context, mut load_history: History) {
+		let music_position = load_history.music_position.take();
+		if let Some(snapshot) = load_history.snapshot.take() {
+			self.restore(ctx, snapshot);
+			self.history = load_history;
+			if let Some(position) = music_position {
+				self.seek_music(ctx, position);
+			}
+			return;
+		}
 
 		load_history.divergences.reverse();
-		state.state.next_target = Some(Target::default());
-		while state.history.execution_count < load_history.execution_count {
-			match state.script[&state.state.target] {
-				Command::Diverge(_) => state.diverge(ctx,
-					&load_history.divergences.pop().unwrap()),
-				_ => state.advance(ctx),
+		self.replay = load_history.divergences;
+		self.history.start_target = load_history.start_target.clone();
+		self.state.next_target = Some(load_history.start_target.unwrap_or_default());
+		self.state.replaying = true;
+		while self.history.execution_count < load_history.execution_count {
+			match self.script[&self.state.target] {
+				Command::Diverge(_) => {
+					let label = self.replay.pop().unwrap();
+					self.follow_branch(ctx, &label);
+				}
+				_ => self.advance(ctx),
 			}
 		}
 
-		assert!(load_history.divergences.is_empty());
-		state
+		assert!(self.replay.is_empty());
+		self.state.replaying = false;
+		if let Some(position) = music_position {
+			self.seek_music(ctx, position);
+		}
+		if !matches!(self.script[&self.state.target], Command::SceneTransition(_, _) | Command::Jump(_, Some(_))) {
+			self.render.transition = None;
+		}
+	}
+
+	/// Rolls the game back to an earlier execution count by replaying the script
+	/// with only the divergences that were made before it.
+	/// Data that persists across playthroughs is kept.
+	/// Returns false and does nothing if the execution count is not earlier than the current one.
+	pub fn rollback(&mut self, ctx: &mut ggez::Context, execution_count: usize) -> bool {
+		if execution_count >= self.history.execution_count {
+			return false;
+		}
+
+		let mut history = std::mem::take(&mut self.history);
+		history.truncate_to(execution_count);
+		history.music_position = None;
+		let persistent = std::mem::take(&mut self.state.persistent);
+		self.state = ScriptState { persistent, ..ScriptState::default() };
+		self.render = Render {
+			quick_menu: std::mem::take(&mut self.render.quick_menu),
+			performance: std::mem::take(&mut self.render.performance),
+			..Render::default()
+		};
+		self.selected_branch = None;
+		self.resume(ctx, history);
+		true
 	}
 
 	pub fn advance(&mut self, ctx: &mut ggez::Context) {
-		self.render.stage.finish_animation();
+		self.render.finish_animations();
 		match &mut self.render.text {
 			Some(text) if !text.is_finished() => text.finish(),
-			_ => loop {
-				self.history.execution_count += 1;
-				self.state.target = self.state.next_target.take()
-					.unwrap_or(self.state.target.next());
-
-				let command = &self.script[&self.state.target];
-				command.execute(ctx, &mut self.state,
-					&mut self.render, &self.script, &self.settings);
-
-				match command {
-					Command::Pause => break,
-					Command::Diverge(_) => break,
-					Command::Dialogue(_, _) => break,
-					_ => (),
+			_ => self.run_commands(ctx),
+		}
+	}
+
+	/// Executes commands until one of them waits for the player, animations, or time to pass.
+	/// Unlike advancing, running animations and text are not finished first.
+	fn run_commands(&mut self, ctx: &mut ggez::Context) {
+		if let Some(command) = self.state.pending_dialogue.pop_front() {
+			return command.execute(ctx, &mut self.state, &mut self.render, &self.script, &self.settings);
+		}
+
+		loop {
+			self.state.delay_until = None;
+			self.state.yielded = false;
+			self.history.execution_count += 1;
+			self.state.target = self.state.next_target.take()
+				.unwrap_or(self.state.target.next());
+
+			if let Command::Together(_) = &self.script[&self.state.target] {
+				let command = self.script[&self.state.target].clone();
+				self.execute(ctx, &command);
+				continue;
+			}
+
+			let resources = self.command_resources(&self.script[&self.state.target]);
+			let spawn_sounds = self.spawn_sounds(&self.script[&self.state.target]);
+			if !self.prepare_resources(ctx, resources, spawn_sounds) { continue; }
+			let command = &self.script[&self.state.target];
+			command.execute(ctx, &mut self.state,
+				&mut self.render, &self.script, &self.settings);
+
+			match command {
+				Command::Unload(path) => {
+					let path = path.clone();
+					self.script.unload(path.as_deref(), &self.render,
+						self.state.music_path.as_deref());
+				}
+				Command::Pause(_, _) => break,
+				Command::Sync => break,
+				Command::Delay(_) => break,
+				Command::Yield => break,
+				Command::Random(branches) => {
+					let label = match self.replay.pop() {
+						Some(label) if branches.iter().any(|(_, branch)| branch == &label) => label,
+						Some(Label(name)) => {
+							eprintln!("Warning: Replayed label: {}, is not a branch of the random choice at: {}",
+								name, self.state.target.0);
+							random_label(branches).clone()
+						}
+						None => random_label(branches).clone(),
+					};
+					self.state.next_target = Some(self.script.labels[&label].clone());
+					self.history.diverge(label);
+				}
+				Command::Diverge(_) => break,
+				Command::Dialogue(_, _, _) => break,
+				Command::SceneTransition(_, _) => break,
+				Command::Jump(_, Some(_)) => break,
+				_ => (),
+			}
+		}
+	}
+
+	/// Continues the script once all animations on the stage, the camera and transitions have finished.
+	/// Does nothing if any animation is still running.
+	pub fn wait_for_animations(&mut self, ctx: &mut ggez::Context) {
+		if self.render.animations_finished() {
+			self.advance(ctx);
+		}
+	}
+
+	/// Finds the state and position of a spawn command.
+	/// Scripts are validated when loaded so a spawn command always has a state and position.
+	fn spawn_parameters<'a>(&'a self, character: &CharacterName, state: &'a Option<StateName>,
+	                        position: &Option<Placement>) -> (&'a StateName, (f32, f32)) {
+		position.as_ref().map(|position| position.resolve(&self.settings)).transpose()
+			.and_then(|position| self.script.spawn_parameters(character, state.as_ref(), position))
+			.unwrap_or_else(|error| panic!("{}", error))
+	}
+
+	/// Finds the paths of the images and audio that a command uses.
+	fn command_resources(&self, command: &Command) -> (Vec<PathBuf>, Option<PathBuf>) {
+		let characters = &self.script.characters;
+		match command {
+			Command::Stage(path) | Command::SceneTransition(path, _) => (vec![normalise_path(path)], None),
+			Command::Spawn(character, state, position, _, _) => {
+				let (state, _) = self.spawn_parameters(character, state, position);
+				(state_images(&characters[(character, state)]), None)
+			}
+			Command::Change(instance, state, _) => (self.state.expand(instance).iter()
+				.filter_map(|instance| self.render.stage.get(self.state.alias(instance)))
+				.flat_map(|instance| state_images(&characters[(&instance.character, state)])).collect(), None),
+			Command::Sprite(_, path, _) => (vec![normalise_path(path)], None),
+			Command::Music(path) | Command::MusicLoop(path, _, _) |
+			Command::Sound(path) => (Vec::new(), Some(normalise_path(path))),
+			_ => (Vec::new(), None),
+		}
+	}
+
+	/// Finds the paths of the sounds played by the states that a command enters.
+	fn spawn_sounds(&self, command: &Command) -> Vec<PathBuf> {
+		let characters = &self.script.characters;
+		let states: Vec<_> = match command {
+			Command::Spawn(character, state, position, _, _) =>
+				vec![&characters[(character, self.spawn_parameters(character, state, position).0)]],
+			Command::Change(instance, state, _) => self.state.expand(instance).iter()
+				.filter_map(|instance| self.render.stage.get(self.state.alias(instance)))
+				.map(|instance| &characters[(&instance.character, state)]).collect(),
+			_ => Vec::new(),
+		};
+		states.into_iter().filter_map(|state| state.spawn_sound.as_ref()).map(normalise_path).collect()
+	}
+
+	/// Loads any of the spawn sounds that are not already loaded.
+	/// Sounds that fail to load print a warning instead of stopping the game.
+	fn load_spawn_sounds(&mut self, ctx: &mut ggez::Context, paths: Vec<PathBuf>) {
+		for path in paths {
+			if let Entry::Vacant(entry) = self.script.audio.entry(path) {
+				match SoundData::new(ctx, entry.key()) {
+					Ok(audio) => { entry.insert(audio); }
+					Err(error) => eprintln!("Warning: Failed to load spawn sound at path: {}, \
+						because: {}", entry.key().display(), error),
 				}
+			}
+		}
+	}
+
+	/// Loads any of the given resources that are not already loaded.
+	fn load_command_resources(&mut self, ctx: &mut ggez::Context,
+	                          (images, audio): (Vec<PathBuf>, Option<PathBuf>)) -> ggez::GameResult {
+		for path in images {
+			if let Entry::Vacant(entry) = self.script.images.entry(path) {
+				let image = graphics::Image::new(ctx, entry.key())?;
+				entry.insert(image);
+			}
+		}
+
+		if let Some(Entry::Vacant(entry)) = audio.map(|path| self.script.audio.entry(path)) {
+			let audio = SoundData::new(ctx, entry.key())?;
+			entry.insert(audio);
+		}
+		Ok(())
+	}
+
+	/// Loads the resources and spawn sounds of a command that are not already loaded.
+	/// If a resource cannot be loaded the player is notified and false is returned,
+	/// so the command is skipped and whatever was displayed or playing before is kept.
+	fn prepare_resources(&mut self, ctx: &mut ggez::Context,
+	                     resources: (Vec<PathBuf>, Option<PathBuf>), spawn_sounds: Vec<PathBuf>) -> bool {
+		if let Err(error) = self.load_command_resources(ctx, resources) {
+			let message = format!("Failed to load resources for command because: {}", error);
+			self.render.notify(message, &self.settings);
+			return false;
+		}
+
+		self.load_spawn_sounds(ctx, spawn_sounds);
+		true
+	}
+
+	/// Executes a command that is not in the script or is inside a `together` block.
+	/// Any resources the command uses are loaded beforehand.
+	fn execute(&mut self, ctx: &mut ggez::Context, command: &Command) {
+		if let Command::Together(commands) = command {
+			return commands.iter().for_each(|command| self.execute(ctx, command));
+		}
+
+		let (resources, spawn_sounds) = (self.command_resources(command), self.spawn_sounds(command));
+		if !self.prepare_resources(ctx, resources, spawn_sounds) { return; }
+		command.execute(ctx, &mut self.state, &mut self.render, &self.script, &self.settings);
+		if let Command::Unload(path) = command {
+			self.script.unload(path.as_deref(), &self.render, self.state.music_path.as_deref());
+		}
+	}
+
+	/// Sets the function that is called every frame while the script is stopped by a yield.
+	pub fn set_yield_callback(&mut self, callback: Box<YieldFn>) {
+		self.yield_callback = Some(YieldCallback(callback));
+	}
+
+	/// Ends a frame that the script is stopped by a yield for, calling a function that may keep it stopped.
+	/// Returns true if the script was stopped and should now continue.
+	fn yield_frame(&mut self, callback: impl FnOnce(&mut GameState)) -> bool {
+		if !std::mem::replace(&mut self.state.yielded, false) { return false; }
+		callback(self);
+		!self.state.yielded
+	}
+
+	/// Keeps the script stopped by a yield for another frame.
+	/// This is called from the yield callback, otherwise the script continues after the frame.
+	pub fn keep_yielding(&mut self) {
+		self.state.yielded = true;
+	}
+
+	/// Immediately executes a command that is not part of the script.
+	/// Injected commands are not recorded in the history and so are not saved.
+	pub fn inject_command(&mut self, ctx: &mut ggez::Context, command: Command) {
+		self.execute(ctx, &command);
+	}
+
+	/// Immediately executes each of the commands in order.
+	/// Injected commands are not recorded in the history and so are not saved.
+	pub fn inject_commands(&mut self, ctx: &mut ggez::Context, commands: Vec<Command>) {
+		commands.iter().for_each(|command| self.execute(ctx, command));
+	}
+
+	/// The target of the command that was most recently executed.
+	pub fn current_target(&self) -> &Target {
+		&self.state.target
+	}
+
+	/// The command that was most recently executed.
+	/// There is no command once the end of the script has been reached.
+	pub fn current_command(&self) -> Option<&Command> {
+		let Target(target) = self.state.target;
+		self.script.commands.get(target)
+	}
+
+	/// The label that was most recently passed.
+	pub fn last_label(&self) -> Option<&Label> {
+		self.script.nearest_preceding_label(&self.state.target)
+	}
+
+	/// Whether the game is waiting for the player to click or choose a branch before continuing.
+	pub fn is_awaiting_input(&self) -> bool {
+		let covered = self.render.transition.as_ref().map(TransitionState::is_covering).unwrap_or(false);
+		!covered && matches!(self.current_command(),
+			Some(Command::Pause(_, _)) | Some(Command::Dialogue(_, _, _)) | Some(Command::Diverge(_)))
+	}
+
+	/// The instances that are on the stage.
+	pub fn stage(&self) -> &Stage {
+		&self.render.stage
+	}
+
+	/// The flags that are set, including persistent flags.
+	pub fn flags(&self) -> impl Iterator<Item=&FlagName> {
+		self.state.flags.union(&self.state.persistent.flags)
+	}
+
+	/// Draws the cursor image at the position of the mouse.
+	/// The cursor is drawn in window coordinates so that it is not scaled with the view
+	/// and is placed exactly under the pointer.
+	fn draw_cursor(&self, ctx: &mut ggez::Context) -> ggez::GameResult {
+		let cursor = match &self.cursor {
+			Some(cursor) => cursor,
+			None => return Ok(()),
+		};
+
+		let view = graphics::screen_coordinates(ctx);
+		let (width, height) = graphics::drawable_size(ctx);
+		let scale_factor = self.settings.scale_factor;
+		let window = graphics::Rect::new(0.0, 0.0, width / scale_factor, height / scale_factor);
+		graphics::set_screen_coordinates(ctx, window)?;
+
+		let position = input::mouse::position(ctx);
+		let (hotspot_x, hotspot_y) = self.settings.cursor_hotspot;
+		let destination = [position.x - hotspot_x, position.y - hotspot_y];
+		graphics::draw(ctx, cursor, graphics::DrawParam::new().dest(destination))?;
+		graphics::set_screen_coordinates(ctx, view)
+	}
+
+	/// Performs the action of a button in the quick menu.
+	/// Loading restarts the game from the saved history.
+	fn quick_action(&mut self, ctx: &mut ggez::Context, action: QuickAction) {
+		match action {
+			QuickAction::Auto | QuickAction::Skip => {
+				self.render.quick_menu.toggle(action, &self.settings);
+				self.auto_elapsed = 0.0;
+			}
+			QuickAction::Save => if self.save(ctx) {
+				self.render.notify("The game has been saved.".to_owned(), &self.settings);
 			},
+			QuickAction::Load => {
+				self.flush_choice_stats(ctx);
+				self.reload = true;
+				event::quit(ctx);
+			}
+		}
+	}
+
+	/// Advances the script while skip or auto mode is active.
+	/// Skipping advances every frame and auto mode advances once dialogue
+	/// has been finished and displayed for the auto delay.
+	/// Neither mode advances past branches or notices.
+	fn update_quick_modes(&mut self, ctx: &mut ggez::Context, delta: f32) {
+		let waiting = self.render.notice.is_none() && self.is_awaiting_input()
+			&& matches!(self.current_command(), Some(Command::Pause(_, _)) | Some(Command::Dialogue(_, _, _)));
+		let quick_menu = &self.render.quick_menu;
+		if !waiting || !(quick_menu.skip || quick_menu.auto) {
+			self.auto_elapsed = 0.0;
+			return;
+		}
+
+		if quick_menu.skip {
+			return self.advance(ctx);
+		}
+
+		if !self.render.is_busy() {
+			self.auto_elapsed += delta;
+			if self.auto_elapsed >= self.settings.auto_delay {
+				self.auto_elapsed = 0.0;
+				self.advance(ctx);
+			}
 		}
 	}
 
-	/// Jumps to a selected label in a divergence.
+	/// Reloads the characters if the characters file has been modified.
+	/// The file is checked at most once every second.
+	fn poll_characters(&mut self, ctx: &mut ggez::Context) {
+		const POLL_PERIOD: Duration = Duration::from_secs(1);
+		let path = match &self.settings.characters_path {
+			Some(path) if self.settings.developer => path.clone(),
+			_ => return,
+		};
+
+		let (last_poll, last_modified) = self.characters_modified;
+		if last_poll.map(|poll| poll.elapsed() < POLL_PERIOD).unwrap_or(false) { return; }
+		let modified = resource_file(&self.settings, &path).and_then(|path|
+			std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok());
+		self.characters_modified = (Some(Instant::now()), modified);
+		if last_poll.is_none() || modified == last_modified { return; }
+
+		match self.reload_characters(ctx, &path) {
+			Ok(()) => if let Some(message) = self.characters_notice.take() {
+				self.render.clear_notice(&message);
+			},
+			Err(error) => {
+				let message = format!("Failed to reload characters because: {}", error);
+				self.render.notify(message.clone(), &self.settings);
+				self.characters_notice = Some(message);
+			}
+		}
+	}
+
+	/// Loads the characters and their images again and updates the instances on the stage.
+	/// The previous characters are kept if the new characters cannot be loaded.
+	fn reload_characters(&mut self, ctx: &mut ggez::Context, path: &str) -> ggez::GameResult {
+		let (characters, spawn_defaults) = load_character_set(ctx, path)?;
+		let Stage(stage) = &self.render.stage;
+		let Characters(definitions) = &characters;
+		if let Some(instance) = stage.values().find(|instance| definitions.get(&instance.character)
+			.map(|states| !states.contains_key(&instance.state)).unwrap_or(true)) {
+			return Err(ggez::GameError::ResourceLoadError(format!("Character: {:?}, state: {:?}, \
+				is in use but no longer exists", instance.character, instance.state)));
+		}
+
+		let previous = (std::mem::replace(&mut self.script.characters, characters),
+			std::mem::replace(&mut self.script.spawn_defaults, spawn_defaults));
+		let validation = self.script.validate(&self.settings).map_err(ggez::GameError::ResourceLoadError);
+		if let Err(error) = validation.and_then(|_| load_images(ctx, &mut self.script, &self.settings)) {
+			let (characters, spawn_defaults) = previous;
+			self.script.characters = characters;
+			self.script.spawn_defaults = spawn_defaults;
+			return Err(error);
+		}
+
+		self.render.stage.refresh(&self.script);
+		Ok(())
+	}
+
+	/// Saves the history and persistent data, retrying once if saving fails.
+	/// The player is notified and `false` is returned if the game could not be saved.
+	pub fn save(&mut self, ctx: &mut ggez::Context) -> bool {
+		match self.write_save(ctx) {
+			Ok(()) => true,
+			Err(error) => {
+				let message = format!("Failed to save the game because: {}", error);
+				self.render.notify(message, &self.settings);
+				false
+			}
+		}
+	}
+
+	/// Saves the history and persistent data, retrying once if saving fails.
+	fn write_save(&mut self, ctx: &mut ggez::Context) -> ggez::GameResult {
+		self.history.snapshot = Some(self.snapshot());
+		self.history.thumbnail = take_screenshot(ctx)
+			.and_then(|screenshot| encode_thumbnail(&screenshot, &self.settings))
+			.map_err(|error| eprintln!("Warning: Failed to capture a thumbnail for the save, because: {}", error)).ok();
+		self.history.music_position = self.state.music.as_ref().map(Music::position);
+		self.flush_choice_stats(ctx);
+		let save = |ctx: &mut ggez::Context| {
+			save_history(ctx, &self.settings, &self.history)?;
+			save_persistent(ctx, &self.settings, &self.state.persistent)
+		};
+		save(ctx).or_else(|_| save(ctx))
+	}
+
+	/// Creates a snapshot of the current state of the game.
+	pub fn snapshot(&self) -> Snapshot {
+		let Target(target) = self.state.target;
+		Snapshot {
+			target,
+			flags: self.state.flags.clone(),
+			music: match (&self.state.music_path, self.state.music_paused) {
+				(Some(path), false) => MusicState::Playing { path: path.clone() },
+				(Some(path), true) => MusicState::Paused { path: path.clone(),
+					position: self.state.music.as_ref().map(Music::position).unwrap_or(0.0) },
+				(None, _) => MusicState::Stopped,
+			},
+			music_loop: self.state.music_loop,
+			background: self.state.background.clone(),
+			camera_offset: self.render.camera.as_ref()
+				.map(|camera| camera.destination).unwrap_or(self.render.camera_offset),
+			draw_order: self.render.draw_order.clone(),
+			aliases: self.state.aliases.clone(),
+			groups: self.state.groups.clone(),
+			loop_counters: self.state.loop_counters.clone(),
+			counters: self.state.counters.clone(),
+			name_font: self.state.name_font.clone(),
+			dialogue_font: self.state.dialogue_font.clone(),
+			instances: self.render.stage.snapshot(self.render.draw_order.as_deref()),
+		}
+	}
+
+	/// Restores the state of the game from a snapshot.
+	/// Any dialogue or branches of the command at the target of the snapshot are displayed
+	/// again without executing the command, and a command that waits resumes waiting.
+	pub fn restore(&mut self, ctx: &mut ggez::Context, snapshot: Snapshot) {
+		self.state.target = Target(snapshot.target);
+		self.state.next_target = None;
+		self.state.pending_dialogue.clear();
+		self.state.flags = snapshot.flags;
+		self.state.aliases = snapshot.aliases;
+		self.state.groups = snapshot.groups;
+		self.state.loop_counters = snapshot.loop_counters;
+		self.state.counters = snapshot.counters;
+		self.state.name_font = snapshot.name_font;
+		self.state.dialogue_font = snapshot.dialogue_font;
+		self.render.camera = None;
+		self.render.camera_offset = snapshot.camera_offset;
+
+		if let Some(path) = snapshot.background {
+			self.execute(ctx, &Command::Stage(path));
+		}
+
+		let (music, paused) = match snapshot.music {
+			MusicState::Playing { path } => (Some(path), false),
+			MusicState::Paused { path, .. } => (Some(path), true),
+			MusicState::Stopped => (None, false),
+		};
+
+		if let Some(path) = music {
+			match snapshot.music_loop {
+				Some((start, end)) => self.execute(ctx, &Command::MusicLoop(path, start, end)),
+				None => self.execute(ctx, &Command::Music(path)),
+			}
+		}
+
+		if paused {
+			self.execute(ctx, &Command::PauseMusic);
+		}
+
+		let characters = &self.script.characters;
+		let instance_images: Vec<_> = snapshot.instances.instances.iter().map(|(name, instance)| {
+			let state = &characters[(&instance.character, &instance.state)];
+			let images = state_images(state).into_iter().chain(instance.sprite.as_ref().map(normalise_path));
+			(name.clone(), images.collect())
+		}).collect();
+
+		let mut instances = snapshot.instances;
+		for (name, images) in instance_images {
+			if let Err(error) = self.load_command_resources(ctx, (images, None)) {
+				let message = format!("Failed to load resources for instance: {}, because: {}", name.0, error);
+				self.render.notify(message, &self.settings);
+				instances.instances.retain(|(other, _)| other != &name);
+			}
+		}
+
+		// The instances are stored in the order they are drawn, which is only kept
+		// if a draw order was set so that instances spawned later are ordered the same.
+		let draw_order = self.render.stage.restore_from_snapshot(&self.script, instances);
+		self.render.draw_order = snapshot.draw_order.map(|_| draw_order);
+
+		let command = &self.script[&self.state.target];
+		match command {
+			Command::Dialogue(_, _, _) | Command::Diverge(_) => {
+				command.display(ctx, &mut self.state, &mut self.render, &self.script, &self.settings);
+			}
+			Command::Delay(_) | Command::Pause(_, Some(_)) | Command::Yield =>
+				command.execute(ctx, &mut self.state, &mut self.render, &self.script, &self.settings),
+			_ => (),
+		}
+	}
+
+	/// Starts the script from a label instead of the beginning when the player next advances.
+	/// The history replays from the label when the game is loaded.
+	/// Returns an error if the label does not exist.
+	pub fn start_at(&mut self, label: &Label) -> ggez::GameResult {
+		let Label(name) = label;
+		let target = self.script.labels.get(label).cloned().ok_or_else(||
+			ggez::GameError::ResourceLoadError(format!("Label: {}, does not exist", name)))?;
+		self.history.start_target = Some(target.clone());
+		self.state.next_target = Some(target);
+		Ok(())
+	}
+
+	/// Saves a screenshot to the screenshots folder of the save directory and notifies the player.
+	fn save_screenshot(&mut self, ctx: &mut ggez::Context) {
+		let time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
+			.map(|time| time.as_millis()).unwrap_or(0);
+		let path = save_file(ctx, &self.settings, &format!("/screenshots/{}.png", time));
+		let message = match take_screenshot(ctx).and_then(|screenshot| encode_png(&screenshot))
+			.and_then(|bytes| write_atomic(&path, &bytes)) {
+			Ok(()) => format!("Saved screenshot to: {}", path.display()),
+			Err(error) => format!("Failed to save screenshot because: {}", error),
+		};
+		self.render.notify(message, &self.settings);
+	}
+
+	/// Continues the music from a position in seconds.
+	/// Music that cannot be sought keeps playing from the beginning.
+	fn seek_music(&mut self, ctx: &mut ggez::Context, position: f32) {
+		let (music, path) = match (&mut self.state.music, &self.state.music_path) {
+			(Some(music), Some(path)) => (music, path),
+			_ => return,
+		};
+
+		let data = self.script.sound_data(path).clone();
+		if let Err(error) = music.seek(ctx, data, self.state.music_loop, position) {
+			eprintln!("Warning: Failed to continue music from: {} seconds, because: {}", position, error);
+		}
+	}
+
+	/// Selects the branch with a keyboard index and deselects every other branch.
+	fn select_branch(&mut self, selected: Option<usize>) {
+		self.selected_branch = selected;
+		self.render.branches.iter_mut().for_each(|(button, _)|
+			button.select(Some(button.keyboard_index) == selected));
+	}
+
+	/// Moves between branches, chooses the selected branch with the keyboard and
+	/// returns whether the key was used.
+	fn branch_key(&mut self, ctx: &mut ggez::Context, key: event::KeyCode, modifiers: event::KeyMods) -> bool {
+		use event::KeyCode;
+		let count = self.render.branches.len();
+		if count == 0 { return false; }
+		let forward = match key {
+			KeyCode::Tab => !modifiers.contains(event::KeyMods::SHIFT),
+			KeyCode::Down | KeyCode::Right => true,
+			KeyCode::Up | KeyCode::Left => false,
+			KeyCode::Return | KeyCode::NumpadEnter | KeyCode::Space => {
+				let selected = self.render.branches.iter()
+					.find(|(button, _)| Some(button.keyboard_index) == self.selected_branch);
+				if let Some((_, label)) = selected {
+					let label = label.clone();
+					self.diverge(ctx, &label);
+				}
+				return true;
+			}
+			_ => return false,
+		};
+
+		self.select_branch(Some(cycle_index(self.selected_branch, count, forward)));
+		true
+	}
+
+	/// Jumps to a label chosen by the player in a divergence.
+	/// The choice is recorded if statistics are collected.
 	pub fn diverge(&mut self, ctx: &mut ggez::Context, label: &Label) {
+		if self.settings.collect_stats {
+			self.record_choice(ctx, label);
+		}
+		self.follow_branch(ctx, label);
+	}
+
+	/// The number of times each branch has been chosen, including in previous playthroughs.
+	/// These are only recorded if `collect_stats` is set in the settings.
+	pub fn choice_stats(&self) -> &ChoiceStats {
+		&self.choice_stats
+	}
+
+	/// Records a choice at the current divergence, writing the statistics once enough choices are unsaved.
+	fn record_choice(&mut self, ctx: &mut ggez::Context, label: &Label) {
+		self.choice_stats.record(&self.state.target, label);
+		self.unsaved_choices += 1;
+		if self.unsaved_choices >= self.settings.stats_flush_interval {
+			self.flush_choice_stats(ctx);
+		}
+	}
+
+	/// Writes the statistics of the choices if any have not been written.
+	fn flush_choice_stats(&mut self, ctx: &mut ggez::Context) {
+		if self.unsaved_choices == 0 { return; }
+		match save_choice_stats(ctx, &self.settings, &self.choice_stats) {
+			Ok(()) => self.unsaved_choices = 0,
+			Err(error) => eprintln!("Warning: Failed to save choice statistics because: {}", error),
+		}
+	}
+
+	/// Draws the share of choices that chose each branch in the top right of its button.
+	fn draw_choice_stats(&self, ctx: &mut ggez::Context) -> ggez::GameResult {
+		if !self.settings.developer || !self.settings.collect_stats { return Ok(()); }
+		let labels = self.render.branches.iter().map(|(_, label)| label);
+		let percentages = self.choice_stats.percentages(&self.state.target, labels);
+		let margin = interface::layout::margin(&self.settings);
+		self.render.branches.iter().zip(percentages).try_for_each(|((button, _), percentage)| {
+			let text = graphics::Text::new(format!("{:.0}%", percentage));
+			let rectangle = button.rectangle();
+			let destination = [rectangle.x + rectangle.w - text.width(ctx) as f32 - margin, rectangle.y + margin];
+			let colour: graphics::Color = self.settings.foreground_colour.into();
+			graphics::draw(ctx, &text, graphics::DrawParam::new().dest(destination).color(colour))
+		})
+	}
+
+	/// Jumps to a label in a divergence without recording the choice.
+	fn follow_branch(&mut self, ctx: &mut ggez::Context, label: &Label) {
 		let target = self.script.labels[label].clone();
-		self.history.divergences.push(label.clone());
+		self.history.diverge(label.clone());
 		self.state.next_target = Some(target);
 		self.render.branches.clear();
+		self.selected_branch = None;
 		self.advance(ctx);
 	}
 }
 
 impl event::EventHandler for GameState {
 	fn update(&mut self, ctx: &mut ggez::Context) -> ggez::GameResult {
+		let instant = Instant::now();
 		rate(ctx, self.settings.text_speed, |_|
 			Ok(self.render.text.as_mut().map(|text| text.step())))?;
 		self.state.sounds.retain(ggez::audio::SoundSource::playing);
 		self.render.stage.update(ctx);
-		Ok(())
+		self.render.tick_text_fade(ctx);
+		self.render.tick_camera(ctx);
+		self.poll_characters(ctx);
+		if let Some(Command::Sync) = self.script.commands.get(self.state.target.0) {
+			self.wait_for_animations(ctx);
+		}
+
+		if self.state.delay_until.map(|time| Instant::now() >= time).unwrap_or(false) {
+			self.run_commands(ctx);
+		}
+
+		let resume = self.yield_frame(|game| if let Some(mut callback) = game.yield_callback.take() {
+			(callback.0)(game, ctx);
+			game.yield_callback.get_or_insert(callback);
+		});
+
+		if resume {
+			self.run_commands(ctx);
+		}
+
+		let delta = ggez::timer::delta(ctx).as_secs_f32() * 1_000.0;
+		self.update_quick_modes(ctx, delta);
+		if let Some(transition) = &mut self.render.transition {
+			if transition.update(delta) {
+				self.advance(ctx);
+			}
+		}
+
+		if self.render.transition.as_ref().map(TransitionState::is_finished).unwrap_or(false) {
+			self.render.transition = None;
+		}
+
+		let Stage(stage) = &self.render.stage;
+		let sources = self.state.sounds.len() + self.state.music.iter().count();
+		let performance = &mut self.render.performance;
+		performance.record_frame(ggez::timer::delta(ctx));
+		performance.record_update(instant.elapsed());
+		performance.rebuild(ctx, stage.len(), self.script.images.len(), sources)
 	}
 
 	fn draw(&mut self, ctx: &mut ggez::Context) -> ggez::GameResult {
+		let instant = Instant::now();
 		graphics::clear(ctx, graphics::BLACK);
-		self.render.background.as_ref().map(|image| graphics::draw(ctx,
-			image, graphics::DrawParam::new())).transpose()?;
-		self.render.stage.draw(ctx)?;
-		self.render.character.as_ref().map(|text| text.draw(ctx)).transpose()?;
-		self.render.text.as_ref().map(|text| text.draw(ctx)).transpose()?;
-		self.render.branches.iter().try_for_each(|(button, _)| button.draw(ctx))?;
-		self.render.shadow_bars.iter().try_for_each(|bar| {
-			let bar = graphics::Mesh::new_rectangle(ctx,
-				graphics::DrawMode::fill(), *bar, graphics::BLACK)?;
-			graphics::draw(ctx, &bar, graphics::DrawParam::new())
-		})?;
+		self.render.draw(ctx, &self.settings)?;
+		self.draw_choice_stats(ctx)?;
+		self.draw_cursor(ctx)?;
+		self.render.performance.record_draw(instant.elapsed());
 		graphics::present(ctx)
 	}
 
 	fn mouse_button_down_event(&mut self, ctx: &mut ggez::Context,
 	                           _: input::mouse::MouseButton, x: f32, y: f32) {
-		let (x, y) = transform(ctx, (x, y));
-		match self.script[&self.state.target] {
-			Command::Diverge(_) => {
-				let label = self.render.branches.iter()
-					.find(|(button, _)| button.rectangle().contains([x, y]));
-				label.map(|(_, label)| label).cloned()
-					.map(|label| self.diverge(ctx, &label));
+		let point = transform(ctx, self.settings.scale_factor, (x, y));
+		if self.render.notice.take().is_some() || self.state.yielded { return; }
+		match self.render.click(point, &self.settings) {
+			Click::Advance => {
+				if let (_, Some(sound)) = &self.ui_sounds {
+					self.state.play_sound(ctx, sound.clone(), &self.settings);
+				}
+
+				match self.settings.click_policy {
+					ClickPolicy::Sequential if !self.render.animations_finished() =>
+						self.render.finish_animations(),
+					_ => self.advance(ctx),
+				}
 			}
-			_ => self.advance(ctx),
+			Click::Choose(label) => self.diverge(ctx, &label),
+			Click::Quick(action) => self.quick_action(ctx, action),
+			Click::Ignore => (),
 		}
 	}
 
 	fn mouse_motion_event(&mut self, ctx: &mut ggez::Context, x: f32, y: f32, _: f32, _: f32) {
-		self.render.branches.iter_mut().for_each(|(button, _)|
-			button.update(transform(ctx, (x, y))));
+		let point = transform(ctx, self.settings.scale_factor, (x, y));
+		let branches = self.render.branches.iter_mut()
+			.fold(false, |entered, (button, _)| button.update(point) || entered);
+		let hovered = self.render.branches.iter().find(|(button, _)| button.hovered);
+		if let Some(index) = hovered.map(|(button, _)| button.keyboard_index) {
+			self.select_branch(Some(index));
+		}
+		let quick_menu = self.render.text_visible() && self.render.quick_menu.update(point);
+		let entered = quick_menu || branches;
+		if let (true, (Some(sound), _)) = (entered, &self.ui_sounds) {
+			self.state.play_sound(ctx, sound.clone(), &self.settings);
+		}
+	}
+
+	fn mouse_wheel_event(&mut self, _: &mut ggez::Context, _: f32, y: f32) {
+		self.render.scroll_branches(y * Render::SCROLL_DISTANCE, &self.settings);
 	}
 
 	fn key_down_event(&mut self, ctx: &mut Context, key: event::KeyCode,
 	                  modifiers: event::KeyMods, _: bool) {
+		// Tab finishes any running animations before it moves the branch selection.
+		if key == event::KeyCode::Tab && !self.render.animations_finished() {
+			return self.render.finish_animations();
+		}
+		if self.branch_key(ctx, key, modifiers) { return; }
+
 		if self.settings.developer {
-			if modifiers.contains(event::KeyMods::CTRL) {
-				if key == event::KeyCode::R {
-					save_history(ctx, &self.settings, &self.history);
+			match key {
+				event::KeyCode::R if modifiers.contains(event::KeyMods::CTRL) && self.save(ctx) => {
 					self.reload = true;
 					event::quit(ctx);
 				}
+				event::KeyCode::F4 => {
+					let performance = &mut self.render.performance;
+					performance.visible = !performance.visible;
+				}
+				event::KeyCode::F12 => self.save_screenshot(ctx),
+				_ => (),
 			}
 		}
 	}
 
 	fn quit_event(&mut self, ctx: &mut Context) -> bool {
-		save_history(ctx, &self.settings, &self.history);
-		false
+		// The game closes on a second request even if it could not be saved.
+		if std::mem::replace(&mut self.quit_requested, true) { return false; }
+		match self.write_save(ctx) {
+			Ok(()) => false,
+			Err(error) => {
+				let message = format!("Failed to save the game because: {}\n\
+					Close the game again to quit without saving.", error);
+				self.render.notify(message, &self.settings);
+				true
+			}
+		}
 	}
 
 	fn resize_event(&mut self, ctx: &mut ggez::Context, width: f32, height: f32) {
-		let window_ratio = width / height;
-		let view_ratio = self.settings.width / self.settings.height;
-		graphics::set_screen_coordinates(ctx, match view_ratio < window_ratio {
-			true => {
-				let (screen_width, view_height) = (height * view_ratio, self.settings.height);
-				let offset = (width - screen_width) * (self.settings.width / screen_width) / 2.0;
-				self.render.shadow_bars[1] = [self.settings.width, 0.0, offset, view_height].into();
-				self.render.shadow_bars[0] = [-offset, 0.0, offset, view_height].into();
-				[-offset, 0.0, self.settings.width + offset * 2.0, view_height]
-			}
-			false => {
-				let (screen_height, view_width) = (width * view_ratio.recip(), self.settings.width);
-				let offset = (height - screen_height) * (self.settings.height / screen_height) / 2.0;
-				self.render.shadow_bars[1] = [0.0, self.settings.height, view_width, offset].into();
-				self.render.shadow_bars[0] = [0.0, -offset, view_width, offset].into();
-				[0.0, -offset, view_width, self.settings.height + offset * 2.0]
-			}
-		}.into()).unwrap();
+		let screen = screen_coordinates((width, height), &self.settings);
+		self.render.shadow_bars = letterbox_bars(screen, &self.settings);
+		graphics::set_screen_coordinates(ctx, screen).unwrap();
+		self.render.relayout(ctx, &self.settings);
+	}
+}
+
+/// Finds the next or previous index of a list, wrapping around at either end.
+/// The first or last index is found if there is no current index.
+fn cycle_index(current: Option<usize>, count: usize, forward: bool) -> usize {
+	match (current, forward) {
+		(None, true) => 0,
+		(None, false) => count - 1,
+		(Some(index), true) => (index + 1) % count,
+		(Some(index), false) => (index + count - 1) % count,
 	}
 }
 
 pub fn rate<F, R>(ctx: &mut ggez::Context, rate: u32, mut function: F) -> ggez::GameResult
 	where F: FnMut(&mut ggez::Context) -> ggez::GameResult<R> {
-	Ok(while ggez::timer::check_update_time(ctx, rate) { function(ctx)?; })
+	while ggez::timer::check_update_time(ctx, rate) { function(ctx)?; }
+	Ok(())
 }
 
-/// Transforms absolute coordinates into screen coordinates.
-pub fn transform(ctx: &ggez::Context, (x, y): (f32, f32)) -> (f32, f32) {
-	let screen = graphics::screen_coordinates(ctx);
-	let (width, height) = graphics::drawable_size(ctx);
+/// Converts a point in window coordinates to a point in screen coordinates.
+pub fn transform(ctx: &ggez::Context, scale_factor: f32, point: (f32, f32)) -> (f32, f32) {
+	transform_point(graphics::screen_coordinates(ctx), graphics::drawable_size(ctx), scale_factor, point)
+}
+
+/// Converts a point in window coordinates to a point in screen coordinates
+/// given the size of the drawable area in physical pixels.
+pub fn transform_point(screen: graphics::Rect, (width, height): (f32, f32),
+                       scale_factor: f32, (x, y): (f32, f32)) -> (f32, f32) {
+	let (x, y) = (x * scale_factor, y * scale_factor);
 	(screen.x + (screen.w / width) * x, screen.y + (screen.h / height) * y)
 }
 
-pub fn run<F>(settings: Settings, mut script: F) -> ggez::GameResult
+/// Converts a point in screen coordinates to a point in window coordinates.
+/// This is the inverse of [`transform`](fn.transform.html).
+pub fn inverse_transform(ctx: &ggez::Context, scale_factor: f32, point: (f32, f32)) -> (f32, f32) {
+	inverse_transform_point(graphics::screen_coordinates(ctx), graphics::drawable_size(ctx), scale_factor, point)
+}
+
+/// Converts a point in screen coordinates to a point in window coordinates
+/// given the size of the drawable area in physical pixels.
+pub fn inverse_transform_point(screen: graphics::Rect, (width, height): (f32, f32),
+                               scale_factor: f32, (x, y): (f32, f32)) -> (f32, f32) {
+	let (x, y) = ((x - screen.x) * (width / screen.w), (y - screen.y) * (height / screen.h));
+	(x / scale_factor, y / scale_factor)
+}
+
+/// Finds the area of the window in window coordinates that the view is drawn in, excluding the letterbox.
+pub fn view_rect(ctx: &ggez::Context, settings: &Settings) -> graphics::Rect {
+	let (left, top) = inverse_transform(ctx, settings.scale_factor, (0.0, 0.0));
+	let (right, bottom) = inverse_transform(ctx, settings.scale_factor, (settings.width, settings.height));
+	graphics::Rect::new(left, top, right - left, bottom - top)
+}
+
+/// Finds the screen coordinates that fit the view in a window of a size while keeping its aspect ratio.
+/// The view is centred and the rest of the window is filled by the letterbox.
+pub fn screen_coordinates((width, height): (f32, f32), settings: &Settings) -> graphics::Rect {
+	let window_ratio = width / height;
+	let view_ratio = settings.width / settings.height;
+	match view_ratio < window_ratio {
+		true => {
+			let screen_width = height * view_ratio;
+			let offset = (width - screen_width) * (settings.width / screen_width) / 2.0;
+			[-offset, 0.0, settings.width + offset * 2.0, settings.height]
+		}
+		false => {
+			let screen_height = width * view_ratio.recip();
+			let offset = (height - screen_height) * (settings.height / screen_height) / 2.0;
+			[0.0, -offset, settings.width, settings.height + offset * 2.0]
+		}
+	}.into()
+}
+
+/// Finds the bars in screen coordinates that fill the screen on either side of the view.
+pub fn letterbox_bars(screen: graphics::Rect, settings: &Settings) -> [graphics::Rect; 2] {
+	match screen.x < 0.0 {
+		true => {
+			let offset = -screen.x;
+			[[-offset, 0.0, offset, settings.height].into(), [settings.width, 0.0, offset, settings.height].into()]
+		}
+		false => {
+			let offset = -screen.y;
+			[[0.0, -offset, settings.width, offset].into(), [0.0, settings.height, settings.width, offset].into()]
+		}
+	}
+}
+
+/// Runs the game from the history returned by the script loader.
+/// The script loader is called again whenever the game is reloaded.
+pub fn run<F>(settings: Settings, script: F) -> ggez::GameResult
 	where F: FnMut(&mut ggez::Context, &Settings) -> ggez::GameResult<(Script, History)> {
+	let mut started = false;
+	run_with_menu(settings, script, move |_, _, _| Ok(match std::mem::replace(&mut started, true) {
+		false => MenuChoice::Continue,
+		true => MenuChoice::Quit,
+	}))
+}
+
+/// A choice made by the player in a menu that is shown before the game starts.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MenuChoice {
+	/// Continues from the history returned by the script loader.
+	Continue,
+	/// Starts the game from the beginning.
+	NewGame,
+	/// Continues from the history in a save slot. The game is saved to the same slot.
+	LoadSlot(u32),
+	/// Starts the game from a label.
+	Chapter(Label),
+	/// Closes the game.
+	Quit,
+}
+
+/// Runs the game, showing a menu before the game starts and again whenever the game is quit.
+/// The menu is given the event loop so that it can run its own event handler,
+/// such as [`show_save_select`](fn.show_save_select.html).
+pub fn run_with_menu<F, M>(mut settings: Settings, mut script: F, mut menu: M) -> ggez::GameResult
+	where F: FnMut(&mut ggez::Context, &Settings) -> ggez::GameResult<(Script, History)>,
+	      M: FnMut(&mut ggez::Context, &mut event::EventsLoop, &Settings) -> ggez::GameResult<MenuChoice> {
 	let ctx = ggez::ContextBuilder::new("kanna", "kanna")
 		.window_mode(ggez::conf::WindowMode {
 			resizable: true,
@@ -177,31 +1071,303 @@ pub fn run<F>(settings: Settings, mut script: F) -> ggez::GameResult
 		});
 
 	let (ctx, event_loop) = &mut ctx.build()?;
+	if settings.auto_detect_scale || settings.auto_detect_ui_scale {
+		settings.scale_factor = graphics::window(ctx).get_hidpi_factor() as f32;
+	}
+
+	// The view is already scaled to fill the drawable size of the display,
+	// so scaling the interface by the scale factor as well would apply it twice.
+	if settings.auto_detect_ui_scale {
+		settings.ui_scale = 1.0;
+	}
+
+	settings.apply_high_contrast();
+
 	settings.resource_paths.iter().map(std::path::PathBuf::from)
 		.for_each(|path| ggez::filesystem::mount(ctx, path.as_path(), true));
 
 	loop {
-		let (script, history) = script(ctx, &settings)?;
+		let choice = menu(ctx, event_loop, &settings)?;
+		ctx.continuing = true;
+		let mut settings = settings.clone();
+		match &choice {
+			MenuChoice::Quit => break Ok(()),
+			MenuChoice::LoadSlot(slot) => settings.save_path = slot_path(*slot),
+			_ => (),
+		}
+
+		play(ctx, event_loop, settings, &mut script, &choice)?;
+	}
+}
+
+/// Plays the game until it is quit, reloading the script whenever the game requests it.
+/// The history from the script loader is discarded when a new game or chapter is started.
+fn play<F>(ctx: &mut ggez::Context, event_loop: &mut event::EventsLoop, settings: Settings,
+           script: &mut F, choice: &MenuChoice) -> ggez::GameResult
+	where F: FnMut(&mut ggez::Context, &Settings) -> ggez::GameResult<(Script, History)> {
+	let mut previous: Option<Script> = None;
+	let mut first = true;
+	loop {
+		let (script, mut history) = script(ctx, &settings)?;
+		if let (true, MenuChoice::NewGame) | (true, MenuChoice::Chapter(_)) = (first, choice) {
+			history = History::default();
+		}
+
+		if let (Some(previous), Some(snapshot)) = (previous.take(), &history.snapshot) {
+			// The saved state can only be restored if the script before it is unchanged.
+			// Otherwise the new script is replayed up to the same point.
+			let target = Target(snapshot.target);
+			if !Script::diff(&previous, &script).is_safe_to_continue_from(&target) {
+				history.snapshot = None;
+			}
+		}
+
 		let mut state = GameState::load(ctx, script, settings.clone(), history);
+		if let (true, MenuChoice::Chapter(label)) = (first, choice) {
+			state.start_at(label)?;
+		}
+
 		event::run(ctx, event_loop, &mut state)?;
 		if !state.reload { break Ok(()); }
+		previous = Some(state.script);
+		first = false;
 		ctx.continuing = true;
 	}
 }
 
+/// Captures the frame that is currently drawn to the screen.
+pub fn take_screenshot(ctx: &mut ggez::Context) -> ggez::GameResult<image::RgbaImage> {
+	let screenshot = graphics::screenshot(ctx)?;
+	let (width, height) = (screenshot.width() as u32, screenshot.height() as u32);
+	let image = image::RgbaImage::from_raw(width, height, screenshot.to_rgba8(ctx)?).ok_or_else(||
+		ggez::GameError::RenderError("Screenshot does not have the size of the screen".to_owned()))?;
+	// The rows of the screen are read from the bottom upwards.
+	Ok(image::imageops::flip_vertical(&image))
+}
+
+/// Scales a screenshot down to the thumbnail size in the settings and encodes it as a PNG image.
+pub fn encode_thumbnail(screenshot: &image::RgbaImage, settings: &Settings) -> ggez::GameResult<Vec<u8>> {
+	encode_png(&image::imageops::resize(screenshot, settings.thumbnail_width.max(1),
+		settings.thumbnail_height.max(1), image::FilterType::Triangle))
+}
+
+/// Decodes a thumbnail that was encoded with [`encode_thumbnail`](fn.encode_thumbnail.html).
+pub fn decode_thumbnail(bytes: &[u8]) -> ggez::GameResult<image::RgbaImage> {
+	Ok(image::load_from_memory_with_format(bytes, image::ImageFormat::PNG)?.to_rgba())
+}
+
+fn encode_png(image: &image::RgbaImage) -> ggez::GameResult<Vec<u8>> {
+	let mut bytes = Vec::new();
+	image::png::PNGEncoder::new(&mut bytes)
+		.encode(image, image.width(), image.height(), image::ColorType::RGBA(8))?;
+	Ok(bytes)
+}
+
+/// Loads the thumbnail of the save in a slot if the slot has been saved to.
+/// Saves without a thumbnail or that cannot be read have no thumbnail.
+fn slot_thumbnail(ctx: &mut ggez::Context, settings: &Settings, slot: u32) -> Option<graphics::Image> {
+	let bytes = std::fs::read(save_file(ctx, settings, &slot_path(slot))).ok()?;
+	let thumbnail = decode_thumbnail(&History::parse(&bytes).ok()?.thumbnail?).ok()?;
+	graphics::Image::from_rgba8(ctx, thumbnail.width() as u16,
+		thumbnail.height() as u16, &thumbnail.into_raw()).ok()
+}
+
+/// Path of the save file for a save slot, relative to the save directory.
+pub fn slot_path(slot: u32) -> String {
+	format!("/slots/{}.save", slot)
+}
+
+/// Shows a button for each save slot and waits for the player to choose one.
+/// Returns `None` if the menu is closed without choosing a slot.
+pub fn show_save_select(ctx: &mut ggez::Context, event_loop: &mut event::EventsLoop,
+                        settings: &Settings) -> ggez::GameResult<Option<u32>> {
+	let margin = interface::layout::margin(settings);
+	let rectangles = interface::layout::branch_buttons(settings, settings.save_slots as usize);
+	let buttons = (1..=settings.save_slots).zip(rectangles).map(|(slot, rectangle)| {
+		let string = match save_file(ctx, settings, &slot_path(slot)).exists() {
+			true => format!("Slot {}", slot),
+			false => format!("Slot {} (empty)", slot),
+		};
+		(menu_button(string, rectangle, margin, settings), slot)
+	}).collect();
+
+	let thumbnails = (1..=settings.save_slots)
+		.map(|slot| slot_thumbnail(ctx, settings, slot)).collect();
+	let mut select = MenuSelect { buttons, thumbnails, margin, scale_factor: settings.scale_factor, choice: None };
+	event::run(ctx, event_loop, &mut select)?;
+	ctx.continuing = true;
+	Ok(select.choice)
+}
+
+/// Shows a button for each chapter of a script that has been reached in any playthrough
+/// and waits for the player to choose one. The chosen chapter starts from a new game.
+/// Returns `None` if the menu is closed without choosing a chapter or if no chapter has been reached.
+pub fn show_chapter_select(ctx: &mut ggez::Context, event_loop: &mut event::EventsLoop,
+                           settings: &Settings, script: &Script) -> ggez::GameResult<Option<MenuChoice>> {
+	let persistent = load_persistent(ctx, settings).unwrap_or_default();
+	let chapters: Vec<_> = script.unlocked_chapters(&persistent).collect();
+	if chapters.is_empty() { return Ok(None); }
+
+	let margin = interface::layout::margin(settings);
+	let rectangles = interface::layout::branch_buttons(settings, chapters.len());
+	let buttons = chapters.into_iter().zip(rectangles).map(|(chapter, rectangle)|
+		(menu_button(chapter.title.clone(), rectangle, margin, settings), chapter.start().clone())).collect();
+
+	let mut select = MenuSelect { buttons, thumbnails: Vec::new(), margin, scale_factor: settings.scale_factor, choice: None };
+	event::run(ctx, event_loop, &mut select)?;
+	ctx.continuing = true;
+	Ok(select.choice.map(MenuChoice::Chapter))
+}
+
+/// Creates a button with centred text for a menu.
+fn menu_button(string: String, rectangle: graphics::Rect, margin: f32, settings: &Settings) -> Button {
+	let text = RenderText::new(string, settings.foreground_colour);
+	Button::new(TextBox::from_rectangle(text, rectangle, settings.background_colour)
+		.alignment(graphics::Align::Center).padding(margin).text_scale(settings.ui_scale),
+		settings.background_colour, settings.secondary_colour)
+}
+
+/// Menu that shows a button for each choice, such as each save slot or chapter.
+struct MenuSelect<T> {
+	buttons: Vec<(Button, T)>,
+	/// Thumbnail of each choice, in the same order as the buttons.
+	thumbnails: Vec<Option<graphics::Image>>,
+	margin: f32,
+	scale_factor: f32,
+	/// Choice that was made by the player.
+	choice: Option<T>,
+}
+
+impl<T: Clone> event::EventHandler for MenuSelect<T> {
+	fn update(&mut self, _: &mut ggez::Context) -> ggez::GameResult {
+		Ok(())
+	}
+
+	fn draw(&mut self, ctx: &mut ggez::Context) -> ggez::GameResult {
+		graphics::clear(ctx, graphics::BLACK);
+		self.buttons.iter().try_for_each(|(button, _)| button.draw(ctx))?;
+		for ((button, _), thumbnail) in self.buttons.iter().zip(&self.thumbnails) {
+			if let Some(thumbnail) = thumbnail {
+				// Thumbnails are scaled down to fit inside the left of the button.
+				let rectangle = button.rectangle();
+				let scale = (rectangle.h - 2.0 * self.margin).max(0.0) / thumbnail.height() as f32;
+				graphics::draw(ctx, thumbnail, graphics::DrawParam::new()
+					.dest([rectangle.x + self.margin, rectangle.y + self.margin]).scale([scale, scale]))?;
+			}
+		}
+		graphics::present(ctx)
+	}
+
+	fn mouse_button_down_event(&mut self, ctx: &mut ggez::Context,
+	                           _: input::mouse::MouseButton, x: f32, y: f32) {
+		let (x, y) = transform(ctx, self.scale_factor, (x, y));
+		if let Some((_, choice)) = self.buttons.iter().find(|(button, _)| button.rectangle().contains([x, y])) {
+			self.choice = Some(choice.clone());
+			event::quit(ctx);
+		}
+	}
+
+	fn mouse_motion_event(&mut self, ctx: &mut ggez::Context, x: f32, y: f32, _: f32, _: f32) {
+		let point = transform(ctx, self.scale_factor, (x, y));
+		self.buttons.iter_mut().for_each(|(button, _)| { button.update(point); });
+	}
+}
+
 /// Loads a script from a given path. No resources are loaded.
 /// Loading referenced resources is performed using [`load_resources`](fn.load_resources.html).
 pub fn load_script<P: Into<PathBuf>>(ctx: &mut ggez::Context, path: P) -> ggez::GameResult<Script> {
 	let path = &path.into();
-	crate::parser::parse(&read_string(ctx, path)?).map_err(|error|
-		panic!("Failed to parse script at: {}, because: {:?}", path.display(), error))
+	parse_script(path, &read_string(ctx, path)?)
+}
+
+/// Parses the source of a script, listing the parse errors with the source text before each of them.
+fn parse_script(path: &Path, source: &str) -> ggez::GameResult<Script> {
+	crate::parser::parse(source).map_err(|errors| {
+		let errors = errors.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+		ggez::GameError::ResourceLoadError(format!("Failed to parse script at: {}, because: {}", path.display(), errors))
+	})
+}
+
+/// Loads a script from a given path, using a cached copy of the parsed script if possible.
+/// The cache is kept in the user data directory at the path of the script with a `.cache`
+/// extension and is rewritten whenever the script changes.
+/// No resources are loaded.
+pub fn load_script_cached<P: Into<PathBuf>>(ctx: &mut ggez::Context, path: P) -> ggez::GameResult<Script> {
+	let path = &path.into();
+	let source = read_string(ctx, path)?;
+	let cache_path = resolve_save_file(ggez::filesystem::user_data_dir(ctx), &format!("{}.cache", path.display()));
+	let cached = std::fs::read(&cache_path).ok()
+		.and_then(|bytes| read_script_cache(&bytes, &source));
+	if let Some(script) = cached {
+		return Ok(script);
+	}
+
+	let script = parse_script(path, &source)?;
+	let bytes = write_script_cache(&script, &source).map_err(|error| ggez::GameError::FilesystemError(
+		format!("Failed to serialize script cache because: {}", error)))?;
+	write_atomic(&cache_path, &bytes)?;
+	Ok(script)
 }
 
-/// Loads a set of characters from a given path. Characters are formatted in the TOML format.
+/// Version of the format of the script cache.
+/// This must be increased whenever the serialized form of a script changes
+/// so that caches written by older versions are parsed again.
+const SCRIPT_CACHE_VERSION: u32 = 7;
+
+/// A parsed script along with the length and hash of the source it was parsed from.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct ScriptCache {
+	version: u32,
+	source_length: u64,
+	source_hash: u64,
+	script: crate::PortableScript,
+}
+
+/// Serializes a parsed script into a cache for the source it was parsed from.
+fn write_script_cache(script: &Script, source: &str) -> serde_cbor::Result<Vec<u8>> {
+	serde_cbor::to_vec(&ScriptCache {
+		version: SCRIPT_CACHE_VERSION,
+		source_length: source.len() as u64,
+		source_hash: content_hash(source.as_bytes()),
+		script: script.to_portable(),
+	})
+}
+
+/// Deserializes a cached script without any resources.
+/// Returns `None` if the cache is invalid, has a different version or was made from a different source.
+fn read_script_cache(bytes: &[u8], source: &str) -> Option<Script> {
+	let cache = serde_cbor::from_slice::<ScriptCache>(bytes).ok().filter(|cache|
+		cache.version == SCRIPT_CACHE_VERSION && cache.source_length == source.len() as u64
+			&& cache.source_hash == content_hash(source.as_bytes()))?;
+	let crate::PortableScript { characters, spawn_defaults, commands, labels } = cache.script;
+	Some(Script { characters, spawn_defaults, commands, labels, ..Script::default() })
+}
+
+/// Loads a set of characters from a given path.
+/// Characters are formatted in JSON or RON if the path has a `.json` or `.ron` extension and TOML otherwise.
 pub fn load_characters<P: Into<PathBuf>>(ctx: &mut ggez::Context, path: P) -> ggez::GameResult<Characters> {
+	load_character_set(ctx, path).map(|(characters, _)| characters)
+}
+
+/// Loads a set of characters and the state and position each is spawned with by default.
+pub fn load_character_set<P: Into<PathBuf>>(ctx: &mut ggez::Context, path: P)
+                                            -> ggez::GameResult<(Characters, SpawnDefaults)> {
 	let path = &path.into();
-	toml::from_str(&read_string(ctx, path)?).map_err(|error|
-		panic!("Failed to parse character set at: {}, because: {}", path.display(), error))
+	let string = read_string(ctx, path)?;
+	let (format, characters) = match path.extension().and_then(std::ffi::OsStr::to_str) {
+		#[cfg(feature = "json")]
+		Some("json") => ("JSON", Characters::parse_json_with_defaults(&string)),
+		#[cfg(not(feature = "json"))]
+		Some("json") => ("JSON", Err("the `json` feature is not enabled".to_owned())),
+		#[cfg(feature = "ron")]
+		Some("ron") => ("RON", Characters::parse_ron_with_defaults(&string)),
+		#[cfg(not(feature = "ron"))]
+		Some("ron") => ("RON", Err("the `ron` feature is not enabled".to_owned())),
+		_ => ("TOML", Characters::parse_with_defaults(&string)),
+	};
+
+	characters.map_err(|error| ggez::GameError::ResourceLoadError(format!("Failed to parse \
+		{} character set at: {}, because: {}", format, path.display(), error)))
 }
 
 /// Reads a file from a given path as a string.
@@ -213,60 +1379,514 @@ pub fn read_string<P: AsRef<Path>>(ctx: &mut ggez::Context, path: P) -> ggez::Ga
 }
 
 /// Loads the game history from the save path in the settings.
-pub fn load_history(ctx: &mut ggez::Context, settings: &Settings) -> ggez::GameResult<History> {
-	let buffer: Result<Vec<_>, _> = ggez::filesystem::open(ctx,
-		&settings.save_path)?.bytes().collect();
-	toml::from_slice(&buffer?).map_err(|error| {
-		let error = format!("Failed to load saved history because: {}", error);
-		ggez::GameError::ResourceLoadError(error)
+/// A corrupt save is copied to a `.bak` file so that it is not lost when the game is next saved.
+pub fn load_history(ctx: &mut ggez::Context, settings: &Settings) -> Result<History, SaveError> {
+	let path = save_file(ctx, settings, &settings.save_path);
+	if !path.exists() {
+		return Err(SaveError::Missing);
+	}
+
+	let bytes = std::fs::read(&path).map_err(|error|
+		SaveError::Corrupt(format!("Failed to read saved history because: {}", error)))?;
+	History::parse(&bytes).map_err(|error| {
+		let mut backup = path.clone().into_os_string();
+		backup.push(".bak");
+		let backup = PathBuf::from(backup);
+		match write_atomic(&backup, &bytes) {
+			Ok(()) => SaveError::Corrupt(format!("Failed to load saved history because: {}", error)),
+			Err(backup_error) => SaveError::Backup(format!("Failed to load saved history because: {}, \
+				and failed to back it up to: {}, because: {}", error, backup.display(), backup_error)),
+		}
 	})
 }
 
 /// Saves the game history to the path specified in the settings.
-pub fn save_history(ctx: &mut ggez::Context, settings: &Settings, history: &History) {
-	let mut file = ggez::filesystem::create(ctx, &settings.save_path).unwrap_or_else(|error|
-		panic!("Failed to open file: {}, for saving because: {}", settings.save_path, error));
-	file.write_all(&toml::to_vec(history).unwrap_or_else(|error|
-		panic!("Failed to serialize history for saving because: {}", error)))
-		.unwrap_or_else(|error| panic!("Failed to write save history to file because: {}", error))
+pub fn save_history(ctx: &mut ggez::Context, settings: &Settings, history: &History) -> ggez::GameResult {
+	write_atomic(&save_file(ctx, settings, &settings.save_path), &serialize(history)?)
+}
+
+/// Loads the data that persists across playthroughs from the path in the settings.
+pub fn load_persistent(ctx: &mut ggez::Context, settings: &Settings) -> ggez::GameResult<Persistent> {
+	toml::from_slice(&std::fs::read(save_file(ctx, settings, &settings.persistent_path))?).map_err(|error| {
+		let error = format!("Failed to load persistent data because: {}", error);
+		ggez::GameError::ResourceLoadError(error)
+	})
+}
+
+/// Saves the data that persists across playthroughs to the path specified in the settings.
+pub fn save_persistent(ctx: &mut ggez::Context, settings: &Settings, persistent: &Persistent) -> ggez::GameResult {
+	write_atomic(&save_file(ctx, settings, &settings.persistent_path), &serialize(persistent)?)
+}
+
+/// Loads the statistics of the choices that have been made from the path in the settings.
+pub fn load_choice_stats(ctx: &mut ggez::Context, settings: &Settings) -> ggez::GameResult<ChoiceStats> {
+	toml::from_slice(&std::fs::read(save_file(ctx, settings, &settings.stats_path))?).map_err(|error| {
+		let error = format!("Failed to load choice statistics because: {}", error);
+		ggez::GameError::ResourceLoadError(error)
+	})
+}
+
+/// Saves the statistics of the choices that have been made to the path specified in the settings.
+pub fn save_choice_stats(ctx: &mut ggez::Context, settings: &Settings, stats: &ChoiceStats) -> ggez::GameResult {
+	write_atomic(&save_file(ctx, settings, &settings.stats_path), &serialize(stats)?)
+}
+
+/// Finds a resource file on disk by searching the resource paths in the settings.
+pub fn resource_file(settings: &Settings, path: &str) -> Option<PathBuf> {
+	settings.resource_paths.iter().map(|directory| Path::new(directory)
+		.join(path.trim_start_matches('/'))).find(|path| path.exists())
+}
+
+/// Finds where a file is saved to on disk.
+/// Saves are placed in the `save_directory` of the settings or the user data directory.
+/// Saves in the user data directory are moved there from the user config directory
+/// if they were written before the save directory was configurable.
+pub fn save_file(ctx: &ggez::Context, settings: &Settings, path: &str) -> PathBuf {
+	if let Some(directory) = &settings.save_directory {
+		return resolve_save_file(directory, path);
+	}
+
+	let file = resolve_save_file(ggez::filesystem::user_data_dir(ctx), path);
+	let legacy = resolve_save_file(ggez::filesystem::user_config_dir(ctx), path);
+	if let Err(error) = migrate_save_file(&legacy, &file) {
+		eprintln!("Warning: Failed to move save from: {}, to: {}, because: {}",
+			legacy.display(), file.display(), error);
+	}
+	file
+}
+
+/// Moves a save from its legacy path to its current path.
+/// Nothing is moved if there is no legacy save or there is already a save at the current path.
+/// Returns whether the save was moved.
+pub fn migrate_save_file(legacy: &Path, path: &Path) -> ggez::GameResult<bool> {
+	if legacy == path || !legacy.is_file() || path.exists() {
+		return Ok(false);
+	}
+
+	write_atomic(path, &std::fs::read(legacy)?)?;
+	std::fs::remove_file(legacy)?;
+	Ok(true)
+}
+
+/// Joins a save path onto the save directory.
+/// Leading slashes are ignored so the save path is always inside the directory.
+pub fn resolve_save_file(directory: &Path, path: &str) -> PathBuf {
+	directory.join(path.trim_start_matches('/'))
+}
+
+/// Serializes data so that it can be saved.
+fn serialize<T: serde::Serialize>(value: &T) -> ggez::GameResult<Vec<u8>> {
+	toml::to_vec(value).map_err(|error| ggez::GameError::FilesystemError(
+		format!("Failed to serialize data for saving because: {}", error)))
 }
 
 /// Loads all resources that are referenced in a script.
 /// Ignores any resources that have already been loaded.
-pub fn load_resources(ctx: &mut ggez::Context, script: &mut Script) -> ggez::GameResult {
-	load_images(ctx, script)?;
-	load_audio(ctx, script)
+/// The script is validated before any resources are loaded.
+pub fn load_resources(ctx: &mut ggez::Context, script: &mut Script,
+                      settings: &Settings) -> ggez::GameResult {
+	script.validate(settings).map_err(ggez::GameError::ResourceLoadError)?;
+	load_images(ctx, script, settings)?;
+	load_audio(ctx, script, settings)?;
+	load_fonts(ctx, script)
 }
 
-/// Loads all the images that are referenced in a script.
-pub fn load_images(ctx: &mut ggez::Context, script: &mut Script) -> ggez::GameResult {
-	let Characters(characters) = &script.characters;
-	let paths = characters.values().flat_map(|states|
-		states.values()).map(|state| &state.image);
-	let paths = Iterator::chain(paths, script.commands.iter()
-		.filter_map(|command| match command {
-			Command::Stage(path) => Some(path),
-			_ => None,
-		}));
-
-	Ok(for path in paths {
-		if !script.images.contains_key(path) {
-			let image = graphics::Image::new(ctx, path)?;
-			script.images.insert(path.clone(), image);
+/// Loads all the fonts that are referenced in a script.
+pub fn load_fonts(ctx: &mut ggez::Context, script: &mut Script) -> ggez::GameResult {
+	let paths: Vec<_> = script.referenced_resources().filter(|resource|
+		resource.kind == ResourceKind::Font).map(|resource| normalise_path(resource.path)).collect();
+	paths.into_iter().try_for_each(|path| {
+		if let Entry::Vacant(entry) = script.fonts.entry(path) {
+			let font = graphics::Font::new(ctx, entry.key())?;
+			entry.insert(font);
 		}
+		Ok(())
 	})
 }
 
+/// Loads all the images that are referenced in a script.
+/// Images with identical contents share the same image if `dedupe_assets` is set.
+pub fn load_images(ctx: &mut ggez::Context, script: &mut Script,
+                   settings: &Settings) -> ggez::GameResult {
+	let paths: Vec<_> = script.referenced_resources().filter(|resource|
+		resource.kind == ResourceKind::Image).map(|resource| normalise_path(resource.path)).collect();
+
+	let mut contents = AssetContents::default();
+	for path in paths {
+		if let Entry::Vacant(entry) = script.images.entry(path) {
+			let path = entry.key();
+			let image = match settings.dedupe_assets {
+				false => graphics::Image::new(ctx, path)?,
+				true => contents.load(read_bytes(ctx, path)?, |bytes| image_from_bytes(ctx, bytes))?,
+			};
+			entry.insert(image);
+		}
+	}
+
+	let Characters(characters) = &script.characters;
+	characters.iter().flat_map(|(CharacterName(character), states)| states.iter()
+		.map(move |(StateName(state), value)| (character, state, value)))
+		.try_for_each(|(character, state, value)| {
+			let image = &script.images[&normalise_path(&value.image)];
+			value.validate_centre((image.width(), image.height())).map_err(|error|
+				ggez::GameError::ResourceLoadError(format!("Character: {}, state: {}, {}", character, state, error)))
+		})
+}
+
+/// Finds the paths of the image and overlay of a state.
+fn state_images(state: &CharacterState) -> Vec<PathBuf> {
+	std::iter::once(&state.image).chain(&state.overlay).map(normalise_path).collect()
+}
+
 /// Loads all the audio that is referenced in a script.
-pub fn load_audio(ctx: &mut ggez::Context, script: &mut Script) -> ggez::GameResult {
+/// Audio with identical contents share the same data if `dedupe_assets` is set.
+pub fn load_audio(ctx: &mut ggez::Context, script: &mut Script,
+                  settings: &Settings) -> ggez::GameResult {
+	let (spawn_sounds, sounds): (Vec<_>, Vec<_>) = script.referenced_resources()
+		.filter(|resource| resource.kind == ResourceKind::Music || resource.kind == ResourceKind::Sound)
+		.map(|resource| (resource.path.clone(), matches!(resource.source, ResourceSource::State(_, _))))
+		.partition(|(_, spawn_sound)| *spawn_sound);
+
 	let script_audio = &mut script.audio;
-	script.commands.iter().try_for_each(|command| match command {
-		Command::Music(path) | Command::Sound(path) => Ok({
-			if !script_audio.contains_key(path) {
-				let audio = ggez::audio::SoundData::new(ctx, path)?;
-				script_audio.insert(path.clone(), audio);
+	let mut contents = AssetContents::default();
+	let mut load = |ctx: &mut ggez::Context, path: &PathBuf| -> ggez::GameResult {
+		if let Entry::Vacant(entry) = script_audio.entry(normalise_path(path)) {
+			let bytes = read_bytes(ctx, entry.key())?;
+			entry.insert(match settings.dedupe_assets {
+				false => SoundData::from_bytes(&bytes),
+				true => contents.load(bytes, |bytes| Ok(SoundData::from_bytes(bytes)))?,
+			});
+		}
+		Ok(())
+	};
+
+	sounds.iter().try_for_each(|(path, _)| load(ctx, path))?;
+	for (path, _) in spawn_sounds {
+		load(ctx, &path).unwrap_or_else(|error| eprintln!("Warning: Failed to \
+			load spawn sound at path: {}, because: {}", path.display(), error));
+	}
+	Ok(())
+}
+
+/// Normalises a resource path so that equivalent paths share the same key.
+/// Current and parent directory components are resolved and the path
+/// is made relative to the root of the mounted resource paths.
+pub fn normalise_path<P: AsRef<Path>>(path: P) -> PathBuf {
+	let mut normal = PathBuf::from("/");
+	for component in path.as_ref().components() {
+		match component {
+			Component::Normal(component) => normal.push(component),
+			Component::ParentDir => { normal.pop(); }
+			Component::RootDir | Component::CurDir | Component::Prefix(_) => (),
+		}
+	}
+	normal
+}
+
+/// Reads a file from a given path as bytes.
+pub fn read_bytes<P: AsRef<Path>>(ctx: &mut ggez::Context, path: P) -> ggez::GameResult<Vec<u8>> {
+	let mut buffer = Vec::new();
+	ggez::filesystem::open(ctx, path)?.read_to_end(&mut buffer)?;
+	Ok(buffer)
+}
+
+/// Writes to a temporary file and then renames it over the destination
+/// so that the destination is never partially written.
+/// The directory of the destination is created if it does not exist.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> ggez::GameResult {
+	if let Some(directory) = path.parent() {
+		std::fs::create_dir_all(directory)?;
+	}
+
+	let mut temporary = path.as_os_str().to_owned();
+	temporary.push(".tmp");
+	std::fs::write(&temporary, bytes)?;
+	Ok(std::fs::rename(&temporary, path)?)
+}
+
+/// Decodes an image from the contents of an image file.
+fn image_from_bytes(ctx: &mut ggez::Context, bytes: &[u8]) -> ggez::GameResult<graphics::Image> {
+	let image = image::load_from_memory(bytes)?.to_rgba();
+	let (width, height) = image.dimensions();
+	graphics::Image::from_rgba8(ctx, width as u16, height as u16, &image)
+}
+
+/// Assets that have been loaded along with the contents they were loaded from.
+/// Contents with the same hash are compared so only identical assets are shared.
+struct AssetContents<T>(HashMap<u64, Vec<(Vec<u8>, T)>>);
+
+impl<T> Default for AssetContents<T> {
+	fn default() -> Self {
+		AssetContents(HashMap::new())
+	}
+}
+
+impl<T: Clone> AssetContents<T> {
+	/// Finds the asset loaded from identical contents or otherwise loads it from the contents.
+	fn load<F>(&mut self, bytes: Vec<u8>, load: F) -> ggez::GameResult<T>
+		where F: FnOnce(&[u8]) -> ggez::GameResult<T> {
+		let assets = self.0.entry(content_hash(&bytes)).or_default();
+		if let Some((_, asset)) = assets.iter().find(|(contents, _)| contents == &bytes) {
+			return Ok(asset.clone());
+		}
+
+		let asset = load(&bytes)?;
+		assets.push((bytes, asset.clone()));
+		Ok(asset)
+	}
+}
+
+/// Hashes contents with 64 bit FNV-1a, which gives the same hash across builds and Rust releases.
+fn content_hash(bytes: &[u8]) -> u64 {
+	bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::character::InstanceName;
+
+	fn scripted_state(script: &str) -> GameState {
+		GameState {
+			script: crate::parser::parse(script).unwrap(),
+			settings: Settings::default(),
+			history: History::default(),
+			state: ScriptState::default(),
+			render: Render::default(),
+			reload: false,
+			characters_modified: (None, None),
+			characters_notice: None,
+			auto_elapsed: 0.0,
+			ui_sounds: (None, None),
+			cursor: None,
+			yield_callback: None,
+			replay: Vec::new(),
+			selected_branch: None,
+			choice_stats: ChoiceStats::default(),
+			unsaved_choices: 0,
+			quit_requested: false,
+		}
+	}
+
+	#[test]
+	fn query_state() {
+		let mut game = scripted_state("flag seen\nlabel start\npause\n\"Hello\"\n");
+		game.state.persistent.flags.insert(FlagName("persistent".to_owned()));
+		assert!(matches!(game.current_command(), Some(Command::Flag(_))));
+		assert!(!game.is_awaiting_input());
+
+		game.script[&Target(0)].execute_state(&mut game.state, &game.script);
+		game.state.target = Target(1);
+		assert_eq!(game.last_label(), Some(&Label::from("start")));
+		assert!(game.is_awaiting_input());
+		let mut flags: Vec<_> = game.flags().map(|FlagName(flag)| flag.as_str()).collect();
+		flags.sort();
+		assert_eq!(flags, vec!["persistent", "seen"]);
+
+		game.state.target = Target(2);
+		assert!(game.is_awaiting_input());
+		assert!(game.stage().get(&InstanceName::from("Girl")).is_none());
+		assert_eq!(game.stage().iter().count(), 0);
+
+		game.state.target = Target(3);
+		assert!(game.current_command().is_none());
+	}
+
+	#[test]
+	fn yield_frames() {
+		let mut game = scripted_state("yield\n\"Hello\"\n");
+		assert_eq!(game.current_command(), Some(&Command::Yield));
+		game.state.yielded = true;
+
+		assert!(!game.yield_frame(GameState::keep_yielding));
+		assert!(game.state.yielded);
+		assert!(game.yield_frame(|_| ()));
+		assert!(!game.state.yielded);
+		assert!(!game.yield_frame(|_| panic!("The script is not stopped by a yield")));
+	}
+
+	#[test]
+	fn script_cache() {
+		let source = "label start\nspawn \"Girl\" \"Happy\" (1, 2)\n\"Girl\" \"Hello\"\njump start\n";
+		let script = crate::parser::parse(source).unwrap();
+		let bytes = write_script_cache(&script, source).unwrap();
+
+		let cached = read_script_cache(&bytes, source).unwrap();
+		assert_eq!(format!("{:?}", cached.commands), format!("{:?}", script.commands));
+		assert_eq!(cached.labels.keys().collect::<Vec<_>>(), script.labels.keys().collect::<Vec<_>>());
+		assert!(read_script_cache(&bytes, "label start\n").is_none());
+
+		let mut cache: ScriptCache = serde_cbor::from_slice(&bytes).unwrap();
+		cache.version += 1;
+		assert!(read_script_cache(&serde_cbor::to_vec(&cache).unwrap(), source).is_none());
+		cache.version = SCRIPT_CACHE_VERSION;
+		cache.source_length += 1;
+		assert!(read_script_cache(&serde_cbor::to_vec(&cache).unwrap(), source).is_none());
+	}
+
+	#[test]
+	fn content_hash_stable() {
+		assert_eq!(content_hash(b""), 0xcbf2_9ce4_8422_2325);
+		assert_eq!(content_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+		assert_eq!(content_hash(b"foobar"), 0x8594_4171_f739_67e8);
+	}
+
+	#[test]
+	fn normalise_current_directory() {
+		assert_eq!(normalise_path("./girl.png"), normalise_path("girl.png"));
+		assert_eq!(normalise_path("/./girl.png"), PathBuf::from("/girl.png"));
+		assert_eq!(normalise_path("girl.png"), PathBuf::from("/girl.png"));
+	}
+
+	#[test]
+	fn normalise_parent_directory() {
+		assert_eq!(normalise_path("/images/../girl.png"), PathBuf::from("/girl.png"));
+		assert_eq!(normalise_path("/../girl.png"), PathBuf::from("/girl.png"));
+		assert_eq!(normalise_path("/images//girl.png"), PathBuf::from("/images/girl.png"));
+	}
+
+	#[test]
+	fn normalise_case_difference() {
+		// Resource paths may be mounted on a case sensitive filesystem.
+		assert_ne!(normalise_path("/Girl.png"), normalise_path("/girl.png"));
+		assert_eq!(normalise_path("./Girl.png"), PathBuf::from("/Girl.png"));
+	}
+
+	#[test]
+	fn transform_scaled() {
+		let screen = graphics::Rect::new(0.0, 0.0, 640.0, 480.0);
+		assert_eq!(transform_point(screen, (640.0, 480.0), 1.0, (320.0, 240.0)), (320.0, 240.0));
+		assert_eq!(transform_point(screen, (1280.0, 960.0), 2.0, (320.0, 240.0)), (320.0, 240.0));
+		assert_eq!(transform_point(screen, (1280.0, 960.0), 2.0, (640.0, 480.0)), (640.0, 480.0));
+
+		let screen = graphics::Rect::new(-80.0, 0.0, 800.0, 480.0);
+		assert_eq!(transform_point(screen, (1600.0, 960.0), 2.0, (400.0, 240.0)), (320.0, 240.0));
+	}
+
+	#[test]
+	fn transform_inverse() {
+		let settings = Settings::default();
+		for &window in [(1280.0, 480.0), (640.0, 960.0)].iter() {
+			let screen = screen_coordinates(window, &settings);
+			let [first, second] = letterbox_bars(screen, &settings);
+			assert_eq!(first.w * first.h, second.w * second.h);
+			assert!(first.w * first.h > 0.0);
+
+			for (x, y) in (0..=4).flat_map(|x| (0..=4).map(move |y| (x as f32 * 160.0, y as f32 * 120.0))) {
+				let window_point = inverse_transform_point(screen, window, 1.0, (x, y));
+				let (screen_x, screen_y) = transform_point(screen, window, 1.0, window_point);
+				assert!((screen_x - x).abs() < 0.001 && (screen_y - y).abs() < 0.001);
 			}
-		}),
-		_ => Ok(()),
-	})
+		}
+
+		let screen = screen_coordinates((1280.0, 480.0), &settings);
+		assert_eq!(inverse_transform_point(screen, (1280.0, 480.0), 1.0, (0.0, 0.0)), (320.0, 0.0));
+	}
+
+	#[test]
+	fn start_at_chapter() {
+		let mut game = scripted_state("\"Prologue\"\nlabel chapter-two\n\"Chapter two\"\n");
+		game.start_at(&Label::from("chapter-two")).unwrap();
+		assert_eq!(game.state.next_target, Some(Target(1)));
+		assert!(game.start_at(&Label::from("missing")).is_err());
+		assert_eq!(game.state.next_target, Some(Target(1)));
+	}
+
+	#[test]
+	fn parse_script_errors() {
+		assert!(parse_script(Path::new("/script.txt"), "flag ready").is_ok());
+		match parse_script(Path::new("/script.txt"), "flag ready\n\"Unterminated") {
+			Err(ggez::GameError::ResourceLoadError(error)) => assert!(error.contains("/script.txt")),
+			result => panic!("Unexpected result: {:?}", result.map(|_| ())),
+		}
+	}
+
+	#[test]
+	fn save_file_in_directory() {
+		let directory = Path::new("/home/player/.local/share/kanna");
+		assert_eq!(resolve_save_file(directory, "/game.save"), directory.join("game.save"));
+		assert_eq!(resolve_save_file(directory, "slots/1.save"), directory.join("slots/1.save"));
+		assert_eq!(resolve_save_file(directory, &slot_path(2)), directory.join("slots/2.save"));
+	}
+
+	#[test]
+	fn branch_cycling() {
+		assert_eq!(cycle_index(None, 3, true), 0);
+		assert_eq!(cycle_index(None, 3, false), 2);
+		assert_eq!(cycle_index(Some(2), 3, true), 0);
+		assert_eq!(cycle_index(Some(0), 3, false), 2);
+		assert_eq!(cycle_index(Some(1), 3, false), 0);
+	}
+
+	#[test]
+	fn thumbnail_round_trip() {
+		let screenshot = image::RgbaImage::from_pixel(640, 480, image::Rgba([255, 0, 0, 255]));
+		let settings = Settings { thumbnail_width: 64, thumbnail_height: 48, ..Settings::default() };
+		let history = History { thumbnail: Some(encode_thumbnail(&screenshot, &settings).unwrap()), ..History::default() };
+		let history = History::parse(&serialize(&history).unwrap()).unwrap();
+		let thumbnail = decode_thumbnail(&history.thumbnail.unwrap()).unwrap();
+		assert_eq!(thumbnail.dimensions(), (64, 48));
+		assert_eq!(thumbnail.get_pixel(32, 24), &image::Rgba([255, 0, 0, 255]));
+		assert!(decode_thumbnail(b"not an image").is_err());
+	}
+
+	#[test]
+	fn write_atomic_creates_directory() {
+		let directory = std::env::temp_dir().join(format!("kanna-save-{}", std::process::id()));
+		let path = resolve_save_file(&directory, "/saves/game.save");
+		write_atomic(&path, b"execution_count = 1").unwrap();
+		write_atomic(&path, b"execution_count = 2").unwrap();
+		assert_eq!(std::fs::read(&path).unwrap(), b"execution_count = 2");
+		assert!(!directory.join("saves/game.save.tmp").exists());
+		std::fs::remove_dir_all(directory).unwrap();
+	}
+
+	#[test]
+	fn migrate_legacy_save() {
+		let directory = std::env::temp_dir().join(format!("kanna-migrate-{}", std::process::id()));
+		let legacy = resolve_save_file(&directory.join("config"), "/game.save");
+		let path = resolve_save_file(&directory.join("data"), "/game.save");
+		assert!(!migrate_save_file(&legacy, &path).unwrap());
+
+		let fixture = include_bytes!("fixtures/history_v0.toml");
+		write_atomic(&legacy, fixture).unwrap();
+		assert!(migrate_save_file(&legacy, &path).unwrap());
+		assert!(!legacy.exists());
+		let history = History::parse(&std::fs::read(&path).unwrap()).unwrap();
+		assert_eq!(history.divergences, vec![Label::from("bruh-moment-sigh")]);
+		assert_eq!(history.execution_count, 12);
+
+		write_atomic(&legacy, b"execution_count = 1").unwrap();
+		assert!(!migrate_save_file(&legacy, &path).unwrap());
+		assert_eq!(std::fs::read(&path).unwrap(), &fixture[..]);
+		std::fs::remove_dir_all(directory).unwrap();
+	}
+
+	#[test]
+	#[cfg(unix)]
+	fn serialize_invalid_path() {
+		use std::os::unix::ffi::OsStrExt;
+		let background = Path::new(std::ffi::OsStr::from_bytes(b"/background\xff.jpg"));
+		let snapshot = Snapshot { background: Some(background.to_owned()), ..Snapshot::default() };
+		let history = History { snapshot: Some(snapshot), ..History::default() };
+		assert!(serialize(&history).is_err());
+		assert!(serialize(&History::default()).is_ok());
+	}
+
+	#[test]
+	fn asset_contents() {
+		let mut contents = AssetContents::default();
+		let mut loads = 0;
+		for bytes in &[&b"same"[..], b"same", b"other"] {
+			contents.load(bytes.to_vec(), |_| { loads += 1; Ok(loads) }).unwrap();
+		}
+		assert_eq!(loads, 2);
+		assert_eq!(contents.load(b"same".to_vec(), |_| Ok(0)).unwrap(), 1);
+
+		let mut contents = AssetContents::default();
+		contents.0.insert(content_hash(b"new"), vec![(b"old".to_vec(), 0)]);
+		assert_eq!(contents.load(b"new".to_vec(), |_| Ok(1)).unwrap(), 1);
+		assert_eq!(contents.load(b"old".to_vec(), |_| Ok(2)).unwrap(), 2);
+		assert_eq!(contents.load(b"new".to_vec(), |_| Ok(3)).unwrap(), 1);
+	}
 }
@@ -1,18 +1,338 @@
+use std::collections::VecDeque;
 use std::ops::{Deref, DerefMut, Range};
+use std::time::{Duration, Instant};
 
 use ggez::graphics::{self, Image};
 
-use crate::character::Stage;
-use crate::Label;
+use crate::character::{CharacterName, InstanceName, SpriteBatches, Stage};
+use crate::{AdvanceClickArea, Label, LetterboxStyle, NameAlignment, Settings};
+
+pub mod layout;
 
 #[derive(Debug, Default)]
 pub struct Render {
 	pub background: Option<Image>,
 	pub stage: Stage,
+	/// Order that instances are drawn in.
+	/// Instances that are not listed are drawn after the listed instances.
+	pub draw_order: Option<Vec<InstanceName>>,
 	pub character: Option<TextBox>,
+	/// Character that is speaking the dialogue, which the name box is placed over.
+	pub speaker: Option<CharacterName>,
 	pub text: Option<TextBox>,
 	pub branches: Vec<(Button, Label)>,
+	/// Distance that the branch buttons have been scrolled from their layout positions.
+	pub branch_scroll: f32,
+	/// Transition between scenes that is drawn over everything else.
+	pub transition: Option<TransitionState>,
+	/// Buttons shown along the bottom of the text box.
+	pub quick_menu: QuickMenu,
+	/// Message shown to the player until they click.
+	pub notice: Option<TextBox>,
+	pub performance: PerformanceOverlay,
 	pub shadow_bars: [graphics::Rect; 2],
+	/// Offset of the camera that is subtracted from the background and the stage.
+	/// Text boxes and buttons are not affected by the camera.
+	pub camera_offset: (f32, f32),
+	/// Movement of the camera that is in progress.
+	pub camera: Option<CameraAnimation>,
+	/// Sprite batches reused between frames when the stage is drawn.
+	pub sprite_batches: SpriteBatches,
+}
+
+/// A movement of the camera towards a destination offset.
+#[derive(Debug)]
+pub struct CameraAnimation {
+	pub destination: (f32, f32),
+	/// Time left until the camera reaches the destination in milliseconds.
+	pub remaining_ms: f32,
+}
+
+impl CameraAnimation {
+	/// Moves the offset towards the destination by the amount of milliseconds that have passed.
+	/// Returns true once the destination has been reached.
+	pub fn update(&mut self, (offset_x, offset_y): &mut (f32, f32), delta: f32) -> bool {
+		let progress = if self.remaining_ms > delta { delta / self.remaining_ms } else { 1.0 };
+		let (destination_x, destination_y) = self.destination;
+		*offset_x += (destination_x - *offset_x) * progress;
+		*offset_y += (destination_y - *offset_y) * progress;
+		self.remaining_ms -= delta;
+		self.remaining_ms <= 0.0
+	}
+}
+
+/// The result of the player clicking on the screen.
+#[derive(Debug, PartialEq)]
+pub enum Click {
+	/// Continues the script.
+	Advance,
+	/// Chooses the branch that jumps to the label.
+	Choose(Label),
+	/// Performs the action of a quick menu button.
+	Quick(QuickAction),
+	/// Does nothing.
+	Ignore,
+}
+
+impl Render {
+	/// Distance that the branch buttons are scrolled by for each step of the mouse wheel.
+	pub const SCROLL_DISTANCE: f32 = 24.0;
+
+	/// Decides what a click at a point in screen coordinates does.
+	/// While branches are displayed one of them must be chosen,
+	/// so clicking between the buttons is ignored instead of advancing the script.
+	/// Otherwise the click only advances if it is inside the advance click area.
+	pub fn click(&self, (x, y): (f32, f32), settings: &Settings) -> Click {
+		if self.transition.as_ref().map(TransitionState::is_covering).unwrap_or(false) {
+			return Click::Ignore;
+		}
+
+		if self.text_visible() {
+			if let Some(action) = self.quick_menu.click((x, y)) {
+				return Click::Quick(action);
+			}
+		}
+
+		if self.branches.is_empty() {
+			let text = self.text.as_ref().filter(|text| text.visible);
+			let inside = match settings.advance_click_area {
+				AdvanceClickArea::Anywhere => true,
+				AdvanceClickArea::TextBoxOnly => text.map(|text|
+					text.rectangle().contains([x, y])).unwrap_or(true),
+				AdvanceClickArea::BottomHalf => y >= settings.height / 2.0,
+			};
+
+			return if inside { Click::Advance } else { Click::Ignore };
+		}
+
+		self.branches.iter().find(|(button, _)| button.rectangle().contains([x, y]))
+			.map(|(_, label)| Click::Choose(label.clone())).unwrap_or(Click::Ignore)
+	}
+
+	/// Moves the branch buttons vertically when they do not all fit in the view.
+	pub fn scroll_branches(&mut self, distance: f32, settings: &Settings) {
+		let rectangles: Vec<_> = self.branches.iter().map(|(button, _)| button.rectangle()).collect();
+		let distance = layout::branch_scroll(&rectangles, distance, settings);
+		self.branches.iter_mut().for_each(|(button, _)| button.text.position.1 += distance);
+		self.branch_scroll += distance;
+	}
+
+	/// Places the branch buttons at their layout positions, keeping as much of
+	/// the distance they were scrolled by as still fits in the view.
+	pub fn relayout_branches(&mut self, settings: &Settings) {
+		let margin = layout::margin(settings);
+		let buttons = layout::branch_buttons(settings, self.branches.len());
+		self.branch_scroll = layout::branch_scroll(&buttons, self.branch_scroll, settings);
+		for ((button, _), mut rectangle) in self.branches.iter_mut().zip(buttons) {
+			rectangle.y += self.branch_scroll;
+			button.text.set_rectangle(rectangle);
+			button.text.padding = margin;
+			button.text.text_scale = settings.ui_scale;
+		}
+	}
+
+	/// Recomputes the positions and sizes of the text boxes and buttons from the settings.
+	/// Their text, how much of it is displayed, and whether they are visible are kept.
+	/// This is called when the window is resized so that layouts do not depend on stale values.
+	pub fn relayout(&mut self, ctx: &mut ggez::Context, settings: &Settings) {
+		let margin = layout::margin(settings);
+		let (text_box, name_box) = layout::dialogue_boxes(settings);
+		let scale = |text: &mut TextBox, rectangle: graphics::Rect| {
+			text.set_rectangle(rectangle);
+			text.padding = margin;
+			text.text_scale = settings.ui_scale;
+		};
+
+		if let Some(text) = &mut self.text {
+			scale(text, text_box);
+			self.quick_menu.layout(text.rectangle(), settings);
+		}
+
+		match (&mut self.character, name_box) {
+			(Some(name), Some(name_box)) => {
+				scale(name, name_box);
+				self.fit_name_box(ctx, settings);
+			}
+			(character, None) => *character = None,
+			_ => (),
+		}
+
+		self.relayout_branches(settings);
+		if let Some(notice) = &mut self.notice {
+			scale(notice, layout::notice(settings));
+		}
+	}
+
+	/// Sizes the name box to fit the name and places it over the speaker if the settings require it.
+	/// The name box keeps its position and height from the dialogue layout.
+	pub fn fit_name_box(&mut self, ctx: &mut ggez::Context, settings: &Settings) {
+		let speaker = match settings.character_name_alignment {
+			NameAlignment::Speaker => self.speaker.as_ref().and_then(|speaker| self.stage.speaker(speaker))
+				.map(|instance| instance.position.0 - self.camera_offset.0),
+			NameAlignment::TextBox => None,
+		};
+
+		if let (Some(name), (_, Some(name_box))) = (&mut self.character, layout::dialogue_boxes(settings)) {
+			let width = name.text_width(ctx) + 2.0 * name.padding;
+			name.set_rectangle(layout::fit_name_box(name_box, width, speaker, settings));
+		}
+	}
+
+	/// Checks whether any instance is animating or the dialogue is still being revealed.
+	pub fn is_busy(&self) -> bool {
+		self.stage.any_animating() || self.text.as_ref().map(|text| !text.is_finished()).unwrap_or(false)
+	}
+
+	/// Whether the dialogue text box is displayed.
+	pub fn text_visible(&self) -> bool {
+		self.text.as_ref().map(|text| text.visible).unwrap_or(false)
+	}
+
+	/// Shows a message to the player in the centre of the screen.
+	pub fn notify(&mut self, message: String, settings: &Settings) {
+		let rectangle = layout::notice(settings);
+		let text = RenderText::new(message, settings.foreground_colour);
+		self.notice = Some(TextBox::from_rectangle(text, rectangle, settings.background_colour)
+			.padding(layout::margin(settings)).alignment(graphics::Align::Center).text_scale(settings.ui_scale));
+	}
+
+	/// Removes the notice if it still shows the message.
+	pub fn clear_notice(&mut self, message: &str) {
+		if self.notice.as_ref().map(|notice| notice.text.string == message).unwrap_or(false) {
+			self.notice = None;
+		}
+	}
+
+	/// Progresses the fades of the dialogue and name text boxes.
+	pub fn tick_text_fade(&mut self, ctx: &mut ggez::Context) {
+		let delta = ggez::timer::delta(ctx).as_secs_f32() * 1_000.0;
+		self.character.iter_mut().chain(self.text.iter_mut())
+			.for_each(|text| text.tick_fade(delta));
+	}
+
+	/// Moves the camera by an offset over an amount of milliseconds.
+	/// Any movement that is in progress is finished first.
+	pub fn pan(&mut self, (offset_x, offset_y): (f32, f32), duration: f32) {
+		self.finish_camera();
+		let (camera_x, camera_y) = self.camera_offset;
+		let destination = (camera_x + offset_x, camera_y + offset_y);
+		match duration > 0.0 {
+			true => self.camera = Some(CameraAnimation { destination, remaining_ms: duration }),
+			false => self.camera_offset = destination,
+		}
+	}
+
+	/// Progresses the movement of the camera.
+	pub fn tick_camera(&mut self, ctx: &mut ggez::Context) {
+		let delta = ggez::timer::delta(ctx).as_secs_f32() * 1_000.0;
+		if let Some(camera) = &mut self.camera {
+			if camera.update(&mut self.camera_offset, delta) {
+				self.camera = None;
+			}
+		}
+	}
+
+	/// Moves the camera to its destination immediately.
+	pub fn finish_camera(&mut self) {
+		if let Some(camera) = self.camera.take() {
+			self.camera_offset = camera.destination;
+		}
+	}
+
+	/// Checks whether the stage, the camera and any transition have stopped animating.
+	/// A transition that is covering the screen is waiting for the script, so it is
+	/// only running once it fades out to reveal the next scene.
+	pub fn animations_finished(&self) -> bool {
+		!self.stage.any_animating() && self.camera.is_none() && self.transition.as_ref()
+			.map(|transition| transition.is_covering() || transition.is_finished()).unwrap_or(true)
+	}
+
+	/// Finishes the animations on the stage, the movement of the camera
+	/// and any transition that is fading out.
+	pub fn finish_animations(&mut self) {
+		self.stage.finish_animation();
+		self.finish_camera();
+		if self.transition.as_ref().map(|transition| !transition.is_covering()).unwrap_or(false) {
+			self.transition = None;
+		}
+	}
+
+	/// Draws all the elements of the game in order.
+	pub fn draw(&mut self, ctx: &mut ggez::Context, settings: &Settings) -> ggez::GameResult {
+		let (camera_x, camera_y) = self.camera_offset;
+		self.background.as_ref().map(|image| graphics::draw(ctx,
+			image, graphics::DrawParam::new().dest([-camera_x, -camera_y]))).transpose()?;
+		self.stage.draw(ctx, settings, self.camera_offset, self.draw_order.as_deref(), &mut self.sprite_batches)?;
+		self.character.as_ref().map(|text| text.draw(ctx)).transpose()?;
+		self.text.as_ref().map(|text| text.draw(ctx)).transpose()?;
+		if self.text_visible() {
+			self.quick_menu.draw(ctx)?;
+		}
+		self.branches.iter().try_for_each(|(button, _)| button.draw(ctx))?;
+		self.transition.as_ref().map(|transition| transition.draw(ctx, settings)).transpose()?;
+		self.notice.as_ref().map(|notice| notice.draw(ctx)).transpose()?;
+		self.performance.draw(ctx)?;
+		self.shadow_bars.iter().enumerate().filter(|(_, bar)| bar.w > 0.0 && bar.h > 0.0)
+			.try_for_each(|(index, bar)| {
+				let bar = shadow_bar(ctx, *bar, index == 0, settings)?;
+				graphics::draw(ctx, &bar, graphics::DrawParam::new())
+			})
+	}
+}
+
+/// Creates the mesh of a bar that fills the window outside of the view.
+/// The first bar is to the left or above the view and the second bar is to the right or below.
+fn shadow_bar(ctx: &mut ggez::Context, bar: graphics::Rect, first: bool,
+              settings: &Settings) -> ggez::GameResult<graphics::Mesh> {
+	let fill = graphics::DrawMode::fill();
+	match settings.letterbox_style {
+		LetterboxStyle::Rectangle => graphics::Mesh::new_rectangle(ctx, fill, bar, settings.letterbox_colour.into()),
+		LetterboxStyle::Rounded(radius) => graphics::Mesh::new_polygon(ctx, fill,
+			&rounded_rectangle(bar, radius), settings.letterbox_colour.into()),
+		LetterboxStyle::Gradient(window, view) => {
+			let (window, view) = (linear_colour(window), linear_colour(view));
+			let (start, end) = if first { (window, view) } else { (view, window) };
+			let [top_left, top_right, bottom_right, bottom_left] = match bar.h >= settings.height {
+				true => [start, end, end, start],
+				false => [start, start, end, end],
+			};
+
+			let vertex = |x, y, color| graphics::Vertex { pos: [x, y], uv: [0.0, 0.0], color };
+			let vertices = [
+				vertex(bar.x, bar.y, top_left),
+				vertex(bar.x + bar.w, bar.y, top_right),
+				vertex(bar.x + bar.w, bar.y + bar.h, bottom_right),
+				vertex(bar.x, bar.y + bar.h, bottom_left),
+			];
+			graphics::Mesh::from_raw(ctx, &vertices, &[0, 1, 2, 0, 2, 3], None)
+		}
+	}
+}
+
+/// Finds the points around a rectangle with corners rounded by a radius in pixels.
+fn rounded_rectangle(rectangle: graphics::Rect, radius: f32) -> Vec<[f32; 2]> {
+	const CORNER_SEGMENTS: usize = 8;
+	let radius = radius.max(0.0).min(rectangle.w / 2.0).min(rectangle.h / 2.0);
+	let corners = [
+		(rectangle.x + rectangle.w - radius, rectangle.y + rectangle.h - radius),
+		(rectangle.x + radius, rectangle.y + rectangle.h - radius),
+		(rectangle.x + radius, rectangle.y + radius),
+		(rectangle.x + rectangle.w - radius, rectangle.y + radius),
+	];
+
+	corners.iter().enumerate().flat_map(|(corner, (x, y))| (0..=CORNER_SEGMENTS).map(move |segment| {
+		let angle = (corner as f32 + segment as f32 / CORNER_SEGMENTS as f32) * std::f32::consts::FRAC_PI_2;
+		[x + radius * angle.cos(), y + radius * angle.sin()]
+	})).collect()
+}
+
+/// Converts a colour from the sRGB colour space into the linear colour space used by vertices.
+fn linear_colour([red, green, blue, alpha]: [f32; 4]) -> [f32; 4] {
+	let linear = |component: f32| match component <= 0.04045 {
+		true => component / 12.92,
+		false => ((component + 0.055) / 1.055).powf(2.4),
+	};
+	[linear(red), linear(green), linear(blue), alpha]
 }
 
 #[derive(Debug)]
@@ -20,26 +340,40 @@ pub struct RenderText {
 	pub string: String,
 	pub slice: Range<usize>,
 	pub colour: [f32; 4],
+	/// Number of the most recently displayed characters that fade in.
+	pub fade_chars: usize,
 }
 
 impl RenderText {
 	/// Creates a `RenderText` with all characters initially displayed.
 	pub fn new(string: String, colour: [f32; 4]) -> Self {
 		let slice = Range { start: 0, end: string.len() };
-		RenderText { string, slice, colour }
+		RenderText { string, slice, colour, fade_chars: 0 }
 	}
 
 	/// Creates a `RenderText` with no characters initially displayed.
 	pub fn empty(string: String, colour: [f32; 4]) -> Self {
 		let slice = Range { start: 0, end: 0 };
-		RenderText { string, slice, colour }
+		RenderText { string, slice, colour, fade_chars: 0 }
+	}
+
+	/// Makes the most recently displayed characters more transparent the more recently they were displayed.
+	pub fn with_colour_fade(mut self, fade_chars: usize) -> Self {
+		self.fade_chars = fade_chars;
+		self
+	}
+
+	/// The colour of the text with its opacity multiplied by how far it has faded in between zero and one.
+	pub fn colour_at_progress(&self, progress: f32) -> [f32; 4] {
+		let [red, green, blue, alpha] = self.colour;
+		[red, green, blue, alpha * progress.clamp(0.0, 1.0)]
 	}
 
 	/// Adds an additional character to be rendered.
 	/// Does nothing if the end of the string is already rendered.
 	pub fn step(&mut self) {
-		self.string[self.slice.end..].char_indices().skip(1)
-			.next().map(|(index, _)| self.slice.end += index)
+		self.string[self.slice.end..].char_indices().nth(1)
+			.map(|(index, _)| self.slice.end += index)
 			.unwrap_or_else(|| self.finish());
 	}
 
@@ -53,9 +387,34 @@ impl RenderText {
 		self.slice.end == self.string.len()
 	}
 
-	pub fn fragment(&self) -> graphics::TextFragment {
-		let string = self.string[self.slice.clone()].to_owned();
-		graphics::TextFragment::new(string).color(self.colour.into())
+	/// Splits the string into the displayed characters and the remaining characters.
+	/// Together the sections always form the whole string, so the text is wrapped
+	/// at the same places while it is revealed as it is once it is finished.
+	pub fn sections(&self) -> (&str, &str) {
+		(&self.string[self.slice.clone()], &self.string[self.slice.end..])
+	}
+
+	/// Creates fragments of the displayed characters and of the remaining characters.
+	/// The remaining characters are transparent so that the text is laid out as if it were finished,
+	/// which stops words from moving between lines and aligned text from moving as characters are added.
+	/// Each of the fading characters has its own fragment, with the newest being the most transparent.
+	pub fn fragments(&self) -> Vec<graphics::TextFragment> {
+		let (shown, hidden) = self.sections();
+		let fading = match self.is_finished() {
+			true => 0,
+			false => shown.chars().count().min(self.fade_chars),
+		};
+		let split = fading.checked_sub(1).and_then(|last| shown.char_indices().rev().nth(last))
+			.map(|(index, _)| index).unwrap_or(shown.len());
+		let (opaque, faded) = shown.split_at(split);
+
+		let mut fragments = vec![graphics::TextFragment::new(opaque).color(self.colour.into())];
+		fragments.extend(faded.chars().enumerate().map(|(index, character)| {
+			let progress = (fading - index) as f32 / (fading + 1) as f32;
+			graphics::TextFragment::new(character).color(self.colour_at_progress(progress).into())
+		}));
+		fragments.push(graphics::TextFragment::new(hidden).color(self.colour_at_progress(0.0).into()));
+		fragments
 	}
 }
 
@@ -67,11 +426,42 @@ pub struct TextBox {
 	pub colour: [f32; 4],
 	pub padding: f32,
 	pub alignment: graphics::Align,
+	/// Font and size of the text, the default font is used if this is not set.
+	pub font: Option<(graphics::Font, f32)>,
+	/// Amount the size of the text is multiplied by.
+	pub text_scale: f32,
+	/// Greatest number of lines of text that are drawn, all lines that fit are drawn if this is not set.
+	pub max_lines: Option<usize>,
+	/// Whether the text box is drawn.
+	pub visible: bool,
+	/// Fade of the text box that is in progress.
+	pub fade: Option<TextFade>,
+}
+
+/// A fade that changes the opacity of a text box and its text over time.
+#[derive(Debug)]
+pub struct TextFade {
+	/// Opacity of the box and the text when fully shown.
+	pub alphas: (f32, f32),
+	/// Whether the text box is fading in instead of out.
+	pub fade_in: bool,
+	/// Time spent fading in milliseconds.
+	pub elapsed: f32,
+	/// Time taken to fade in milliseconds.
+	pub duration: f32,
 }
 
 impl TextBox {
+	/// Text added to the end of a page of dialogue when there are more pages after it.
+	pub const MORE_INDICATOR: &'static str = "[more]";
+
 	pub fn new(text: RenderText, position: (f32, f32), size: (f32, f32), colour: [f32; 4]) -> Self {
-		TextBox { text, position, size, colour, padding: 0.0, alignment: graphics::Align::Left }
+		TextBox { text, position, size, colour, padding: 0.0, alignment: graphics::Align::Left, font: None, text_scale: 1.0, max_lines: None, visible: true, fade: None }
+	}
+
+	/// Creates a text box that covers a rectangle.
+	pub fn from_rectangle(text: RenderText, rectangle: graphics::Rect, colour: [f32; 4]) -> Self {
+		Self::new(text, (rectangle.x, rectangle.y), (rectangle.w, rectangle.h), colour)
 	}
 
 	pub fn padding(mut self, padding: f32) -> Self {
@@ -84,17 +474,160 @@ impl TextBox {
 		self
 	}
 
+	pub fn font(mut self, font: Option<(graphics::Font, f32)>) -> Self {
+		self.font = font;
+		self
+	}
+
+	pub fn text_scale(mut self, text_scale: f32) -> Self {
+		self.text_scale = text_scale;
+		self
+	}
+
+	pub fn max_lines(mut self, max_lines: Option<usize>) -> Self {
+		self.max_lines = max_lines;
+		self
+	}
+
+	/// Shows the text box if it is hidden and hides it otherwise.
+	pub fn toggle_visible(&mut self) {
+		self.finish_fade();
+		self.visible = !self.visible;
+	}
+
+	/// Makes the text box visible and fades it in over an amount of milliseconds.
+	pub fn fade_in(&mut self, duration: f32) {
+		self.finish_fade();
+		self.visible = true;
+		self.fade = Some(TextFade { alphas: (self.colour[3], self.text.colour[3]), fade_in: true, elapsed: 0.0, duration });
+		self.apply_fade();
+	}
+
+	/// Fades the text box out over an amount of milliseconds and then hides it.
+	pub fn fade_out(&mut self, duration: f32) {
+		self.finish_fade();
+		if self.visible {
+			self.fade = Some(TextFade { alphas: (self.colour[3], self.text.colour[3]), fade_in: false, elapsed: 0.0, duration });
+			self.apply_fade();
+		}
+	}
+
+	/// Progresses any fade by an amount of milliseconds.
+	pub fn tick_fade(&mut self, delta: f32) {
+		if let Some(fade) = &mut self.fade {
+			fade.elapsed += delta;
+			self.apply_fade();
+		}
+	}
+
+	/// Finishes any fade, restoring the opacity of the text box
+	/// and leaving it visible only if it was fading in.
+	pub fn finish_fade(&mut self) {
+		if let Some(TextFade { alphas: (box_alpha, text_alpha), fade_in, .. }) = self.fade.take() {
+			self.colour[3] = box_alpha;
+			self.text.colour[3] = text_alpha;
+			self.visible = fade_in;
+		}
+	}
+
+	/// Sets the opacity of the text box from the progress of the fade.
+	fn apply_fade(&mut self) {
+		let (progress, opacity, (box_alpha, text_alpha)) = match &self.fade {
+			None => return,
+			Some(fade) => {
+				let progress = match fade.duration > 0.0 {
+					true => (fade.elapsed / fade.duration).min(1.0),
+					false => 1.0,
+				};
+				(progress, if fade.fade_in { progress } else { 1.0 - progress }, fade.alphas)
+			}
+		};
+
+		self.colour[3] = box_alpha * opacity;
+		self.text.colour[3] = text_alpha * opacity;
+		if progress >= 1.0 {
+			self.finish_fade();
+		}
+	}
+
 	pub fn draw(&self, ctx: &mut ggez::Context) -> ggez::GameResult {
+		if !self.visible {
+			return Ok(());
+		}
+
 		let rectangle = self.rectangle();
-		let fragment = self.text.fragment();
+		let mut text = self.layout_text();
+
 		let text_box = graphics::Mesh::new_rectangle(ctx,
 			graphics::DrawMode::fill(), rectangle, self.colour.into())?;
 		graphics::draw(ctx, &text_box, graphics::DrawParam::new())?;
 
-		let bounds = [rectangle.w - 2.0 * self.padding, rectangle.h - 2.0 * self.padding];
+		let bounds = [rectangle.w - 2.0 * self.padding, self.height_limit(ctx)];
 		let text_position = ([rectangle.x + self.padding, rectangle.y + self.padding], );
-		graphics::draw(ctx, graphics::Text::new(fragment)
-			.set_bounds(bounds, self.alignment), text_position)
+		graphics::draw(ctx, text.set_bounds(bounds, self.alignment), text_position)
+	}
+
+	/// Checks whether the whole text is taller than the space in the text box,
+	/// which is limited to the maximum number of lines if one is set.
+	pub fn overflows(&self, ctx: &mut ggez::Context) -> bool {
+		!self.fits(ctx, &self.text.string)
+	}
+
+	/// Splits the text into pages that each fit in the text box.
+	/// Every page except the last ends with the more indicator.
+	pub fn pages(&self, ctx: &mut ggez::Context) -> Vec<String> {
+		layout::paginate(&self.text.string, Self::MORE_INDICATOR, |page| self.fits(ctx, page))
+	}
+
+	/// Checks whether a string would fit in the text box when laid out with its font and size.
+	fn fits(&self, ctx: &mut ggez::Context, string: &str) -> bool {
+		let mut text = self.layout_fragments(&[graphics::TextFragment::new(string)]);
+		text.set_bounds([self.size.0 - 2.0 * self.padding, f32::INFINITY], self.alignment);
+		text.height(ctx) as f32 <= self.height_limit(ctx)
+	}
+
+	/// Height that text can take up in the text box.
+	fn height_limit(&self, ctx: &mut ggez::Context) -> f32 {
+		let height = self.size.1 - 2.0 * self.padding;
+		match self.max_lines {
+			Some(lines) => {
+				let lines = vec!["|"; lines.max(1)].join("\n");
+				let lines = self.layout_fragments(&[graphics::TextFragment::new(lines)]).height(ctx) as f32;
+				height.min(lines)
+			}
+			None => height,
+		}
+	}
+
+	/// Width of the whole text on a single line, including characters that are not displayed yet.
+	pub fn text_width(&self, ctx: &mut ggez::Context) -> f32 {
+		self.layout_text().width(ctx) as f32
+	}
+
+	/// Creates the text with the font and size of the text box.
+	fn layout_text(&self) -> graphics::Text {
+		self.layout_fragments(&self.text.fragments())
+	}
+
+	/// Creates text from fragments with the font and size of the text box.
+	fn layout_fragments(&self, fragments: &[graphics::TextFragment]) -> graphics::Text {
+		let (font, size) = match self.font {
+			Some((font, size)) => (Some(font), size),
+			None => (None, graphics::DEFAULT_FONT_SCALE),
+		};
+
+		let mut text = graphics::Text::default();
+		fragments.iter().cloned().for_each(|mut fragment| {
+			fragment.font = font;
+			text.add(fragment.scale(graphics::Scale::uniform(size * self.text_scale)));
+		});
+		text
+	}
+
+	/// Moves and resizes the text box to cover a rectangle.
+	pub fn set_rectangle(&mut self, rectangle: graphics::Rect) {
+		self.position = (rectangle.x, rectangle.y);
+		self.size = (rectangle.w, rectangle.h);
 	}
 
 	pub fn rectangle(&self) -> graphics::Rect {
@@ -123,18 +656,39 @@ pub struct Button {
 	pub text: TextBox,
 	pub default: [f32; 4],
 	pub hover: [f32; 4],
+	/// Whether the mouse is over the button.
+	pub hovered: bool,
+	/// Position of the button in the order that the keyboard moves between buttons.
+	pub keyboard_index: usize,
+	/// Whether the button has been selected with the keyboard.
+	pub is_selected: bool,
 }
 
 impl Button {
 	pub fn new(text: TextBox, default: [f32; 4], hover: [f32; 4]) -> Self {
-		Button { text, default, hover }
+		Button { text, default, hover, hovered: false, keyboard_index: 0, is_selected: false }
 	}
 
-	pub fn update(&mut self, (x, y): (f32, f32)) {
-		match self.text.rectangle().contains([x, y]) {
-			false => self.text.colour = self.default,
-			true => self.text.colour = self.hover,
-		}
+	/// Sets the colour of the button from whether a point is over it or it is selected.
+	/// Returns true if the point has just moved over the button.
+	pub fn update(&mut self, (x, y): (f32, f32)) -> bool {
+		let hovered = self.hovered;
+		self.hovered = self.text.rectangle().contains([x, y]);
+		self.refresh();
+		self.hovered && !hovered
+	}
+
+	/// Selects or deselects the button, highlighting it while it is selected.
+	pub fn select(&mut self, selected: bool) {
+		self.is_selected = selected;
+		self.refresh();
+	}
+
+	fn refresh(&mut self) {
+		self.text.colour = match self.hovered || self.is_selected {
+			false => self.default,
+			true => self.hover,
+		};
 	}
 }
 
@@ -145,3 +699,617 @@ impl Deref for Button {
 		&self.text
 	}
 }
+
+/// The action performed by a button in the quick menu.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuickAction {
+	/// Toggles advancing the script automatically once dialogue is finished.
+	Auto,
+	/// Toggles advancing the script every frame until a branch is reached.
+	Skip,
+	/// Saves the game.
+	Save,
+	/// Loads the saved game.
+	Load,
+}
+
+/// A row of buttons for actions that are available while dialogue is displayed.
+#[derive(Debug, Default)]
+pub struct QuickMenu {
+	pub buttons: Vec<(Button, QuickAction)>,
+	/// Whether the script advances automatically once dialogue is finished.
+	pub auto: bool,
+	/// Whether the script advances every frame.
+	pub skip: bool,
+}
+
+impl QuickMenu {
+	const ACTIONS: [(&'static str, QuickAction); 4] = [("Auto", QuickAction::Auto),
+		("Skip", QuickAction::Skip), ("Save", QuickAction::Save), ("Load", QuickAction::Load)];
+
+	/// Places the buttons in a row at the bottom right corner of a text box.
+	/// There are no buttons if the quick menu is disabled.
+	pub fn layout(&mut self, text_box: graphics::Rect, settings: &Settings) {
+		self.buttons.clear();
+		if !settings.quick_menu { return; }
+
+		let spacing = layout::margin(settings) / 2.0;
+		let rectangles = layout::quick_menu_buttons(text_box, settings, Self::ACTIONS.len());
+		for ((string, action), rectangle) in Self::ACTIONS.iter().zip(rectangles) {
+			let text = RenderText::new(string.to_string(), settings.foreground_colour);
+			let text = TextBox::from_rectangle(text, rectangle, settings.background_colour)
+				.alignment(graphics::Align::Center).padding(spacing / 2.0).text_scale(settings.ui_scale);
+			self.buttons.push((Button::new(text, settings.background_colour, settings.secondary_colour), *action));
+		}
+		self.refresh(settings);
+	}
+
+	/// Finds the action of the button at a point.
+	pub fn click(&self, (x, y): (f32, f32)) -> Option<QuickAction> {
+		self.buttons.iter().find(|(button, _)| button.rectangle().contains([x, y]))
+			.map(|(_, action)| *action)
+	}
+
+	/// Whether the mode toggled by an action is active.
+	pub fn is_active(&self, action: QuickAction) -> bool {
+		match action {
+			QuickAction::Auto => self.auto,
+			QuickAction::Skip => self.skip,
+			QuickAction::Save | QuickAction::Load => false,
+		}
+	}
+
+	/// Toggles the mode of an action and updates the colour of its button.
+	/// Actions that are not modes are unaffected.
+	pub fn toggle(&mut self, action: QuickAction, settings: &Settings) {
+		match action {
+			QuickAction::Auto => self.auto = !self.auto,
+			QuickAction::Skip => self.skip = !self.skip,
+			QuickAction::Save | QuickAction::Load => return,
+		}
+		self.refresh(settings);
+	}
+
+	/// Updates the hover colours of the buttons.
+	/// Returns true if the point has just moved over a button.
+	pub fn update(&mut self, point: (f32, f32)) -> bool {
+		self.buttons.iter_mut().fold(false, |entered, (button, _)| button.update(point) || entered)
+	}
+
+	/// Sets the colour of the buttons of active modes to the active colour.
+	fn refresh(&mut self, settings: &Settings) {
+		let mut buttons = std::mem::take(&mut self.buttons);
+		buttons.iter_mut().for_each(|(button, action)| {
+			let active = self.is_active(*action);
+			button.default = if active { settings.active_colour } else { settings.background_colour };
+			if !button.hovered {
+				button.text.colour = button.default;
+			}
+		});
+		self.buttons = buttons;
+	}
+
+	pub fn draw(&self, ctx: &mut ggez::Context) -> ggez::GameResult {
+		self.buttons.iter().try_for_each(|(button, _)| button.draw(ctx))
+	}
+}
+
+/// The stage of a transition between scenes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransitionPhase {
+	/// The transition image fades in over the current scene.
+	FadeIn,
+	/// The transition image covers the screen.
+	Hold,
+	/// The transition image fades out to reveal the next scene.
+	FadeOut,
+}
+
+/// An image that covers the screen while the scene changes.
+/// The previous scene fades into the image, which then fades out to reveal the next scene.
+#[derive(Debug)]
+pub struct TransitionState {
+	/// Image that is shown during the transition.
+	/// The screen is covered in black if there is no image.
+	pub image: Option<Image>,
+	/// Image of the scene when the transition started, which is shown until the image covers it.
+	/// The scene that is being drawn is shown instead if there is no image.
+	pub previous: Option<Image>,
+	pub phase: TransitionPhase,
+	/// Time spent in the current phase in milliseconds.
+	pub elapsed: f32,
+	/// Time taken to fade in and out in milliseconds.
+	pub duration: f32,
+}
+
+impl TransitionState {
+	/// Time that the image covers the screen for in milliseconds.
+	const HOLD_PERIOD: f32 = 100.0;
+	/// Time taken to fade in and out in milliseconds if no duration is specified.
+	pub const DEFAULT_DURATION: f32 = 500.0;
+
+	pub fn new(image: Image, duration: f32) -> Self {
+		TransitionState { image: Some(image), ..Self::black(duration) }
+	}
+
+	/// Creates a transition that covers the screen in black.
+	pub fn black(duration: f32) -> Self {
+		TransitionState { image: None, previous: None, phase: TransitionPhase::FadeIn, elapsed: 0.0, duration }
+	}
+
+	/// Sets the image of the scene that the transition fades from.
+	pub fn previous(mut self, previous: Option<Image>) -> Self {
+		self.previous = previous;
+		self
+	}
+
+	/// Progresses the transition by an amount of milliseconds.
+	/// Returns true once the image has finished covering the screen
+	/// and the script should continue to the next scene.
+	pub fn update(&mut self, delta: f32) -> bool {
+		self.elapsed += delta;
+		let period = match self.phase {
+			TransitionPhase::Hold => Self::HOLD_PERIOD,
+			_ => self.duration / 2.0,
+		};
+
+		if self.elapsed < period { return false; }
+		self.elapsed -= period;
+		match self.phase {
+			TransitionPhase::FadeIn => self.phase = TransitionPhase::Hold,
+			TransitionPhase::Hold => {
+				self.phase = TransitionPhase::FadeOut;
+				return true;
+			}
+			TransitionPhase::FadeOut => self.elapsed = period,
+		}
+		false
+	}
+
+	/// Whether the image is still covering the previous scene.
+	pub fn is_covering(&self) -> bool {
+		self.phase != TransitionPhase::FadeOut
+	}
+
+	/// Whether the image has completely faded out.
+	pub fn is_finished(&self) -> bool {
+		self.phase == TransitionPhase::FadeOut && self.elapsed >= self.duration / 2.0
+	}
+
+	/// The opacity of the transition image.
+	pub fn alpha(&self) -> f32 {
+		let progress = match self.duration > 0.0 {
+			true => (self.elapsed / (self.duration / 2.0)).min(1.0),
+			false => 1.0,
+		};
+
+		match self.phase {
+			TransitionPhase::FadeIn => progress,
+			TransitionPhase::Hold => 1.0,
+			TransitionPhase::FadeOut => 1.0 - progress,
+		}
+	}
+
+	/// Draws the transition image stretched over the view.
+	/// The image of the previous scene is drawn beneath it until the image covers the screen.
+	pub fn draw(&self, ctx: &mut ggez::Context, settings: &Settings) -> ggez::GameResult {
+		let stretch = |image: &Image| [settings.width / image.width() as f32, settings.height / image.height() as f32];
+		if let (Some(previous), true) = (&self.previous, self.is_covering()) {
+			graphics::draw(ctx, previous, graphics::DrawParam::new().scale(stretch(previous)))?;
+		}
+
+		let image = match &self.image {
+			Some(image) => image,
+			None => {
+				let view = graphics::Rect::new(0.0, 0.0, settings.width, settings.height);
+				let colour = [0.0, 0.0, 0.0, self.alpha()].into();
+				let cover = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), view, colour)?;
+				return graphics::draw(ctx, &cover, graphics::DrawParam::new());
+			}
+		};
+
+		graphics::draw(ctx, image, graphics::DrawParam::new()
+			.scale(stretch(image)).color([1.0, 1.0, 1.0, self.alpha()].into()))
+	}
+}
+
+/// Displays frame timings and resource counts for diagnosing performance.
+#[derive(Debug)]
+pub struct PerformanceOverlay {
+	/// Whether the overlay is displayed.
+	pub visible: bool,
+	/// Durations of the most recent frames in milliseconds.
+	frame_times: VecDeque<f32>,
+	update_time: Duration,
+	draw_time: Duration,
+	text: Option<graphics::Text>,
+	graph: Option<graphics::Mesh>,
+	last_rebuild: Option<Instant>,
+}
+
+impl PerformanceOverlay {
+	/// Amount of frames that are displayed in the frame time graph.
+	const FRAME_COUNT: usize = 120;
+	/// Minimum time between rebuilding the displayed text and graph.
+	const REBUILD_PERIOD: Duration = Duration::from_millis(250);
+	/// Height of the frame time graph in pixels.
+	const GRAPH_HEIGHT: f32 = 40.0;
+	/// Frame time in milliseconds that reaches the top of the graph.
+	const GRAPH_SCALE: f32 = 50.0;
+
+	/// Records the duration of the last frame.
+	pub fn record_frame(&mut self, frame: Duration) {
+		if self.frame_times.len() == Self::FRAME_COUNT {
+			self.frame_times.pop_front();
+		}
+		self.frame_times.push_back(frame.as_secs_f32() * 1_000.0);
+	}
+
+	/// Records the time spent in the last update.
+	pub fn record_update(&mut self, update: Duration) {
+		self.update_time = update;
+	}
+
+	/// Records the time spent in the last draw.
+	pub fn record_draw(&mut self, draw: Duration) {
+		self.draw_time = draw;
+	}
+
+	/// Rebuilds the displayed text and graph if enough time has passed since the last rebuild.
+	/// Does nothing if the overlay is not visible.
+	pub fn rebuild(&mut self, ctx: &mut ggez::Context, instances: usize,
+	               images: usize, sources: usize) -> ggez::GameResult {
+		let elapsed = self.last_rebuild.map(|instant| instant.elapsed());
+		if !self.visible || elapsed.map(|elapsed| elapsed < Self::REBUILD_PERIOD).unwrap_or(false) {
+			return Ok(());
+		}
+
+		self.last_rebuild = Some(Instant::now());
+		self.text = Some(graphics::Text::new(format!("FPS: {:.0}\nUpdate: {:.2}ms\nDraw: {:.2}ms\n\
+			Instances: {}\nImages: {}\nSources: {}", ggez::timer::fps(ctx),
+			self.update_time.as_secs_f32() * 1_000.0, self.draw_time.as_secs_f32() * 1_000.0,
+			instances, images, sources)));
+
+		let points: Vec<_> = self.frame_times.iter().enumerate().map(|(index, time)| {
+			let height = (time / Self::GRAPH_SCALE).min(1.0) * Self::GRAPH_HEIGHT;
+			[index as f32, Self::GRAPH_HEIGHT - height]
+		}).collect();
+		self.graph = match points.len() >= 2 {
+			true => Some(graphics::Mesh::new_line(ctx, &points, 1.0, graphics::WHITE)?),
+			false => None,
+		};
+		Ok(())
+	}
+
+	/// Draws the overlay in the top left corner of the view.
+	pub fn draw(&self, ctx: &mut ggez::Context) -> ggez::GameResult {
+		if !self.visible { return Ok(()); }
+		let background = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(),
+			[0.0, 0.0, Self::FRAME_COUNT as f32, Self::GRAPH_HEIGHT + 100.0].into(),
+			[0.0, 0.0, 0.0, 0.6].into())?;
+		graphics::draw(ctx, &background, graphics::DrawParam::new())?;
+		self.graph.as_ref().map(|graph| graphics::draw(ctx,
+			graph, graphics::DrawParam::new())).transpose()?;
+		self.text.as_ref().map(|text| graphics::draw(ctx, text,
+			graphics::DrawParam::new().dest([2.0, Self::GRAPH_HEIGHT + 2.0]))).transpose()?;
+		Ok(())
+	}
+}
+
+impl Default for PerformanceOverlay {
+	fn default() -> Self {
+		PerformanceOverlay {
+			visible: false,
+			frame_times: VecDeque::with_capacity(Self::FRAME_COUNT),
+			update_time: Duration::default(),
+			draw_time: Duration::default(),
+			text: None,
+			graph: None,
+			last_rebuild: None,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn branch(position_y: f32, label: &str) -> (Button, Label) {
+		let text = RenderText::new(label.to_owned(), [0.0; 4]);
+		let text = TextBox::new(text, (100.0, position_y), (200.0, 40.0), [1.0; 4]);
+		(Button::new(text, [1.0; 4], [0.5; 4]), Label::from(label))
+	}
+
+	#[test]
+	fn reveal_keeps_layout() {
+		let string = "A word that wraps — über lines";
+		let mut text = RenderText::empty(string.to_owned(), [1.0; 4]);
+		let mut revealed = Vec::new();
+		while !text.is_finished() {
+			let (shown, hidden) = text.sections();
+			assert_eq!([shown, hidden].concat(), string);
+			revealed.push(shown.chars().count());
+			text.step();
+		}
+
+		assert_eq!(text.sections(), (string, ""));
+		assert_eq!(revealed, (0..string.chars().count()).collect::<Vec<_>>());
+		let fragments = RenderText::empty(string.to_owned(), [1.0; 4]).fragments();
+		assert_eq!(fragments.last().unwrap().color.map(|colour| colour.a), Some(0.0));
+	}
+
+	#[test]
+	fn reveal_keeps_line_breaks() {
+		use glyph_brush_layout::{GlyphPositioner, Layout, SectionGeometry, SectionText};
+		use glyph_brush_layout::rusttype::{Font, Point};
+
+		let font = Font::from_bytes(&include_bytes!("../resources/DejaVuSerif.ttf")[..]).unwrap();
+		let fonts = [font];
+		let geometry = SectionGeometry { bounds: (120.0, f32::INFINITY), ..SectionGeometry::default() };
+		let positions = |texts: &[&str]| -> Vec<Point<f32>> {
+			let sections: Vec<_> = texts.iter()
+				.map(|text| SectionText { text, ..SectionText::default() }).collect();
+			Layout::default_wrap().calculate_glyphs(&fonts, &geometry, &sections)
+				.into_iter().map(|(glyph, _, _)| glyph.position()).collect()
+		};
+
+		let string = "A line of dialogue that wraps — über several lines";
+		let finished = positions(&[string]);
+		let lines = finished.iter().map(|position| position.y.to_bits())
+			.collect::<std::collections::HashSet<_>>();
+		assert!(lines.len() > 2);
+
+		let mut text = RenderText::empty(string.to_owned(), [1.0; 4]);
+		while !text.is_finished() {
+			let fragments = text.fragments();
+			let texts: Vec<_> = fragments.iter().map(|fragment| fragment.text.as_str()).collect();
+			assert_eq!(positions(&texts), finished);
+			text.step();
+		}
+	}
+
+	#[test]
+	fn animations_finished() {
+		let mut render = Render::default();
+		assert!(render.animations_finished() && render.stage.all_animations_finished());
+		render.camera = Some(CameraAnimation { destination: (40.0, 0.0), remaining_ms: 100.0 });
+		assert!(!render.animations_finished());
+		render.finish_animations();
+		assert!(render.animations_finished());
+		assert_eq!(render.camera_offset, (40.0, 0.0));
+
+		let mut transition = TransitionState::black(200.0);
+		transition.update(100.0);
+		render.transition = Some(transition);
+		assert!(render.animations_finished());
+		render.transition.as_mut().unwrap().update(TransitionState::HOLD_PERIOD);
+		assert!(!render.animations_finished());
+		render.finish_animations();
+		assert!(render.animations_finished());
+	}
+
+	#[test]
+	fn clear_notice() {
+		let (mut render, settings) = (Render::default(), Settings::default());
+		render.notify("Failed to reload".to_owned(), &settings);
+		render.clear_notice("Something else");
+		assert!(render.notice.is_some());
+		render.clear_notice("Failed to reload");
+		assert!(render.notice.is_none());
+	}
+
+	#[test]
+	fn render_busy() {
+		let mut render = Render::default();
+		assert!(!render.is_busy());
+		assert_eq!(render.stage.animating_count(), 0);
+		let text = RenderText::empty("Hello".to_owned(), [1.0; 4]);
+		render.text = Some(TextBox::new(text, (0.0, 0.0), (100.0, 50.0), [1.0; 4]));
+		assert!(render.is_busy());
+		render.text.as_mut().unwrap().finish();
+		assert!(!render.is_busy());
+	}
+
+	#[test]
+	fn transition_phases() {
+		let mut transition = TransitionState::black(200.0);
+		assert!(transition.is_covering() && !transition.is_finished());
+		assert!(!transition.update(50.0));
+		assert_eq!(transition.alpha(), 0.5);
+		assert!(!transition.update(50.0));
+		assert_eq!(transition.phase, TransitionPhase::Hold);
+		assert!(transition.is_covering());
+		assert!(transition.update(TransitionState::HOLD_PERIOD));
+		assert!(!transition.is_covering() && !transition.is_finished());
+		assert!(!transition.update(60.0));
+		assert!(!transition.update(60.0));
+		assert!(transition.is_finished());
+		assert_eq!(transition.alpha(), 0.0);
+	}
+
+	#[test]
+	fn render_animations_finished() {
+		let mut render = Render::default();
+		assert!(render.animations_finished());
+		render.camera = Some(CameraAnimation { destination: (10.0, 0.0), remaining_ms: 100.0 });
+		render.transition = Some(TransitionState::black(200.0));
+		assert!(!render.animations_finished());
+		render.finish_camera();
+		assert!(render.animations_finished());
+
+		let transition = render.transition.as_mut().unwrap();
+		(0..2).for_each(|_| { transition.update(100.0); });
+		assert!(!render.animations_finished());
+		render.finish_animations();
+		assert!(render.transition.is_none() && render.animations_finished());
+	}
+
+	#[test]
+	fn button_selection() {
+		let text = TextBox::new(RenderText::empty(String::new(), [1.0; 4]), (0.0, 0.0), (100.0, 50.0), [0.0; 4]);
+		let mut button = Button::new(text, [0.0; 4], [1.0; 4]);
+		button.select(true);
+		assert_eq!(button.text.colour, [1.0; 4]);
+		button.update((500.0, 500.0));
+		assert_eq!(button.text.colour, [1.0; 4]);
+		button.select(false);
+		assert_eq!(button.text.colour, [0.0; 4]);
+	}
+
+	#[test]
+	fn colour_fade() {
+		let mut text = RenderText::empty("Fade".to_owned(), [1.0; 4]).with_colour_fade(2);
+		(0..3).for_each(|_| text.step());
+		let fragments = text.fragments();
+		let alphas: Vec<_> = fragments.iter().map(|fragment| fragment.color.unwrap().a).collect();
+		let texts: Vec<_> = fragments.iter().map(|fragment| fragment.text.as_str()).collect();
+		assert_eq!(texts, vec!["F", "a", "d", "e"]);
+		assert_eq!(alphas, vec![1.0, 2.0 / 3.0, 1.0 / 3.0, 0.0]);
+
+		text.finish();
+		assert_eq!(text.fragments().len(), 2);
+		assert_eq!(text.colour_at_progress(0.5), [1.0, 1.0, 1.0, 0.5]);
+	}
+
+	#[test]
+	fn rounded_rectangle_within_bounds() {
+		let points = rounded_rectangle([10.0, 20.0, 30.0, 100.0].into(), 50.0);
+		assert!(points.iter().all(|[x, y]| (10.0..=40.0001).contains(x) && (20.0..=120.0001).contains(y)));
+		assert!(points.iter().any(|[x, _]| *x < 10.001) && points.iter().any(|[x, _]| *x > 39.999));
+	}
+
+	#[test]
+	fn camera_pan() {
+		let mut render = Render::default();
+		render.pan((100.0, -50.0), 0.0);
+		assert_eq!(render.camera_offset, (100.0, -50.0));
+
+		render.pan((100.0, 50.0), 100.0);
+		let camera = render.camera.as_mut().unwrap();
+		assert!(!camera.update(&mut render.camera_offset, 50.0));
+		assert_eq!(render.camera_offset, (150.0, -25.0));
+		assert!(camera.update(&mut render.camera_offset, 80.0));
+		assert_eq!(render.camera_offset, (200.0, 0.0));
+
+		render.pan((-200.0, 0.0), 100.0);
+		render.finish_camera();
+		assert!(render.camera.is_none());
+		assert_eq!(render.camera_offset, (0.0, 0.0));
+	}
+
+	#[test]
+	fn text_box_fade() {
+		let text = RenderText::new("Hello".to_owned(), [1.0, 1.0, 1.0, 0.8]);
+		let mut text = TextBox::new(text, (0.0, 0.0), (200.0, 40.0), [0.0, 0.0, 0.0, 0.5]);
+		text.fade_out(100.0);
+		text.tick_fade(50.0);
+		assert_eq!((text.colour[3], text.text.colour[3]), (0.25, 0.4));
+		text.tick_fade(50.0);
+		assert!(!text.visible && text.fade.is_none());
+		assert_eq!((text.colour[3], text.text.colour[3]), (0.5, 0.8));
+
+		text.fade_in(100.0);
+		assert!(text.visible);
+		assert_eq!((text.colour[3], text.text.colour[3]), (0.0, 0.0));
+		text.toggle_visible();
+		assert!(!text.visible);
+		assert_eq!((text.colour[3], text.text.colour[3]), (0.5, 0.8));
+	}
+
+	#[test]
+	fn click_choice_then_dialogue() {
+		let branches = vec![branch(100.0, "left"), branch(150.0, "right")];
+		let mut render = Render { branches, ..Render::default() };
+		let settings = Settings::default();
+		assert_eq!(render.click((200.0, 170.0), &settings), Click::Choose(Label::from("right")));
+
+		// Choosing a branch clears the buttons before the dialogue is shown.
+		render.branches.clear();
+		let text = RenderText::empty("Hello".to_owned(), [0.0; 4]);
+		render.text = Some(TextBox::new(text, (0.0, 360.0), (640.0, 120.0), [1.0; 4]));
+		assert_eq!(render.click((200.0, 170.0), &settings), Click::Advance);
+	}
+
+	#[test]
+	fn relayout_keeps_branch_scroll() {
+		let mut settings = Settings::default();
+		let branches = layout::branch_buttons(&settings, 30).into_iter().map(|rectangle| {
+			let text = TextBox::from_rectangle(RenderText::new("Choice".to_owned(), [0.0; 4]), rectangle, [1.0; 4]);
+			(Button::new(text, [1.0; 4], [0.5; 4]), Label::from("choice"))
+		}).collect();
+		let mut render = Render { branches, ..Render::default() };
+		let positions = |render: &Render| render.branches.iter()
+			.map(|(button, _)| button.rectangle().y).collect::<Vec<_>>();
+
+		render.scroll_branches(-Render::SCROLL_DISTANCE, &settings);
+		assert_eq!(render.branch_scroll, -Render::SCROLL_DISTANCE);
+		let scrolled = positions(&render);
+		render.relayout_branches(&settings);
+		assert_eq!(positions(&render), scrolled);
+
+		settings.branch_button_min_height = 0.0;
+		render.relayout_branches(&settings);
+		assert_eq!(render.branch_scroll, 0.0);
+		let buttons = layout::branch_buttons(&settings, 30);
+		assert_eq!(positions(&render), buttons.iter().map(|rectangle| rectangle.y).collect::<Vec<_>>());
+	}
+
+	#[test]
+	fn click_between_branches() {
+		let branches = vec![branch(100.0, "left"), branch(150.0, "right")];
+		let render = Render { branches, ..Render::default() };
+		let settings = Settings::default();
+		assert_eq!(render.click((200.0, 145.0), &settings), Click::Ignore);
+		assert_eq!(render.click((20.0, 120.0), &settings), Click::Ignore);
+	}
+
+	#[test]
+	fn click_advance_area() {
+		let mut render = Render::default();
+		let mut settings = Settings { advance_click_area: AdvanceClickArea::TextBoxOnly, ..Settings::default() };
+		assert_eq!(render.click((200.0, 100.0), &settings), Click::Advance);
+
+		let text = RenderText::empty("Hello".to_owned(), [0.0; 4]);
+		render.text = Some(TextBox::new(text, (0.0, 360.0), (640.0, 120.0), [1.0; 4]));
+		assert_eq!(render.click((200.0, 100.0), &settings), Click::Ignore);
+		assert_eq!(render.click((200.0, 400.0), &settings), Click::Advance);
+
+		settings.advance_click_area = AdvanceClickArea::BottomHalf;
+		assert_eq!(render.click((200.0, 200.0), &settings), Click::Ignore);
+		assert_eq!(render.click((200.0, 300.0), &settings), Click::Advance);
+	}
+
+	#[test]
+	fn quick_menu_click() {
+		let settings = Settings { quick_menu: true, ..Settings::default() };
+		let mut render = Render::default();
+		let text = RenderText::empty("Hello".to_owned(), [0.0; 4]);
+		let text = TextBox::new(text, (8.0, 360.0), (624.0, 112.0), [1.0; 4]);
+		render.quick_menu.layout(text.rectangle(), &settings);
+		render.text = Some(text);
+
+		let (button, _) = &render.quick_menu.buttons[1];
+		let point = (button.position.0 + 1.0, button.position.1 + 1.0);
+		assert!(render.text.as_ref().unwrap().rectangle().contains([point.0, point.1]));
+		assert_eq!(render.click(point, &settings), Click::Quick(QuickAction::Skip));
+
+		render.quick_menu.toggle(QuickAction::Skip, &settings);
+		let (button, _) = &render.quick_menu.buttons[1];
+		assert!(render.quick_menu.skip);
+		assert_eq!(button.colour, settings.active_colour);
+
+		render.text.as_mut().unwrap().toggle_visible();
+		assert_eq!(render.click(point, &settings), Click::Advance);
+	}
+
+	#[test]
+	fn button_hover_transition() {
+		let (mut button, _) = branch(100.0, "left");
+		assert!(!button.update((0.0, 0.0)));
+		assert!(button.update((150.0, 120.0)));
+		assert!(!button.update((160.0, 120.0)));
+		assert_eq!(button.colour, [0.5; 4]);
+		assert!(!button.update((0.0, 0.0)));
+		assert!(button.update((150.0, 120.0)));
+	}
+}
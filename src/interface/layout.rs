@@ -0,0 +1,226 @@
+use ggez::graphics::Rect;
+
+use crate::Settings;
+
+/// Space between interface elements and the edges of the view, scaled by the interface scale.
+pub fn margin(settings: &Settings) -> f32 {
+	settings.interface_margin * settings.ui_scale
+}
+
+/// Finds the positions of the dialogue text box and the name text box above it.
+/// The text box lies along the bottom of the view and the heights of the text boxes and the margins
+/// are scaled by the interface scale.
+/// The name box has the largest width and is resized to fit the name with [`fit_name_box`].
+/// The name box is `None` if it would have no area.
+pub fn dialogue_boxes(settings: &Settings) -> (Rect, Option<Rect>) {
+	let margin = margin(settings);
+	let height = settings.height * settings.text_box_height * settings.ui_scale - margin;
+	let text = Rect::new(margin, settings.height - height, settings.width - 2.0 * margin, height - margin);
+
+	let name_height = settings.height * settings.character_name_height * settings.ui_scale;
+	let name_width = settings.width * settings.character_name_max_width * settings.ui_scale - margin;
+	let name = match name_height > 0.0 && name_width > 0.0 {
+		true => Some(Rect::new(margin, settings.height - (height + margin + name_height), name_width, name_height)),
+		false => None,
+	};
+	(text, name)
+}
+
+/// Resizes the name box to a width between the smallest and largest widths of the character name.
+/// If the horizontal centre of the speaker is given, the name box is centred above the speaker
+/// without leaving the view.
+pub fn fit_name_box(name_box: Rect, width: f32, speaker: Option<f32>, settings: &Settings) -> Rect {
+	let margin = margin(settings);
+	let minimum = settings.width * settings.character_name_min_width * settings.ui_scale - margin;
+	let width = width.max(minimum).min(name_box.w);
+	let position_x = match speaker {
+		Some(centre) => (centre - width / 2.0).max(margin).min(settings.width - margin - width),
+		None => name_box.x,
+	};
+	Rect::new(position_x, name_box.y, width, name_box.h)
+}
+
+/// Finds the positions of the buttons for a number of branches.
+/// The buttons are placed in one or two columns in the centre of the view and shrink if they would not fit.
+/// If the buttons would be shorter than the minimum height they start at the top of the view instead
+/// and extend past the bottom, to be scrolled with [`branch_scroll`].
+pub fn branch_buttons(settings: &Settings, count: usize) -> Vec<Rect> {
+	let margin = margin(settings);
+	let columns = if settings.branch_two_columns && count > 1 { 2 } else { 1 };
+	let rows = count.div_ceil(columns);
+
+	let fit_height = (settings.height - margin) / rows.max(1) as f32 - margin;
+	let minimum_height = settings.height * settings.branch_button_min_height * settings.ui_scale;
+	let button_height = (settings.height * settings.branch_button_height * settings.ui_scale)
+		.min(fit_height.max(minimum_height));
+	let column_width = (settings.width - (columns + 1) as f32 * margin) / columns as f32;
+	let button_width = (settings.width * settings.branch_button_width * settings.ui_scale).min(column_width);
+
+	let total_width = columns as f32 * (button_width + margin) - margin;
+	let position_x = (settings.width - total_width) / 2.0;
+	let true_height = button_height + margin;
+	let position_y = ((settings.height - rows as f32 * true_height + margin) / 2.0).max(margin);
+	(0..count).map(|index| {
+		let (row, column) = (index / columns, index % columns);
+		Rect::new(position_x + column as f32 * (button_width + margin),
+			position_y + row as f32 * true_height, button_width, button_height)
+	}).collect()
+}
+
+/// Limits the distance that branch buttons are scrolled by so that the first and last buttons
+/// do not move further into the view than the margin.
+/// Buttons that already fit in the view are not scrolled.
+pub fn branch_scroll(buttons: &[Rect], distance: f32, settings: &Settings) -> f32 {
+	let margin = margin(settings);
+	let top = buttons.iter().map(Rect::top).fold(f32::INFINITY, f32::min);
+	let bottom = buttons.iter().map(Rect::bottom).fold(f32::NEG_INFINITY, f32::max);
+	match bottom - top > settings.height - 2.0 * margin {
+		true => distance.max(settings.height - margin - bottom).min(margin - top),
+		false => 0.0,
+	}
+}
+
+/// Finds the position of a notice in the centre of the view.
+pub fn notice(settings: &Settings) -> Rect {
+	let margin = margin(settings);
+	let height = settings.height * settings.text_box_height * settings.ui_scale;
+	Rect::new(margin, (settings.height - height) / 2.0, settings.width - 2.0 * margin, height)
+}
+
+/// Finds the positions of a row of quick menu buttons in the bottom right corner of the text box.
+pub fn quick_menu_buttons(text_box: Rect, settings: &Settings, count: usize) -> Vec<Rect> {
+	let spacing = margin(settings) / 2.0;
+	let width = settings.width * settings.quick_menu_button_width * settings.ui_scale;
+	let height = settings.height * settings.quick_menu_button_height * settings.ui_scale;
+	let position_y = text_box.bottom() - height - spacing;
+	let position_x = text_box.right() - count as f32 * (width + spacing);
+	(0..count).map(|index| Rect::new(position_x + index as f32 * (width + spacing),
+		position_y, width, height)).collect()
+}
+
+/// Splits text into pages at spaces so that every page fits.
+/// Every page except the last ends with the indicator, which is included when checking whether a page fits.
+/// A word that does not fit on a page by itself is given its own page.
+pub fn paginate<F: FnMut(&str) -> bool>(string: &str, indicator: &str, mut fits: F) -> Vec<String> {
+	let mut pages = Vec::new();
+	let mut page = String::new();
+	for word in string.split(' ') {
+		if page.is_empty() {
+			page.push_str(word);
+		} else if fits(&format!("{} {} {}", page, word, indicator)) {
+			page.push(' ');
+			page.push_str(word);
+		} else {
+			pages.push(format!("{} {}", std::mem::replace(&mut page, word.to_owned()), indicator));
+		}
+	}
+	pages.push(page);
+	pages
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn within(inner: Rect, outer: Rect) -> bool {
+		inner.left() >= outer.left() && inner.right() <= outer.right() &&
+			inner.top() >= outer.top() && inner.bottom() <= outer.bottom()
+	}
+
+	#[test]
+	fn dialogue_margins() {
+		let settings = Settings::default();
+		let margin = settings.interface_margin;
+		let (text, name) = dialogue_boxes(&settings);
+		let name = name.unwrap();
+		assert_eq!(text.x, margin);
+		assert_eq!(settings.width - text.right(), margin);
+		assert_eq!(settings.height - text.bottom(), margin);
+		assert_eq!(name.x, margin);
+		assert_eq!(text.top() - name.bottom(), margin);
+
+		let scaled = Settings { ui_scale: 2.0, ..Settings::default() };
+		let (scaled_text, scaled_name) = dialogue_boxes(&scaled);
+		assert!(scaled_text.h > text.h);
+		assert!(scaled_name.unwrap().w > name.w);
+		assert_eq!(scaled_text.x, 2.0 * margin);
+
+		let unnamed = Settings { character_name_height: 0.0, ..Settings::default() };
+		assert_eq!(dialogue_boxes(&unnamed).1, None);
+	}
+
+	#[test]
+	fn name_box_fits_name() {
+		let settings = Settings::default();
+		let margin = settings.interface_margin;
+		let name_box = dialogue_boxes(&settings).1.unwrap();
+		assert_eq!(name_box.w, settings.width * settings.character_name_max_width - margin);
+
+		let minimum = settings.width * settings.character_name_min_width - margin;
+		assert_eq!(fit_name_box(name_box, 10.0, None, &settings).w, minimum);
+		assert_eq!(fit_name_box(name_box, 150.0, None, &settings), Rect::new(margin, name_box.y, 150.0, name_box.h));
+		assert_eq!(fit_name_box(name_box, 1000.0, None, &settings).w, name_box.w);
+
+		assert_eq!(fit_name_box(name_box, 100.0, Some(400.0), &settings).x, 350.0);
+		assert_eq!(fit_name_box(name_box, 100.0, Some(10.0), &settings).x, margin);
+		assert_eq!(fit_name_box(name_box, 100.0, Some(630.0), &settings).right(), settings.width - margin);
+	}
+
+	#[test]
+	fn branch_buttons_fit() {
+		let settings = Settings::default();
+		let view = Rect::new(0.0, 0.0, settings.width, settings.height);
+		let buttons = branch_buttons(&settings, 3);
+		assert_eq!(buttons.len(), 3);
+		assert_eq!(buttons[1].y - buttons[0].y, buttons[0].h + settings.interface_margin);
+		assert_eq!(buttons[0].top() - view.top(), view.bottom() - buttons[2].bottom());
+		assert!(buttons.iter().all(|button| button.x + button.w / 2.0 == settings.width / 2.0));
+
+		let scaled = Settings { ui_scale: 2.0, ..Settings::default() };
+		assert_eq!(branch_buttons(&scaled, 3)[0].w, 2.0 * buttons[0].w);
+
+		let two_columns = Settings { branch_two_columns: true, ..Settings::default() };
+		for settings in [settings, scaled, two_columns].iter() {
+			for count in 1..=6 {
+				let buttons = branch_buttons(settings, count);
+				assert!(buttons.iter().all(|button| within(*button, view)));
+				assert!(buttons.iter().enumerate().all(|(index, button)| buttons[index + 1..].iter()
+					.all(|other| button.bottom() < other.top() || button.right() < other.left())));
+			}
+		}
+	}
+
+	#[test]
+	fn branch_buttons_scroll() {
+		let settings = Settings::default();
+		let margin = settings.interface_margin;
+		let buttons = branch_buttons(&settings, 20);
+		assert_eq!(buttons[0].top(), margin);
+		assert!(buttons[19].bottom() > settings.height);
+		assert_eq!(buttons[0].h, settings.height * settings.branch_button_min_height);
+
+		assert_eq!(branch_scroll(&buttons, 50.0, &settings), 0.0);
+		assert_eq!(branch_scroll(&buttons, -50.0, &settings), -50.0);
+		let end = settings.height - margin - buttons[19].bottom();
+		assert_eq!(branch_scroll(&buttons, -10_000.0, &settings), end);
+		assert_eq!(branch_scroll(&branch_buttons(&settings, 3), -50.0, &settings), 0.0);
+	}
+
+	#[test]
+	fn paginate_words() {
+		let pages = paginate("one two three four five", "[more]", |page| page.len() <= 17);
+		assert_eq!(pages, vec!["one two [more]", "three four [more]", "five"]);
+		assert_eq!(paginate("short", "[more]", |_| false), vec!["short"]);
+		assert_eq!(paginate("long words", "[more]", |_| false), vec!["long [more]", "words"]);
+	}
+
+	#[test]
+	fn branch_two_columns() {
+		let settings = Settings { branch_two_columns: true, ..Settings::default() };
+		let buttons = branch_buttons(&settings, 4);
+		assert_eq!(buttons[0].y, buttons[1].y);
+		assert!(buttons[0].right() < buttons[1].left());
+		assert_eq!(buttons[2].x, buttons[0].x);
+		assert_eq!(buttons[0].left(), settings.width - buttons[1].right());
+	}
+}
@@ -1,6 +1,8 @@
 use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::iter::Peekable;
 use std::str::CharIndices;
+use std::sync::Arc;
 
 use crate::parser::{ParserError, Token};
 
@@ -8,16 +10,51 @@ use crate::parser::{ParserError, Token};
 pub struct Lexer<'a> {
 	string: &'a str,
 	characters: Peekable<CharIndices<'a>>,
+	/// Byte offset in the string of the next character that has not been consumed.
+	current_byte_offset: usize,
 	indentation: usize,
 	target_indent: usize,
 	new_line: bool,
 	peek: Option<Token>,
+	/// Strings that have been interned so that equal names share memory.
+	names: HashSet<Arc<str>>,
 }
 
 impl<'a> Lexer<'a> {
 	pub fn new(string: &'a str) -> Self {
 		let characters = string.char_indices().peekable();
-		Lexer { string, characters, indentation: 0, target_indent: 0, new_line: true, peek: None }
+		Lexer { string, characters, current_byte_offset: 0, indentation: 0, target_indent: 0, new_line: true, peek: None, names: HashSet::new() }
+	}
+
+	/// Gets a shared copy of a string, reusing any equal string that has already been interned.
+	pub fn intern(&mut self, string: String) -> Arc<str> {
+		match self.names.get(string.as_str()) {
+			Some(name) => name.clone(),
+			None => {
+				let name: Arc<str> = string.into();
+				self.names.insert(name.clone());
+				name
+			}
+		}
+	}
+
+	/// The source text that has not been consumed yet.
+	pub fn remaining_source(&self) -> &str {
+		&self.string[self.current_byte_offset..]
+	}
+
+	/// The source text that has been consumed, including any token that has been peeked.
+	pub fn consumed_source(&self) -> &str {
+		&self.string[..self.current_byte_offset]
+	}
+
+	/// Consumes the next character and records the offset of the character after it.
+	fn next_character(&mut self) -> Option<(usize, char)> {
+		let next = self.characters.next();
+		if let Some((index, character)) = next {
+			self.current_byte_offset = index + character.len_utf8();
+		}
+		next
 	}
 
 	pub fn token(&mut self) -> Result<Option<Token>, ParserError> {
@@ -60,10 +97,11 @@ impl<'a> Lexer<'a> {
 		}
 	}
 
-	/// Skips all tokens until the target token is consumed.
+	/// Skips all tokens until the target token is consumed, including a token that has been peeked.
 	pub fn skip_take(&mut self, target: Token) {
+		if self.peek.take().as_ref() == Some(&target) { return; }
 		let target = Ok(target);
-		while let Some(token) = self.next() {
+		for token in self.by_ref() {
 			if token == target { break; }
 		}
 	}
@@ -89,7 +127,7 @@ impl<'a> Iterator for Lexer<'a> {
 			self.new_line = false;
 			let mut target_indent = 0;
 			while let Some((_, '\t')) = self.characters.peek() {
-				self.characters.next();
+				self.next_character();
 				target_indent += 1;
 			}
 
@@ -100,7 +138,7 @@ impl<'a> Iterator for Lexer<'a> {
 			return self.next();
 		}
 
-		let (start, character) = match self.characters.next() {
+		let (start, character) = match self.next_character() {
 			Some((start, character)) => (start, character),
 			None if self.target_indent == 0 => return None,
 			None => {
@@ -116,6 +154,13 @@ impl<'a> Iterator for Lexer<'a> {
 			'[' => Token::SquareOpen,
 			']' => Token::SquareClose,
 			'_' => Token::Underscore,
+			'<' | '>' | '=' | '!' => {
+				if let Some((_, '=')) = self.characters.peek() {
+					self.next_character();
+				}
+				let end = self.characters.peek().map(|(index, _)| *index);
+				Token::Identifier(self.string[start..end.unwrap_or(self.string.len())].to_owned())
+			}
 			'\n' => {
 				self.new_line = true;
 				Token::Terminator
@@ -124,17 +169,17 @@ impl<'a> Iterator for Lexer<'a> {
 				let character = self.characters.peek();
 				match character {
 					Some((_, '"')) => {
-						let (index, _) = self.characters.next().unwrap();
+						let (index, _) = self.next_character().unwrap();
 						let string = self.string[start + 1..index].to_owned();
 						break Token::String(escape(string));
 					}
 					Some((_, '\\')) => {
-						self.characters.next();
-						self.characters.next()
+						self.next_character();
+						self.next_character()
 					}
 					None | Some((_, '\n')) =>
 						return Some(Err(ParserError::UnmatchedQuote)),
-					Some(_) => self.characters.next(),
+					Some(_) => self.next_character(),
 				};
 			},
 			_ => match character.is_whitespace() {
@@ -144,14 +189,14 @@ impl<'a> Iterator for Lexer<'a> {
 						let is_punctuation = !['-', '.'].contains(character)
 							&& character.is_ascii_punctuation();
 						match character.is_whitespace() || is_punctuation {
-							false => self.characters.next(),
+							false => self.next_character(),
 							true => break,
 						};
 					}
 
 					let end = self.characters.peek().map(|(index, _)| *index);
 					let string = &self.string[start..end.unwrap_or(self.string.len())];
-					match character == '-' || character.is_digit(10) {
+					match character == '-' || character.is_ascii_digit() {
 						false => Token::Identifier(string.to_owned()),
 						true => match string.parse() {
 							Ok(numeric) => Token::Numeric(numeric),
@@ -164,8 +209,47 @@ impl<'a> Iterator for Lexer<'a> {
 	}
 }
 
+/// Replaces escape sequences in a string with the characters they represent.
+/// Unknown escape sequences are left as they are, so `\{` and `\}` keep
+/// their backslash and are shown as braces instead of being interpolated in dialogue.
 pub fn escape(string: String) -> String {
-	string.replace("\\n", "\n").replace("\\\"", "\"")
+	let mut escaped = String::with_capacity(string.len());
+	let mut characters = string.chars();
+	while let Some(character) = characters.next() {
+		match character {
+			'\\' => match characters.next() {
+				Some('n') => escaped.push('\n'),
+				Some('t') => escaped.push('\t'),
+				Some('r') => escaped.push('\r'),
+				Some('"') => escaped.push('"'),
+				Some('\\') => escaped.push('\\'),
+				Some(character) => {
+					escaped.push('\\');
+					escaped.push(character);
+				}
+				None => escaped.push('\\'),
+			},
+			character => escaped.push(character),
+		}
+	}
+	escaped
+}
+
+/// Replaces characters in a string with the escape sequences that represent them.
+/// This is the reverse of [`escape`](fn.escape.html).
+pub fn unescape(string: &str) -> String {
+	let mut unescaped = String::with_capacity(string.len());
+	for character in string.chars() {
+		match character {
+			'\n' => unescaped.push_str("\\n"),
+			'\t' => unescaped.push_str("\\t"),
+			'\r' => unescaped.push_str("\\r"),
+			'"' => unescaped.push_str("\\\""),
+			'\\' => unescaped.push_str("\\\\"),
+			character => unescaped.push(character),
+		}
+	}
+	unescaped
 }
 
 #[cfg(test)]
@@ -181,6 +265,45 @@ mod tests {
 		assert_eq!(Lexer::new("\"string").next(), Some(Err(ParserError::UnmatchedQuote)));
 	}
 
+	#[test]
+	fn lexer_operators() {
+		let tokens: Vec<_> = Lexer::new("a >= 5").collect();
+		assert_eq!(tokens, vec![Ok(Token::Identifier("a".to_owned())),
+			Ok(Token::Identifier(">=".to_owned())), Ok(Token::Numeric(5.0))]);
+		assert_eq!(Lexer::new("<5").next(), Some(Ok(Token::Identifier("<".to_owned()))));
+	}
+
+	#[test]
+	fn lexer_source() {
+		let mut lexer = Lexer::new("pause\n\"ä\" end");
+		lexer.identifier().unwrap();
+		assert_eq!(lexer.consumed_source(), "pause");
+		lexer.token().unwrap();
+		lexer.string().unwrap();
+		assert_eq!(lexer.consumed_source(), "pause\n\"ä\"");
+		assert_eq!(lexer.remaining_source(), " end");
+	}
+
+	#[test]
+	fn escape_sequences() {
+		assert_eq!(escape("\\n".to_owned()), "\n");
+		assert_eq!(escape("\\t".to_owned()), "\t");
+		assert_eq!(escape("\\r".to_owned()), "\r");
+		assert_eq!(escape("\\\"".to_owned()), "\"");
+		assert_eq!(escape("\\\\".to_owned()), "\\");
+		assert_eq!(escape("\\\\n".to_owned()), "\\n");
+		assert_eq!(escape("\\a".to_owned()), "\\a");
+		assert_eq!(escape("\\{".to_owned()), "\\{");
+		assert_eq!(escape("string\\".to_owned()), "string\\");
+	}
+
+	#[test]
+	fn unescape_sequences() {
+		assert_eq!(unescape("\n\t\r\"\\"), "\\n\\t\\r\\\"\\\\");
+		let string = "tab\tquote\"slash\\n";
+		assert_eq!(escape(unescape(string)), string);
+	}
+
 	#[test]
 	fn lexer_scope() {
 		assert_eq!(Lexer::new("\t").next(), None);
@@ -1,123 +1,421 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::ops::Index;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use ggez::audio::{SoundData, SoundSource, Source};
 use ggez::graphics::{self, Image};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 
-use character::{CharacterName, Characters, Instance, InstanceName, StateName};
-use interface::{Button, Render, RenderText, TextBox};
+use audio::Music;
+use character::{CharacterName, CharacterState, Characters, Instance, InstanceName, SpawnDefaults, Stage, StageSnapshot, StateName};
+use interface::{Button, Render, RenderText, TextBox, TransitionState};
+use script_types::{Comparison, Placement};
 
 use animation::*;
 
+/// Implements constructors and dereferencing for a name that wraps a shared string.
+/// Names are cheap to clone and names parsed from the same script share their strings.
+macro_rules! name {
+	($name:ident) => {
+		impl $name {
+			/// Creates a name from a string.
+			pub fn new<S: Into<std::sync::Arc<str>>>(name: S) -> Self {
+				$name(name.into())
+			}
+		}
+
+		impl std::ops::Deref for $name {
+			type Target = str;
+
+			fn deref(&self) -> &str {
+				&self.0
+			}
+		}
+
+		impl From<&str> for $name {
+			fn from(name: &str) -> Self {
+				$name::new(name)
+			}
+		}
+
+		impl From<String> for $name {
+			fn from(name: String) -> Self {
+				$name::new(name)
+			}
+		}
+	};
+}
+
 pub mod animation;
+pub mod audio;
 pub mod game;
 pub mod lexer;
 pub mod parser;
 pub mod interface;
 pub mod character;
+pub mod script_types;
 
-#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, Eq, PartialEq)]
+#[serde(transparent)]
 pub struct FlagName(pub String);
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum Command {
 	/// Changes the state of an instance.
+	/// The `expression` keyword is an alias that changes the state without an animation.
 	Change(InstanceName, StateName, Option<AnimationDeclaration>),
 	/// Displays text associated with a character.
-	Dialogue(Option<CharacterName>, String),
+	/// The text is aligned with the default alignment from the settings if no alignment is specified.
+	/// Names of counters and flags between braces, such as `{score}`, are replaced with their values.
+	Dialogue(Option<CharacterName>, String, Option<TextAlignment>),
 	/// Presents the user with a list of options and jumps to a label
 	/// depending on the option that is chosen.
 	Diverge(Vec<(String, Label)>),
+	/// Jumps to a label chosen at random, where each label is chosen in proportion to its weight.
+	/// The chosen label is recorded as a divergence so that loading a save makes the same choice.
+	Random(Vec<(u32, Label)>),
 	/// Jumps to a label if the flag has been set.
 	If(FlagName, Label),
+	/// Jumps to a label if comparing the value of a counter with a number holds.
+	/// Counters that have not been set have a value of zero.
+	IfCounter(String, Comparison, i32, Label),
+	/// Jumps to a label if the value of a counter is between two numbers inclusively.
+	IfBetween(String, i32, i32, Label),
+	/// Sets the value of a counter.
+	SetCounter(String, i32),
+	/// Adds a number to the value of a counter.
+	AddCounter(String, i32),
 	/// Sets a flag.
 	Flag(FlagName),
 	/// Removes a flag if it has been set.
+	/// Persistent flags are also removed.
 	Unflag(FlagName),
+	/// Sets a flag that persists across playthroughs.
+	Persist(FlagName),
+	/// Marks the start of a chapter and unlocks it in the chapter select for every playthrough.
+	Chapter(Chapter),
 	/// Makes an instance visible.
 	Show(InstanceName, Option<AnimationDeclaration>),
 	/// Makes an instance invisible.
 	Hide(InstanceName, Option<AnimationDeclaration>),
+	/// Sets the opacity of an instance between zero and one without changing whether it is visible.
+	/// The opacity is kept when the instance is hidden and shown again.
+	Opacity(InstanceName, f32, Option<AnimationDeclaration>),
 	/// Sets the position of an instance.
-	Position(InstanceName, (f32, f32), Option<AnimationDeclaration>),
+	/// Positions can be given in pixels or as a named slot from the settings.
+	Position(InstanceName, Placement, Option<AnimationDeclaration>),
+	/// Moves an instance by an offset from the position it has once any animation finishes.
+	/// Any running animation is finished first so that consecutive moves add up.
+	Move(InstanceName, (f32, f32), Option<AnimationDeclaration>),
+	/// Sets the position of every instance of a character.
+	CharacterPosition(CharacterName, Placement, Option<AnimationDeclaration>),
+	/// Makes every instance of a character visible.
+	CharacterShow(CharacterName, Option<AnimationDeclaration>),
+	/// Makes every instance of a character invisible.
+	CharacterHide(CharacterName, Option<AnimationDeclaration>),
+	/// Kills every instance of a character.
+	CharacterKill(CharacterName, Option<AnimationDeclaration>),
+	/// Moves an instance from a start position to an end position over an amount of milliseconds.
+	/// The instance is placed at the start position regardless of where it was.
+	TweenPosition(InstanceName, Placement, Placement, f32),
 	/// Kills an instance.
 	Kill(InstanceName, Option<AnimationDeclaration>),
+	/// Changes the image of an instance to an image that is not a state of its character.
+	Sprite(InstanceName, PathBuf, Option<AnimationDeclaration>),
+	/// Covers the screen with an image while the scene changes.
+	/// The script continues once the image covers the screen and
+	/// the image fades out to reveal the next scene.
+	SceneTransition(PathBuf, f32),
+	/// Sets the font and size of both the dialogue and the character name.
+	/// An empty path resets them to the default font.
+	TextFont(PathBuf, f32),
+	/// Sets the font and size of the character name.
+	/// An empty path resets it to the default font.
+	NameFont(PathBuf, f32),
+	/// Sets the font and size of the dialogue.
+	/// An empty path resets it to the default font.
+	DialogueFont(PathBuf, f32),
+	/// Copies an instance to a new name at a specified position.
+	/// The copy has the values the instance will have once any animation finishes.
+	Copy(InstanceName, InstanceName, Placement),
+	/// Changes the name of an instance.
+	Rename(InstanceName, InstanceName),
+	/// Mirrors an instance horizontally so that it faces the other way.
+	/// Flipping an instance that is already flipped restores it.
+	Flip(InstanceName, Option<AnimationDeclaration>),
+	/// Mirrors an instance vertically.
+	FlipVertical(InstanceName),
+	/// Draws the instances in the order they are listed.
+	/// Instances that are not listed are drawn afterwards.
+	SetDrawOrder(Vec<InstanceName>),
+	/// Stops drawing the instances in a specified order.
+	ClearDrawOrder,
+	/// Draws an instance in front of every other instance.
+	/// The draw order is set to the current order with the instance moved to the end.
+	Front(InstanceName),
+	/// Draws an instance behind every other instance.
+	/// The draw order is set to the current order with the instance moved to the start.
+	Back(InstanceName),
+	/// Applies the same show, hide or kill animation to every instance on the stage.
+	AnimateAll(BatchAnimation, AnimationDeclaration),
 	/// Creates an instance of a character onto the screen at a specified position.
 	/// If no instance name is specified, the character name is used.
-	Spawn(CharacterName, StateName, (f32, f32), Option<InstanceName>, Option<AnimationDeclaration>),
+	/// If no state or position is specified, the defaults of the character are used.
+	/// The `enter` keyword is an alias that takes a state and position and no animation.
+	Spawn(CharacterName, Option<StateName>, Option<Placement>, Option<InstanceName>, Option<AnimationDeclaration>),
 	/// Sets the background image.
 	Stage(PathBuf),
+	/// Moves the camera by an offset over an amount of milliseconds.
+	/// The background and the stage move while text boxes and buttons stay in place.
+	/// A duration of zero moves the camera immediately.
+	Pan(f32, f32, f32),
 	/// Jumps directly to a label.
-	Jump(Label),
+	/// If a `fade` transition is specified, the screen fades to black before the jump
+	/// and fades back in once the script after the label is waiting for input.
+	Jump(Label, Option<AnimationDeclaration>),
+	/// Jumps back to a label to repeat a section of the script.
+	Repeat(Label),
+	/// Jumps back to a label only while the flag is set.
+	RepeatIf(Label, FlagName),
+	/// Jumps back to a label a fixed number of times before continuing.
+	RepeatN(Label, u32),
 	/// Sets the currently playing music. Music audio is repeated.
 	Music(PathBuf),
+	/// Sets the currently playing music with the start and end of a loop in seconds.
+	/// The music plays from the beginning and then repeats the section between the loop points.
+	MusicLoop(PathBuf, f32, f32),
+	/// Pauses the currently playing music so that it can be resumed.
+	PauseMusic,
+	/// Resumes music that was paused.
+	ResumeMusic,
 	/// Plays a sound effect.
 	Sound(PathBuf),
 	/// Waits for user interaction before continuing.
-	Pause,
+	/// The text box and character name are removed first if the pause clears them,
+	/// and the script continues on its own after a timeout in milliseconds if one is given.
+	Pause(bool, Option<f32>),
+	/// Waits for an amount of milliseconds before continuing.
+	/// Unlike `Pause`, the script continues on its own without the player clicking,
+	/// and unlike `Sync`, it waits for a fixed time instead of for animations.
+	/// Clicking continues the script early.
+	Delay(f32),
+	/// Creates a named group of instances.
+	/// Commands that act on an instance can act on every instance
+	/// in a group by prefixing the group name with `#`.
+	Group(String, Vec<InstanceName>),
+	/// Removes a group of instances.
+	UnGroup(String),
+	/// Registers an alternative name that refers to an instance.
+	/// Aliases only redirect names and do not move or copy the instance.
+	Alias(InstanceName, InstanceName),
+	/// Removes an alternative name for an instance.
+	RemoveAlias(InstanceName),
+	/// Removes loaded resources from memory.
+	/// If no path is specified, all resources that are not in use are removed.
+	/// Resources that are removed are loaded again when they are next used.
+	Unload(Option<PathBuf>),
+	/// Waits for all animations on the stage, the camera and any scene transition
+	/// that is fading out to finish before continuing without the player clicking.
+	/// Unlike advancing, the animations are not skipped, but clicking skips them and continues.
+	Sync,
+	/// Stops the script for one frame so that code outside of the script can run.
+	/// The yield callback of the game is called during the frame and can keep the script stopped.
+	Yield,
+	/// Runs several commands one after another in the same step so that they start on the same frame.
+	/// Commands that wait or change which command runs next cannot be run together.
+	Together(Vec<Command>),
+	/// Finishes the animations of every instance without waiting for them.
+	/// The script continues immediately.
+	StopAllAnimations,
+	/// Finishes the animations of every instance without waiting for them.
+	/// This is the same as `StopAllAnimations`.
+	FinishAllAnimations,
+	/// Finishes the animation of an instance without waiting for it.
+	StopAnimation(InstanceName),
+	/// Panics in developer mode if whether the flag is set does not match the expected value.
+	/// Assertions are for testing scripts and should be removed before the game is released.
+	Assert(FlagName, bool),
+	/// Panics in developer mode if the label was not the most recent label passed.
+	/// Assertions are for testing scripts and should be removed before the game is released.
+	AssertLabel(Label),
+	/// Panics in developer mode if the value of the counter does not match the expected value.
+	/// Assertions are for testing scripts and should be removed before the game is released.
+	AssertCounter(String, i32),
+	/// Prints a message in developer mode and does nothing otherwise.
+	Log(String),
+	/// Prints the value of a counter after a message in developer mode and does nothing otherwise.
+	LogCounter(String, String),
+	/// Prints whether a flag is set in developer mode and does nothing otherwise.
+	LogFlag(FlagName),
 }
 
 impl Command {
 	pub fn execute(&self, ctx: &mut ggez::Context, state: &mut ScriptState,
 	               render: &mut Render, script: &Script, settings: &Settings) {
+		if let Some(InstanceName(name)) = self.instance() {
+			if let Some(group) = name.strip_prefix('#') {
+				let instances = state.expand(&InstanceName(name.clone()));
+				if instances.iter().any(|InstanceName(instance)| instance == name) {
+					return report(render, settings, format!("Group: {}, does not exist", group));
+				}
+
+				return instances.into_iter().for_each(|instance| {
+					let mut command = self.clone();
+					*command.instance_mut().unwrap() = instance;
+					command.execute(ctx, state, render, script, settings);
+				});
+			}
+		}
+
+		match self.resolve_instances(state) {
+			Some(command) => command.run(ctx, state, render, script, settings),
+			None => self.run(ctx, state, render, script, settings),
+		}
+	}
+
+	/// Creates a copy of the command where aliases are replaced by the instances they refer to
+	/// and groups in a draw order are expanded. Returns `None` if nothing needs to be replaced.
+	/// This is the only place that aliases are resolved so commands can use instance names directly.
+	fn resolve_instances(&self, state: &ScriptState) -> Option<Command> {
+		if let Command::SetDrawOrder(instances) = self {
+			return Some(Command::SetDrawOrder(instances.iter()
+				.flat_map(|instance| state.expand(instance))
+				.map(|instance| state.alias(&instance).clone()).collect()));
+		}
+
+		let instance = match self {
+			Command::Copy(instance, _, _) | Command::Rename(instance, _) => Some(instance),
+			_ => self.instance(),
+		}.and_then(|instance| state.aliases.get(instance))?;
+		let mut command = self.clone();
+		match &mut command {
+			Command::Copy(name, _, _) | Command::Rename(name, _) => *name = instance.clone(),
+			command => *command.instance_mut().unwrap() = instance.clone(),
+		}
+		Some(command)
+	}
+
+	/// Shows the dialogue or branches of a command without any of its other effects,
+	/// such as passing the dialogue to the dialogue hook, so that they can be shown again.
+	/// Returns the dialogue that is shown, which may be the first page of a longer dialogue.
+	pub fn display(&self, ctx: &mut ggez::Context, state: &mut ScriptState,
+	               render: &mut Render, script: &Script, settings: &Settings) -> Option<String> {
 		match self {
-			Command::Change(instance, state, animation) => {
+			Command::Dialogue(character, string, alignment) => {
+				let (text_box, name_box) = interface::layout::dialogue_boxes(settings);
+				let margin = interface::layout::margin(settings);
+				let alignment = alignment.unwrap_or(match character {
+					Some(_) => settings.dialogue_alignment,
+					None => settings.narration_alignment,
+				});
+
+				let mut string = state.interpolate(string, settings);
+				let text = RenderText::empty(string.clone(), settings.foreground_colour)
+					.with_colour_fade(settings.text_fade_chars);
+				let mut text = TextBox::from_rectangle(text, text_box, settings.background_colour).padding(margin)
+					.font(script.font(&state.dialogue_font)).text_scale(settings.ui_scale).alignment(alignment.into())
+					.max_lines(settings.max_dialogue_lines);
+				if settings.max_dialogue_lines.is_some() && text.overflows(ctx) {
+					let mut pages = text.pages(ctx).into_iter();
+					string = pages.next().unwrap();
+					text.text = RenderText::empty(string.clone(), settings.foreground_colour)
+						.with_colour_fade(settings.text_fade_chars);
+					let delimiters = (settings.interpolation_prefix, settings.interpolation_suffix);
+					state.pending_dialogue.extend(pages.map(|page| Command::Dialogue(character.clone(),
+						escape_interpolation(&page, delimiters), Some(alignment))));
+				}
+
+				render.quick_menu.layout(text.rectangle(), settings);
+				render.text = Some(text);
+				if settings.speaker_highlight {
+					render.stage.highlight_speaker(character.as_ref(), settings.inactive_tint);
+				}
+
+				render.speaker = character.clone();
+				if let (Some(character), Some(name_box)) = (character, name_box) {
+					let text = RenderText::new(character.0.to_string(), settings.foreground_colour);
+					render.character = Some(TextBox::from_rectangle(text, name_box, settings.background_colour)
+						.padding(margin).font(script.font(&state.name_font)).text_scale(settings.ui_scale));
+					render.fit_name_box(ctx, settings);
+				}
+
+				Some(string)
+			}
+			Command::Diverge(branches) => {
+				let margin = interface::layout::margin(settings);
+				let layout = interface::layout::branch_buttons(settings, branches.len());
+				render.branches = branches.iter().zip(layout).enumerate().map(|(index, ((string, label), rectangle))| {
+					let text = RenderText::new(string.clone(), settings.foreground_colour);
+					let mut button = Button::new(TextBox::from_rectangle(text, rectangle, settings.background_colour)
+						.alignment(graphics::Align::Center).padding(margin).text_scale(settings.ui_scale),
+						settings.background_colour, settings.secondary_colour);
+					button.keyboard_index = index;
+					(button, label.clone())
+				}).collect();
+				render.branch_scroll = 0.0;
+				None
+			}
+			_ => None,
+		}
+	}
+
+	fn run(&self, ctx: &mut ggez::Context, state: &mut ScriptState,
+	       render: &mut Render, script: &Script, settings: &Settings) {
+		match self {
+			Command::Together(commands) => commands.iter()
+				.for_each(|command| command.execute(ctx, state, render, script, settings)),
+			Command::Change(instance, new_state, animation) => {
 				let instance = &mut render.stage[instance];
+				let character = &script.characters[(&instance.character, new_state)];
+				state.play_spawn_sound(ctx, script, character, settings);
 				if let Some(animation) = animation {
-					let change_animation = ChangeAnimation::new(animation.arguments.clone(), &instance.character, script, state);
+					let change_animation = ChangeAnimation::new(animation.arguments.clone(), &instance.character, script, new_state);
 					let animation = script.animations.change.get(&animation.name).unwrap_or_else(|| panic!("Error finding animation: {}", animation.name))
 						.initialise(change_animation);
 					instance.add_animation(animation);
+					instance.state = new_state.clone();
+					instance.sprite = None;
 				} else {
+					let (flip_h, flip_v) = (instance.flip_h, instance.flip_v);
 					*instance = Instance::new(script, instance.character.clone(),
-						state, instance.position);
+						new_state, instance.position);
+					instance.flip_h = flip_h;
+					instance.flip_v = flip_v;
 				}
 			}
-			Command::Dialogue(character, string) => {
-				let height = settings.height * settings.text_box_height - settings.interface_margin;
-				let width = settings.width - 2.0 * settings.interface_margin;
-				let size = (width, height - settings.interface_margin);
-				let position = (settings.interface_margin, settings.height - height);
-				let text = RenderText::empty(string.clone(), settings.foreground_colour);
-				render.text = Some(TextBox::new(text, position, size,
-					settings.background_colour).padding(settings.interface_margin));
-
-				if let Some(CharacterName(character)) = character {
-					let character_height = settings.height * settings.character_name_height;
-					let position = (settings.interface_margin, settings.height -
-						(height + settings.interface_margin + character_height));
-					let width = settings.width * settings.character_name_width - settings.interface_margin;
-					let text = RenderText::new(character.clone(), settings.foreground_colour);
-					render.character = Some(TextBox::new(text, position, (width, character_height),
-						settings.background_colour).padding(settings.interface_margin))
+			Command::Sprite(instance, path, animation) => {
+				let instance = &mut render.stage[instance];
+				let sprite = CharacterState::new(path.clone());
+				if let Some(animation) = animation {
+					let change_animation = ChangeAnimation::from_state(animation.arguments.clone(), script, &sprite);
+					let animation = script.animations.change.get(&animation.name).unwrap_or_else(|| panic!("Error finding animation: {}", animation.name))
+						.initialise(change_animation);
+					instance.add_animation(animation);
+					instance.sprite = Some(sprite.image);
+				} else {
+					instance.set_sprite(script, &sprite);
 				}
 			}
-			Command::Diverge(branches) => {
-				let button_height = settings.height * settings.branch_button_height;
-				let button_width = settings.width * settings.branch_button_width;
-				let position_x = (settings.width - button_width) / 2.0;
-
-				let size = (button_width, button_height);
-				let true_height = button_height + settings.interface_margin;
-				let mut position_y = (settings.height - branches.len() as f32 * true_height) / 2.0;
-
-				render.branches = branches.iter().map(|(string, label)| {
-					let text = RenderText::new(string.clone(), settings.foreground_colour);
-					let position = (position_x, position_y);
-					position_y += true_height;
-
-					(Button::new(TextBox::new(text, position, size, settings.background_colour)
-						.alignment(graphics::Align::Center).padding(settings.interface_margin),
-						settings.background_colour, settings.secondary_colour), label.clone())
-				}).collect();
+			Command::Dialogue(character, _, _) => {
+				let string = self.display(ctx, state, render, script, settings).unwrap_or_default();
+				announce_dialogue(state, settings, character.as_ref(), &string);
 			}
-			Command::If(flag, label) => if state.flags.contains(flag) {
-				state.next_target = Some(script.labels[label].clone());
+			Command::Diverge(_) => { self.display(ctx, state, render, script, settings); }
+			Command::If(_, _) | Command::IfCounter(_, _, _, _) | Command::IfBetween(_, _, _, _) |
+			Command::SetCounter(_, _) | Command::AddCounter(_, _) | Command::Group(_, _) | Command::UnGroup(_) | Command::Alias(_, _) |
+			Command::RemoveAlias(_) | Command::Flag(_) | Command::Unflag(_) | Command::Persist(_) |
+			Command::Chapter(_) | Command::Jump(_, None) | Command::Repeat(_) | Command::RepeatIf(_, _) | Command::RepeatN(_, _) |
+			Command::TextFont(_, _) | Command::NameFont(_, _) | Command::DialogueFont(_, _) =>
+				self.execute_state(state, script),
+			Command::Jump(_, Some(animation)) => {
+				self.execute_state(state, script);
+				let duration = animation.arguments.first().and_then(|duration| *duration)
+					.unwrap_or(TransitionState::DEFAULT_DURATION);
+				render.transition = Some(TransitionState::black(duration));
 			}
-			Command::Flag(flag) => { state.flags.insert(flag.clone()); }
-			Command::Unflag(flag) => { state.flags.remove(flag); }
 			Command::Show(instance, animation) => {
 				if let Some(animation) = animation {
 					let animation_producer = script.animations.show.get(&animation.name).unwrap_or_else(|| panic!("Error finding animation named: {}", animation.name));
@@ -134,37 +432,100 @@ impl Command {
 					render.stage[instance].visible = false
 				}
 			}
-			Command::Position(instance, position, animation) => {
+			Command::Opacity(instance, opacity, animation) => {
+				let instance = &mut render.stage[instance];
+				let opacity = opacity.clamp(0.0, 1.0);
 				if let Some(animation) = animation {
-					let position_animation = PositionAnimation {
-						destination: *position,
-						arguments: animation.arguments.clone(),
-					};
-					let animation = script.animations.position.get(&animation.name)
-						.unwrap_or_else(|| panic!("Error finding animation named `{}`", animation.name))
-						.initialise(position_animation);
-					render.stage[instance].add_animation(animation);
+					let animation = script.animations.opacity.get(&animation.name)
+						.unwrap_or_else(|| panic!("Error finding animation named: {}", animation.name))
+						.initialise(OpacityAnimation { opacity, arguments: animation.arguments.clone() });
+					instance.add_animation(animation);
 				} else {
-					render.stage[instance].position = *position;
+					instance.colour[3] = opacity;
 				}
 			}
-			Command::Kill(instance, animation) => {
+			Command::Flip(instance, animation) => {
+				let instance = &mut render.stage[instance];
 				if let Some(animation) = animation {
+					let animation_producer = script.animations.flip.get(&animation.name).unwrap_or_else(|| panic!("Error finding animation named: {}", animation.name));
+					instance.add_animation(animation_producer.initialise(FlipAnimation { arguments: animation.arguments.clone() }))
+				} else {
+					instance.flip_h = !instance.flip_h
+				}
+			}
+			Command::AnimateAll(kind, animation) => {
+				let arguments = animation.arguments.clone();
+				let (animations, view_dimensions) = (&script.animations, (settings.width, settings.height));
+				match kind {
+					BatchAnimation::Show => render.stage.animate_all_show(animations, &animation.name, arguments, view_dimensions),
+					BatchAnimation::Hide => render.stage.animate_all_hide(animations, &animation.name, arguments, view_dimensions),
+					BatchAnimation::Kill => render.stage.animate_all_kill(animations, &animation.name, arguments, view_dimensions),
+				}.unwrap_or_else(|error| panic!("{}", error))
+			}
+			Command::FlipVertical(instance) => {
+				let instance = &mut render.stage[instance];
+				instance.flip_v = !instance.flip_v;
+			}
+			Command::SetDrawOrder(instances) => render.draw_order = Some(instances.clone()),
+			Command::ClearDrawOrder => render.draw_order = None,
+			Command::Front(instance) | Command::Back(instance) => {
+				if !render.stage.contains(instance) {
+					let InstanceName(name) = instance;
+					return report(render, settings, format!("Instance: {}, cannot be reordered because it does not exist", name));
+				}
+
+				let draw_order = render.draw_order.as_deref();
+				render.draw_order = Some(match self {
+					Command::Front(_) => render.stage.raise(draw_order, instance),
+					_ => render.stage.lower(draw_order, instance),
+				});
+			}
+			Command::Position(instance, position, animation) => {
+				let instance = &mut render.stage[instance];
+				let destination = settings.clamp_position(resolve_placement(position, settings));
+				move_instance(instance, destination, animation.as_ref(), script);
+			}
+			Command::Move(instance, (x, y), animation) => {
+				let instance = &mut render.stage[instance];
+				instance.finish_animation();
+				let destination = move_destination(settings, instance.position, (*x, *y));
+				move_instance(instance, destination, animation.as_ref(), script);
+			}
+			Command::CharacterPosition(character, _, _) | Command::CharacterShow(character, _) |
+			Command::CharacterHide(character, _) | Command::CharacterKill(character, _) =>
+				render.stage.instances_of(character).into_iter()
+					.filter_map(|instance| self.instance_command(instance))
+					.for_each(|command| command.execute(ctx, state, render, script, settings)),
+			Command::TweenPosition(instance, start, end, duration) => {
+				let instance = &mut render.stage[instance];
+				instance.finish_animation();
+				let start = resolve_placement(start, settings);
+				instance.position = start;
+				instance.add_animation(animation::tween(start, resolve_placement(end, settings), *duration));
+			}
+			Command::Kill(instance, animation) => {
+				if let Some(animation) = animation.as_ref().filter(|_| render.stage.contains(instance)) {
 					let animation = script.animations.kill.get(&animation.name)
 						.unwrap_or_else(|| panic!("Error finding animation: {}", animation.name))
 						.initialise(KillAnimation { arguments: animation.arguments.clone(), view_dimensions: (settings.width, settings.height) });
 					render.stage[instance].add_animation(animation);
 					render.stage[instance].tbk = true;
-				} else {
-					render.stage.remove(instance)
+				} else if render.stage.remove(instance).is_none() {
+					let InstanceName(name) = instance;
+					report(render, settings, format!("Instance: {}, cannot be killed because it does not exist", name));
 				}
 			}
-			Command::Spawn(character, state, position, instance_name, animation) => {
+			Command::Spawn(character, state_name, position, instance_name, animation) => {
 				let CharacterName(character_name) = character;
-				let instance = Instance::new(script, character.clone(), state, *position);
+				let position = position.as_ref().map(|position| resolve_placement(position, settings));
+				let (state_name, position) = script.spawn_parameters(character, state_name.as_ref(), position)
+					.unwrap_or_else(|error| panic!("{}", error));
+				let instance = Instance::new(script, character.clone(), state_name, position);
 				let instance_name = instance_name.clone().unwrap_or_else(||
 					InstanceName(character_name.clone()));
+				warn_replaced(render, settings, &instance_name);
 				render.stage.spawn(instance_name.clone(), instance);
+				state.play_spawn_sound(ctx, script, &script.characters[(character, state_name)], settings);
 				if let Some(animation) = animation {
 					let animation = script.animations.spawn.get(&animation.name)
 						.unwrap_or_else(|| panic!("Error finding animation named `{}`", animation.name))
@@ -172,27 +533,291 @@ impl Command {
 					render.stage[&instance_name].add_animation(animation);
 				}
 			}
-			Command::Stage(path) => render.background = Some(script.images[path].clone()),
-			Command::Jump(label) => state.next_target = Some(script.labels[label].clone()),
+			Command::Copy(instance, new_name, position) => {
+				warn_replaced(render, settings, new_name);
+				if !render.stage.duplicate(instance, new_name.clone(), resolve_placement(position, settings)) {
+					let InstanceName(name) = instance;
+					report(render, settings, format!("Instance: {}, cannot be copied because it does not exist", name));
+				} else if let Some(draw_order) = &mut render.draw_order {
+					character::copy_in_order(draw_order, instance, new_name);
+				}
+			}
+			Command::Rename(instance, new_name) => {
+				warn_replaced(render, settings, new_name);
+				if !render.stage.rename(instance, new_name.clone()) {
+					let InstanceName(name) = instance;
+					report(render, settings, format!("Instance: {}, cannot be renamed because it does not exist", name));
+				} else if let Some(draw_order) = &mut render.draw_order {
+					character::rename_in_order(draw_order, instance, new_name);
+				}
+			}
+			Command::Stage(path) => {
+				render.background = Some(script.image(path).clone());
+				state.background = Some(path.clone());
+			}
+			Command::Yield => state.yielded = true,
+			Command::Pause(_, _) | Command::Delay(_) => self.wait(state, render),
+			Command::Pan(offset_x, offset_y, duration) => render.pan((*offset_x, *offset_y), *duration),
 			Command::Music(path) => {
-				let mut source = Source::from_data(ctx, script.audio[path].clone());
-				source.iter_mut().for_each(|source| source.set_volume(settings.music_volume));
-				source.iter_mut().for_each(|source| source.set_repeat(true));
-				source.iter_mut().try_for_each(Source::play).unwrap();
-				state.music = Some(source.unwrap());
+				let data = script.sound_data(path).clone();
+				state.music = Some(Music::repeat(ctx, data, settings.music_volume).unwrap());
+				state.music_path = Some(path.clone());
+				state.music_loop = None;
+				state.music_paused = false;
+			}
+			Command::MusicLoop(path, start, end) => {
+				let data = script.sound_data(path).clone();
+				state.music = Music::looped(ctx, data, (*start, *end), settings.music_volume)
+					.map_err(|error| eprintln!("Warning: Failed to play music loop at path: {}, because: {}",
+						path.display(), error)).ok();
+				state.music_path = Some(path.clone());
+				state.music_loop = Some((*start, *end));
+				state.music_paused = false;
+			}
+			Command::PauseMusic => {
+				state.music.iter_mut().for_each(Music::pause);
+				state.music_paused = state.music.is_some();
+			}
+			Command::ResumeMusic => {
+				state.music.iter_mut().for_each(Music::resume);
+				state.music_paused = false;
+			}
+			Command::Sound(path) => state.play_sound(ctx, script.sound_data(path).clone(), settings),
+			Command::SceneTransition(path, duration) => render.transition =
+				Some(TransitionState::new(script.image(path).clone(), *duration).previous(game::take_screenshot(ctx)
+					.and_then(|screenshot| Image::from_rgba8(ctx, screenshot.width() as u16,
+						screenshot.height() as u16, &screenshot)).ok())),
+			Command::StopAllAnimations | Command::FinishAllAnimations => render.stage.finish_animation(),
+			Command::StopAnimation(instance) => render.stage[instance].finish_animation(),
+			Command::Assert(_, _) | Command::AssertLabel(_) | Command::AssertCounter(_, _) if settings.developer =>
+				self.check(state, script).unwrap_or_else(|error| panic!("{}", error)),
+			Command::Log(_) | Command::LogCounter(_, _) | Command::LogFlag(_) if settings.developer =>
+				eprintln!("[kanna] {}", self.log_message(state).unwrap()),
+			Command::Sync | Command::Unload(_) | Command::Random(_) |
+			Command::Assert(_, _) | Command::AssertLabel(_) | Command::AssertCounter(_, _) |
+			Command::Log(_) | Command::LogCounter(_, _) | Command::LogFlag(_) => (),
+		}
+	}
+
+	/// Creates the message that a log command prints for the state of the script.
+	pub fn log_message(&self, state: &ScriptState) -> Option<String> {
+		Some(match self {
+			Command::Log(message) => message.clone(),
+			Command::LogCounter(counter, message) if message.is_empty() =>
+				format!("Counter: {} = {}", counter, state.counter(counter)),
+			Command::LogCounter(counter, message) =>
+				format!("{}: {} = {}", message, counter, state.counter(counter)),
+			Command::LogFlag(flag) => {
+				let FlagName(name) = flag;
+				format!("Flag: {} is {}", name, if state.flag(flag) { "set" } else { "not set" })
+			}
+			_ => return None,
+		})
+	}
+
+	/// Checks whether an assertion holds for the state of the script.
+	/// Commands that are not assertions always hold.
+	pub fn check(&self, state: &ScriptState, script: &Script) -> Result<(), String> {
+		match self {
+			Command::Together(commands) => commands.iter()
+				.try_for_each(|command| command.check(state, script)),
+			Command::Assert(flag, expected) if state.flag(flag) != *expected => {
+				let FlagName(name) = flag;
+				let set = |value| if value { "set" } else { "not set" };
+				Err(format!("Assertion failed: flag: {}, is {} but was expected to be {}",
+					name, set(!expected), set(*expected)))
+			}
+			Command::AssertCounter(counter, expected) if state.counter(counter) != *expected =>
+				Err(format!("Assertion failed: counter: {}, is {} but was expected to be {}",
+					counter, state.counter(counter), expected)),
+			Command::AssertLabel(label) => {
+				let Label(name) = label;
+				let Target(expected) = script.labels.get(label).ok_or_else(||
+					format!("Assertion failed: label: {}, does not exist", name))?;
+				match script.nearest_preceding_label(&state.target) {
+					Some(last) if script.labels[last].0 == *expected => Ok(()),
+					Some(Label(last)) => Err(format!("Assertion failed: label: {}, \
+						was expected to be passed but label: {}, was passed", name, last)),
+					None => Err(format!("Assertion failed: label: {}, \
+						was expected to be passed but no label was passed", name)),
+				}
 			}
-			Command::Sound(path) => {
-				let mut source = Source::from_data(ctx, script.audio[path].clone());
-				source.iter_mut().for_each(|source| source.set_volume(settings.sound_volume));
-				source.iter_mut().try_for_each(Source::play).unwrap();
-				state.music = Some(source.unwrap());
+			_ => Ok(()),
+		}
+	}
+
+	/// Starts waiting for a pause or delay, clearing the text box if the pause clears it.
+	pub fn wait(&self, state: &mut ScriptState, render: &mut Render) {
+		match self {
+			Command::Pause(clear, timeout) => {
+				if *clear {
+					render.text = None;
+					render.character = None;
+				}
+				state.delay_until = timeout.and_then(instant_after);
 			}
-			Command::Pause => (),
+			Command::Delay(duration) => state.delay_until = instant_after(*duration),
+			_ => (),
+		}
+	}
+
+	/// Executes the parts of a command that only affect the state of the script.
+	/// Commands that draw to the screen or play audio do nothing.
+	pub fn execute_state(&self, state: &mut ScriptState, script: &Script) {
+		match self {
+			Command::Together(commands) => commands.iter()
+				.for_each(|command| command.execute_state(state, script)),
+			Command::If(flag, label) if state.flag(flag) =>
+				state.next_target = Some(script.labels[label].clone()),
+			Command::IfCounter(counter, comparison, number, label) if comparison.compare(state.counter(counter), *number) =>
+				state.next_target = Some(script.labels[label].clone()),
+			Command::IfBetween(counter, low, high, label) if (*low..=*high).contains(&state.counter(counter)) =>
+				state.next_target = Some(script.labels[label].clone()),
+			Command::SetCounter(counter, value) => { state.counters.insert(counter.clone(), *value); }
+			Command::AddCounter(counter, value) => {
+				let count = state.counters.entry(counter.clone()).or_insert(0);
+				*count = count.saturating_add(*value);
+			}
+			Command::Group(group, instances) => { state.groups.insert(group.clone(), instances.clone()); }
+			Command::UnGroup(group) => { state.groups.remove(group); }
+			Command::Alias(alias, instance) => { state.aliases.insert(alias.clone(), instance.clone()); }
+			Command::RemoveAlias(alias) => { state.aliases.remove(alias); }
+			Command::Flag(flag) => { state.flags.insert(flag.clone()); }
+			Command::Unflag(flag) => {
+				state.flags.remove(flag);
+				state.persistent.flags.remove(flag);
+			}
+			Command::Persist(flag) => { state.persistent.flags.insert(flag.clone()); }
+			Command::Chapter(chapter) => { state.persistent.chapters.insert(chapter.label.clone()); }
+			Command::Jump(label, _) | Command::Repeat(label) =>
+				state.next_target = Some(script.labels[label].clone()),
+			Command::RepeatIf(label, flag) if state.flag(flag) =>
+				state.next_target = Some(script.labels[label].clone()),
+			Command::RepeatN(label, count) => {
+				let counter = state.loop_counters.entry(label.clone()).or_insert(*count);
+				match *counter {
+					0 => { state.loop_counters.remove(label); }
+					_ => {
+						*counter -= 1;
+						state.next_target = Some(script.labels[label].clone());
+					}
+				}
+			}
+			Command::TextFont(path, size) => {
+				state.name_font = font_declaration(path, *size);
+				state.dialogue_font = font_declaration(path, *size);
+			}
+			Command::NameFont(path, size) => state.name_font = font_declaration(path, *size),
+			Command::DialogueFont(path, size) => state.dialogue_font = font_declaration(path, *size),
+			_ => (),
+		}
+	}
+
+	/// Creates the command that acts on one instance for a command
+	/// that acts on every instance of a character.
+	fn instance_command(&self, instance: InstanceName) -> Option<Command> {
+		Some(match self {
+			Command::CharacterPosition(_, position, animation) =>
+				Command::Position(instance, position.clone(), animation.clone()),
+			Command::CharacterShow(_, animation) => Command::Show(instance, animation.clone()),
+			Command::CharacterHide(_, animation) => Command::Hide(instance, animation.clone()),
+			Command::CharacterKill(_, animation) => Command::Kill(instance, animation.clone()),
+			_ => return None,
+		})
+	}
+
+	/// Whether the command waits before the script continues or changes which command runs next.
+	pub fn is_blocking(&self) -> bool {
+		matches!(self, Command::Pause(_, _) | Command::Sync | Command::Delay(_) | Command::Yield |
+			Command::Dialogue(_, _, _) | Command::Diverge(_) | Command::Random(_) |
+			Command::SceneTransition(_, _) | Command::Jump(_, _) | Command::If(_, _) |
+			Command::IfCounter(_, _, _, _) | Command::IfBetween(_, _, _, _) |
+			Command::Repeat(_) | Command::RepeatIf(_, _) | Command::RepeatN(_, _))
+	}
+
+	/// Lists the command followed by every command nested inside it.
+	pub fn nested(&self) -> Vec<&Command> {
+		match self {
+			Command::Together(commands) => std::iter::once(self)
+				.chain(commands.iter().flat_map(Command::nested)).collect(),
+			_ => vec![self],
+		}
+	}
+
+	/// Gets the name of the instance that the command acts on.
+	pub fn instance(&self) -> Option<&InstanceName> {
+		match self {
+			Command::Change(instance, _, _) | Command::Show(instance, _) |
+			Command::Hide(instance, _) | Command::Position(instance, _, _) |
+			Command::Opacity(instance, _, _) | Command::Move(instance, _, _) |
+			Command::TweenPosition(instance, _, _, _) |
+			Command::Kill(instance, _) | Command::Sprite(instance, _, _) |
+			Command::Flip(instance, _) | Command::FlipVertical(instance) |
+			Command::Front(instance) | Command::Back(instance) |
+			Command::StopAnimation(instance) => Some(instance),
+			_ => None,
+		}
+	}
+
+	/// Gets a mutable reference to the name of the instance that the command acts on.
+	pub fn instance_mut(&mut self) -> Option<&mut InstanceName> {
+		match self {
+			Command::Change(instance, _, _) | Command::Show(instance, _) |
+			Command::Hide(instance, _) | Command::Position(instance, _, _) |
+			Command::Opacity(instance, _, _) | Command::Move(instance, _, _) |
+			Command::TweenPosition(instance, _, _, _) |
+			Command::Kill(instance, _) | Command::Sprite(instance, _, _) |
+			Command::Flip(instance, _) | Command::FlipVertical(instance) |
+			Command::Front(instance) | Command::Back(instance) |
+			Command::StopAnimation(instance) => Some(instance),
+			_ => None,
+		}
+	}
+
+	/// Gets the animation that the command plays on instances, if any.
+	/// Transitions of jumps are not animations of instances and are not included.
+	pub fn animation(&self) -> Option<&AnimationDeclaration> {
+		match self {
+			Command::Change(_, _, animation) | Command::Show(_, animation) |
+			Command::Hide(_, animation) | Command::Opacity(_, _, animation) |
+			Command::Position(_, _, animation) | Command::Move(_, _, animation) |
+			Command::CharacterPosition(_, _, animation) | Command::CharacterShow(_, animation) |
+			Command::CharacterHide(_, animation) | Command::CharacterKill(_, animation) |
+			Command::Kill(_, animation) | Command::Sprite(_, _, animation) |
+			Command::Flip(_, animation) | Command::Spawn(_, _, _, _, animation) => animation.as_ref(),
+			Command::AnimateAll(_, animation) => Some(animation),
+			_ => None,
+		}
+	}
+
+	/// Checks whether the animation of the command is registered for its type of command.
+	/// Commands without an animation have nothing to check.
+	pub fn animation_registered(&self, animations: &AnimationMap) -> bool {
+		let name = match self.animation() {
+			Some(animation) => &animation.name,
+			None => return true,
+		};
+
+		match self {
+			Command::Change(_, _, _) | Command::Sprite(_, _, _) => animations.change.contains_key(name),
+			Command::Position(_, _, _) | Command::Move(_, _, _) |
+			Command::CharacterPosition(_, _, _) => animations.position.contains_key(name),
+			Command::Show(_, _) | Command::CharacterShow(_, _) |
+			Command::AnimateAll(BatchAnimation::Show, _) => animations.show.contains_key(name),
+			Command::Hide(_, _) | Command::CharacterHide(_, _) |
+			Command::AnimateAll(BatchAnimation::Hide, _) => animations.hide.contains_key(name),
+			Command::Kill(_, _) | Command::CharacterKill(_, _) |
+			Command::AnimateAll(BatchAnimation::Kill, _) => animations.kill.contains_key(name),
+			Command::Spawn(_, _, _, _, _) => animations.spawn.contains_key(name),
+			Command::Opacity(_, _, _) => animations.opacity.contains_key(name),
+			Command::Flip(_, _) => animations.flip.contains_key(name),
+			_ => animations.contains(name),
 		}
 	}
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(transparent)]
 pub struct Target(pub usize);
 
 impl Target {
@@ -203,18 +828,495 @@ impl Target {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Hash, Eq, PartialEq)]
-pub struct Label(pub String);
+#[serde(transparent)]
+pub struct Label(pub Arc<str>);
+
+name!(Label);
+
+/// A part of the script that can be started from the chapter select.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Chapter {
+	/// Label at the start of the chapter.
+	pub label: Label,
+	/// Name of the chapter shown in the chapter select.
+	pub title: String,
+	/// Label that sets up the flags, counters and stage that the chapter expects
+	/// when it is started from the chapter select. It should jump to the chapter once it is done.
+	pub init: Option<Label>,
+}
+
+impl Chapter {
+	/// Label that the script starts from when the chapter is chosen in the chapter select.
+	pub fn start(&self) -> &Label {
+		self.init.as_ref().unwrap_or(&self.label)
+	}
+}
 
 #[derive(Debug, Default)]
 pub struct Script {
 	pub characters: Characters,
+	pub spawn_defaults: SpawnDefaults,
 	pub commands: Vec<Command>,
 	pub labels: HashMap<Label, Target>,
 	pub images: HashMap<PathBuf, Image>,
 	pub audio: HashMap<PathBuf, SoundData>,
+	pub fonts: HashMap<PathBuf, graphics::Font>,
 	pub animations: AnimationMap,
 }
 
+/// A resource that is referenced by a script.
+#[derive(Debug, Clone)]
+pub struct ResourceRef<'a> {
+	/// Path to the resource.
+	pub path: &'a PathBuf,
+	/// What the resource is used as.
+	pub kind: ResourceKind,
+	/// What references the resource.
+	pub source: ResourceSource<'a>,
+}
+
+/// What a resource is used as.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResourceKind {
+	Image,
+	Music,
+	Sound,
+	Font,
+}
+
+/// What references a resource.
+#[derive(Debug, Clone)]
+pub enum ResourceSource<'a> {
+	/// The command at a target.
+	Command(Target),
+	/// A state of a character.
+	State(&'a CharacterName, &'a StateName),
+}
+
+/// The differences between two versions of a script.
+/// Indices of added and changed commands are in the new script
+/// and indices of removed commands are in the old script.
+#[derive(Debug, Default, PartialEq)]
+pub struct ScriptDiff {
+	pub added: Vec<usize>,
+	pub removed: Vec<usize>,
+	pub changed: Vec<usize>,
+	/// Labels in both scripts that refer to different commands, with their old and new targets.
+	pub label_changes: HashMap<Label, (Target, Target)>,
+}
+
+impl ScriptDiff {
+	/// Whether a game that has executed up to a target in the old script
+	/// can continue in the new script without executing the script again.
+	/// This is only true if every difference comes after the target.
+	pub fn is_safe_to_continue_from(&self, Target(target): &Target) -> bool {
+		let commands = self.added.iter().chain(&self.removed).chain(&self.changed);
+		let labels = self.label_changes.values()
+			.flat_map(|(Target(old), Target(new))| vec![old, new]);
+		commands.chain(labels).all(|index| index > target)
+	}
+}
+
+/// The result of simulating a script.
+#[derive(Debug)]
+pub struct SimulationResult {
+	/// State of the script when the simulation stopped.
+	pub state: ScriptState,
+	/// History of the divergences that were chosen.
+	pub history: History,
+	/// Target of the divergence the simulation stopped at if there were no more divergences to choose.
+	pub diverge: Option<Target>,
+	/// Errors that stopped the simulation.
+	pub errors: Vec<String>,
+}
+
+/// Finds the branch that a roll between zero and the total weight of the branches lands on.
+/// Rolls at or past the total weight land on no branch.
+pub fn weighted_label(branches: &[(u32, Label)], mut roll: u32) -> Option<&Label> {
+	branches.iter().find(|(weight, _)| match roll.checked_sub(*weight) {
+		Some(remaining) => {
+			roll = remaining;
+			false
+		}
+		None => true,
+	}).map(|(_, label)| label)
+}
+
+/// Chooses a branch at random in proportion to the weights of the branches.
+/// Scripts are validated when loaded so the total weight is never zero and never overflows.
+pub fn random_label(branches: &[(u32, Label)]) -> &Label {
+	let total: u32 = branches.iter().map(|(weight, _)| weight).sum();
+	weighted_label(branches, rand::thread_rng().gen_range(0, total))
+		.expect("Random choice has no branch with a weight above zero")
+}
+
+/// Replaces each name between a prefix and suffix in a string with its value.
+/// A prefix or suffix after a backslash is kept as it is without the backslash
+/// and a prefix without a matching suffix is left unchanged.
+pub fn interpolate<F: Fn(&str) -> String>(string: &str, (prefix, suffix): (char, char), value: F) -> String {
+	let mut interpolated = String::with_capacity(string.len());
+	let mut characters = string.chars().peekable();
+	while let Some(character) = characters.next() {
+		match character {
+			'\\' => match characters.peek() {
+				Some(&next) if next == prefix || next == suffix => {
+					interpolated.push(next);
+					characters.next();
+				}
+				_ => interpolated.push(character),
+			},
+			character if character == prefix => {
+				let remaining: String = characters.clone().collect();
+				match remaining.find(suffix) {
+					Some(end) => {
+						interpolated.push_str(&value(&remaining[..end]));
+						let length = remaining[..end].chars().count();
+						(0..=length).for_each(|_| { characters.next(); });
+					}
+					None => interpolated.push(character),
+				}
+			}
+			character => interpolated.push(character),
+		}
+	}
+	interpolated
+}
+
+/// Places a backslash before every prefix and suffix in a string so that it is not interpolated again.
+fn escape_interpolation(string: &str, (prefix, suffix): (char, char)) -> String {
+	string.chars().fold(String::with_capacity(string.len()), |mut escaped, character| {
+		if character == prefix || character == suffix {
+			escaped.push('\\');
+		}
+		escaped.push(character);
+		escaped
+	})
+}
+
+/// A font and the size that text is drawn with.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FontDeclaration {
+	/// Path to the font file.
+	pub path: PathBuf,
+	/// Size of the text in pixels.
+	pub size: f32,
+}
+
+/// Creates the font of a font command, an empty path means the default font is used.
+fn font_declaration(path: &Path, size: f32) -> Option<FontDeclaration> {
+	match path.as_os_str().is_empty() {
+		false => Some(FontDeclaration { path: path.to_owned(), size }),
+		true => None,
+	}
+}
+
+/// A script without any of its loaded resources or animations.
+/// This can be serialized so that a parsed script can be cached.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PortableScript {
+	pub characters: Characters,
+	#[serde(default)]
+	pub spawn_defaults: SpawnDefaults,
+	pub commands: Vec<Command>,
+	pub labels: HashMap<Label, Target>,
+}
+
+impl Script {
+	/// Creates a copy of the script without any loaded resources or animations.
+	pub fn to_portable(&self) -> PortableScript {
+		PortableScript {
+			characters: self.characters.clone(),
+			spawn_defaults: self.spawn_defaults.clone(),
+			commands: self.commands.clone(),
+			labels: self.labels.clone(),
+		}
+	}
+
+	/// Creates a script from a portable script and loads all its resources.
+	/// The default animations are used.
+	pub fn from_portable(portable: PortableScript, ctx: &mut ggez::Context,
+	                     settings: &Settings) -> ggez::GameResult<Script> {
+		let PortableScript { characters, spawn_defaults, commands, labels } = portable;
+		let mut script = Script { characters, spawn_defaults, commands, labels, ..Script::default() };
+		game::load_resources(ctx, &mut script, settings)?;
+		Ok(script)
+	}
+
+	/// Simulates the script from the start, or from the start target of the history,
+	/// without drawing anything or playing any audio.
+	/// Divergences in the history are chosen in order and the simulation stops at the
+	/// first divergence that is not in the history or at the end of the script.
+	pub fn simulate(&self, history: History) -> SimulationResult {
+		const COMMAND_LIMIT: usize = 1_000_000;
+		let mut divergences = history.divergences.into_iter();
+		let start_target = history.start_target;
+		let (mut state, mut history) = (ScriptState::default(), History::default());
+		let (mut diverge, mut errors) = (None, Vec::new());
+		state.next_target = Some(start_target.clone().unwrap_or_default());
+		history.start_target = start_target;
+
+		loop {
+			state.target = state.next_target.take().unwrap_or_else(|| state.target.next());
+			let command = match self.commands.get(state.target.0) {
+				None => break,
+				Some(command) => command,
+			};
+
+			history.execution_count += 1;
+			if history.execution_count > COMMAND_LIMIT {
+				errors.push(format!("Simulation stopped after {} commands, \
+					the script may repeat forever", COMMAND_LIMIT));
+				break;
+			}
+
+			match command {
+				Command::Diverge(branches) => match divergences.next() {
+					None => {
+						diverge = Some(state.target.clone());
+						break;
+					}
+					Some(label) => {
+						if !branches.iter().any(|(_, branch)| branch == &label) {
+							let Label(name) = &label;
+							errors.push(format!("Label: {}, is not a branch of the divergence at: {}",
+								name, state.target.0));
+							break;
+						}
+
+						state.next_target = Some(self.labels[&label].clone());
+						history.diverge(label);
+					}
+				},
+				Command::Random(branches) => {
+					let label = divergences.next().unwrap_or_else(|| random_label(branches).clone());
+					if !branches.iter().any(|(_, branch)| branch == &label) {
+						let Label(name) = &label;
+						errors.push(format!("Label: {}, is not a branch of the random choice at: {}",
+							name, state.target.0));
+						break;
+					}
+
+					state.next_target = Some(self.labels[&label].clone());
+					history.diverge(label);
+				}
+				command => {
+					if let Err(error) = command.check(&state, self) {
+						errors.push(error);
+						break;
+					}
+					command.execute_state(&mut state, self);
+				}
+			}
+		}
+		SimulationResult { state, history, diverge, errors }
+	}
+
+	/// Iterates over every resource that the characters and commands of the script reference.
+	/// Resources that are referenced multiple times are found multiple times.
+	pub fn referenced_resources(&self) -> impl Iterator<Item=ResourceRef<'_>> {
+		let Characters(characters) = &self.characters;
+		let states = characters.iter().flat_map(|(character, states)| states.iter()
+			.flat_map(move |(state, value)| {
+				let source = move || ResourceSource::State(character, state);
+				let images = std::iter::once(&value.image).chain(&value.overlay).map(move |path|
+					ResourceRef { path, kind: ResourceKind::Image, source: source() });
+				images.chain(value.spawn_sound.iter().map(move |path|
+					ResourceRef { path, kind: ResourceKind::Sound, source: source() }))
+			}));
+
+		let commands = self.commands.iter().enumerate().flat_map(|(index, command)| command.nested()
+			.into_iter().map(move |command| (index, command))).filter_map(|(index, command)| {
+			let (path, kind) = match command {
+				Command::Stage(path) | Command::Sprite(_, path, _) |
+				Command::SceneTransition(path, _) => (path, ResourceKind::Image),
+				Command::Music(path) | Command::MusicLoop(path, _, _) => (path, ResourceKind::Music),
+				Command::Sound(path) => (path, ResourceKind::Sound),
+				Command::TextFont(path, _) | Command::NameFont(path, _) | Command::DialogueFont(path, _)
+					if !path.as_os_str().is_empty() => (path, ResourceKind::Font),
+				_ => return None,
+			};
+			Some(ResourceRef { path, kind, source: ResourceSource::Command(Target(index)) })
+		});
+		states.chain(commands)
+	}
+
+	/// Finds problems in the script that do not stop it from being executed.
+	/// Instances that are shown, hidden or killed but never spawned or aliased are reported.
+	pub fn lint(&self) -> Vec<String> {
+		let spawned: HashSet<_> = self.commands.iter().flat_map(Command::nested).filter_map(|command| match command {
+			Command::Spawn(CharacterName(character), _, _, instance, _) =>
+				Some(instance.clone().unwrap_or_else(|| InstanceName(character.clone()))),
+			Command::Alias(alias, _) | Command::Copy(_, alias, _) |
+			Command::Rename(_, alias) => Some(alias.clone()),
+			_ => None,
+		}).collect();
+
+		self.commands.iter().flat_map(Command::nested).filter_map(|command| match command {
+			Command::Show(instance, _) => Some(("show", instance)),
+			Command::Hide(instance, _) => Some(("hide", instance)),
+			Command::Kill(instance, _) => Some(("kill", instance)),
+			_ => None,
+		}).filter(|(_, instance)| !instance.0.starts_with('#') && !spawned.contains(instance))
+			.map(|(keyword, InstanceName(name))| format!("Instance: {}, \
+				is used by `{}` but is never spawned", name, keyword)).collect()
+	}
+
+	/// Finds the commands and labels that differ between two versions of a script.
+	/// The commands that are the same at the start and end of both scripts are unchanged,
+	/// and the commands in between are changed, added or removed.
+	pub fn diff(old: &Script, new: &Script) -> ScriptDiff {
+		let (old_commands, new_commands) = (&old.commands, &new.commands);
+		let prefix = old_commands.iter().zip(new_commands)
+			.take_while(|(old, new)| old == new).count();
+		let suffix = old_commands[prefix..].iter().rev().zip(new_commands[prefix..].iter().rev())
+			.take_while(|(old, new)| old == new).count();
+
+		let (old_end, new_end) = (old_commands.len() - suffix, new_commands.len() - suffix);
+		let changed_end = prefix + (old_end - prefix).min(new_end - prefix);
+		let label_changes = old.labels.iter().filter_map(|(label, old_target)| {
+			let new_target = new.labels.get(label)?;
+			Some((label.clone(), (old_target.clone(), new_target.clone())))
+				.filter(|_| old_target != new_target)
+		}).collect();
+
+		ScriptDiff {
+			added: (changed_end..new_end).collect(),
+			removed: (changed_end..old_end).collect(),
+			changed: (prefix..changed_end).collect(),
+			label_changes,
+		}
+	}
+
+	/// Checks that every command can be executed with the characters of the script,
+	/// that every slot that is used is defined in the settings
+	/// and that every animation that is used is registered for the type of command that uses it.
+	pub fn validate(&self, settings: &Settings) -> Result<(), String> {
+		self.commands.iter().flat_map(Command::nested)
+			.find(|command| !command.animation_registered(&self.animations))
+			.and_then(Command::animation).map_or(Ok(()), |animation|
+				Err(format!("Animation: {}, is not registered for this command", animation.name)))?;
+		self.commands.iter().flat_map(Command::nested).try_for_each(|command| match command {
+			Command::Spawn(character, state, position, _, _) => {
+				let position = position.as_ref().map(|position| position.resolve(settings)).transpose()?;
+				self.spawn_parameters(character, state.as_ref(), position).map(|_| ())
+			}
+			Command::Position(_, position, _) | Command::Copy(_, _, position) |
+			Command::CharacterPosition(_, position, _) =>
+				position.resolve(settings).map(|_| ()),
+			Command::TweenPosition(_, start, end, _) =>
+				start.resolve(settings).and(end.resolve(settings)).map(|_| ()),
+			Command::Random(branches) => match branches.iter()
+				.try_fold(0u32, |total, (weight, _)| total.checked_add(*weight)) {
+				None => Err("Random choice has a total weight that is too large".to_owned()),
+				Some(0) => Err("Random choice has no branch with a weight above zero".to_owned()),
+				Some(_) => Ok(()),
+			},
+			Command::Jump(Label(label), Some(animation)) if animation.name != "fade" =>
+				Err(format!("Jump to label: {}, uses transition: {}, but only `fade` is supported", label, animation.name)),
+			Command::Chapter(Chapter { title, init: Some(init), .. }) if !self.labels.contains_key(init) =>
+				Err(format!("Chapter: {}, starts from label: {}, which does not exist", title, init.0)),
+			_ => Ok(()),
+		})
+	}
+
+	/// The chapters of the script in the order that they appear.
+	pub fn chapters(&self) -> impl Iterator<Item = &Chapter> {
+		self.commands.iter().filter_map(|command| match command {
+			Command::Chapter(chapter) => Some(chapter),
+			_ => None,
+		})
+	}
+
+	/// The chapters of the script that have been reached in any playthrough.
+	pub fn unlocked_chapters<'a>(&'a self, persistent: &'a Persistent) -> impl Iterator<Item = &'a Chapter> {
+		self.chapters().filter(move |chapter| persistent.chapters.contains(&chapter.label))
+	}
+
+	/// Finds the state and position that a character is spawned with.
+	/// Any that are not given are taken from the defaults of the character.
+	pub fn spawn_parameters<'a>(&'a self, character: &CharacterName, state: Option<&'a StateName>,
+	                            position: Option<(f32, f32)>) -> Result<(&'a StateName, (f32, f32)), String> {
+		let CharacterName(name) = character;
+		let state = self.spawn_defaults.state(character, state).ok_or_else(||
+			format!("Character: {}, is spawned without a state and has no default state", name))?;
+		let position = self.spawn_defaults.position(character, position).ok_or_else(||
+			format!("Character: {}, is spawned without a position and has no default position", name))?;
+		Ok((state, position))
+	}
+
+	/// Gets a loaded image from its path.
+	pub fn image<P: AsRef<Path>>(&self, path: P) -> &Image {
+		let path = game::normalise_path(path);
+		self.images.get(&path).unwrap_or_else(||
+			panic!("Image at path: {:?}, is not loaded", path))
+	}
+
+	/// Gets the loaded overlay of a state and its offset.
+	pub fn overlay(&self, state: &CharacterState) -> Option<(Image, (f32, f32))> {
+		state.overlay.as_ref().map(|path| (self.image(path).clone(), state.overlay_offset))
+	}
+
+	/// Gets a loaded font and its size from a font declaration.
+	/// No font is returned if the default font is to be used.
+	pub fn font(&self, font: &Option<FontDeclaration>) -> Option<(graphics::Font, f32)> {
+		font.as_ref().map(|FontDeclaration { path, size }| {
+			let path = game::normalise_path(path);
+			let font = self.fonts.get(&path).unwrap_or_else(||
+				panic!("Font at path: {:?}, is not loaded", path));
+			(*font, *size)
+		})
+	}
+
+	/// Gets loaded audio from its path.
+	pub fn sound_data<P: AsRef<Path>>(&self, path: P) -> &SoundData {
+		let path = game::normalise_path(path);
+		self.audio.get(&path).unwrap_or_else(||
+			panic!("Audio at path: {:?}, is not loaded", path))
+	}
+
+	/// Finds the label that points exactly to a target.
+	/// Returns `None` if no label exists at this position.
+	/// If several labels point to the target then the first by name is returned.
+	pub fn get_label_at(&self, Target(index): &Target) -> Option<&Label> {
+		self.labels.iter().filter(|(_, Target(target))| target == index)
+			.map(|(label, _)| label).min_by(|a, b| a.0.cmp(&b.0))
+	}
+
+	/// Finds the label with the largest target that is at or before a given target.
+	/// This is the label of the section that the target is currently inside.
+	/// Ties between labels at the same target are broken by name.
+	pub fn nearest_preceding_label(&self, Target(index): &Target) -> Option<&Label> {
+		self.labels.iter().filter(|(_, Target(target))| target <= index)
+			.max_by(|(a, Target(x)), (b, Target(y))| x.cmp(y).then_with(|| b.0.cmp(&a.0)))
+			.map(|(label, _)| label)
+	}
+
+	/// Removes loaded resources that are not being used by the render or the music.
+	/// If a path is specified, only the resources at that path are removed.
+	pub fn unload(&mut self, path: Option<&Path>, render: &Render, music: Option<&Path>) {
+		let Stage(stage) = &render.stage;
+		let image_used = |image: &Image| render.background.as_ref() == Some(image) ||
+			stage.values().any(|instance| &instance.image == image);
+		let audio_used = |path: &PathBuf| music.map(game::normalise_path).as_ref() == Some(path);
+
+		match path.map(game::normalise_path) {
+			Some(path) => {
+				if !self.images.get(&path).map(image_used).unwrap_or(false) {
+					self.images.remove(&path);
+				}
+
+				if !audio_used(&path) {
+					self.audio.remove(&path);
+				}
+			}
+			None => {
+				self.images.retain(|_, image| image_used(image));
+				self.audio.retain(|path, _| audio_used(path));
+			}
+		}
+	}
+}
+
 impl Index<&Target> for Script {
 	type Output = Command;
 
@@ -228,14 +1330,408 @@ pub struct ScriptState {
 	pub target: Target,
 	pub next_target: Option<Target>,
 	pub flags: HashSet<FlagName>,
-	pub music: Option<Source>,
+	pub persistent: Persistent,
+	pub aliases: HashMap<InstanceName, InstanceName>,
+	pub groups: HashMap<String, Vec<InstanceName>>,
+	/// Amount of times left to repeat each bounded loop.
+	pub loop_counters: HashMap<Label, u32>,
+	/// Values of the counters set by the script.
+	pub counters: HashMap<String, i32>,
+	pub background: Option<PathBuf>,
+	pub music: Option<Music>,
+	pub music_path: Option<PathBuf>,
+	/// Start and end in seconds of the section of the music that is repeated.
+	pub music_loop: Option<(f32, f32)>,
+	/// Whether the music has been paused.
+	pub music_paused: bool,
 	pub sounds: Vec<Source>,
+	/// Font used for the character name.
+	pub name_font: Option<FontDeclaration>,
+	/// Font used for the dialogue.
+	pub dialogue_font: Option<FontDeclaration>,
+	/// Time that the script continues at after a delay.
+	pub delay_until: Option<Instant>,
+	/// Whether the script is stopped by a yield until the next frame.
+	pub yielded: bool,
+	/// Pages of dialogue that did not fit in the text box and are shown before the next command.
+	pub pending_dialogue: VecDeque<Command>,
+	/// Whether earlier commands are being run again to load a save.
+	/// Dialogue is not passed to the dialogue hook while replaying.
+	pub replaying: bool,
+}
+
+impl ScriptState {
+	/// Plays a sound effect at the sound volume.
+	pub fn play_sound(&mut self, ctx: &mut ggez::Context, data: SoundData, settings: &Settings) {
+		let mut source = Source::from_data(ctx, data).unwrap();
+		source.set_volume(settings.sound_volume);
+		source.play().unwrap();
+		self.sounds.push(source);
+	}
+
+	/// Plays the sound of a state that an instance has entered.
+	/// A warning is printed instead if the sound is not loaded.
+	fn play_spawn_sound(&mut self, ctx: &mut ggez::Context, script: &Script,
+	                    character: &CharacterState, settings: &Settings) {
+		if let Some(path) = &character.spawn_sound {
+			match script.audio.get(&game::normalise_path(path)) {
+				Some(data) => self.play_sound(ctx, data.clone(), settings),
+				None => eprintln!("Warning: Spawn sound at path: {}, is not loaded", path.display()),
+			}
+		}
+	}
+
+	/// Checks whether a flag has been set either
+	/// in this playthrough or as a persistent flag.
+	pub fn flag(&self, flag: &FlagName) -> bool {
+		self.flags.contains(flag) || self.persistent.flags.contains(flag)
+	}
+
+	/// Finds the value of a counter.
+	/// Counters that have not been set have a value of zero.
+	pub fn counter(&self, counter: &str) -> i32 {
+		self.counters.get(counter).copied().unwrap_or(0)
+	}
+
+	/// Replaces the variables in dialogue with the values of the counters and flags they name.
+	/// Names of counters that have been set are replaced with their value
+	/// and any other name is replaced with whether the flag of that name is set.
+	pub fn interpolate(&self, string: &str, settings: &Settings) -> String {
+		let delimiters = (settings.interpolation_prefix, settings.interpolation_suffix);
+		interpolate(string, delimiters, |name| match self.counters.get(name) {
+			Some(value) => value.to_string(),
+			None => self.flag(&FlagName(name.to_owned())).to_string(),
+		})
+	}
+
+	/// Resolves an alias into the name of the instance it refers to.
+	/// Names that are not aliases are returned unchanged.
+	pub fn alias<'a>(&'a self, name: &'a InstanceName) -> &'a InstanceName {
+		self.aliases.get(name).unwrap_or(name)
+	}
+
+	/// Expands a group reference into the names of the instances in the group.
+	/// Names that do not refer to an existing group are returned unchanged.
+	pub fn expand(&self, name: &InstanceName) -> Vec<InstanceName> {
+		let InstanceName(string) = name;
+		string.strip_prefix('#').and_then(|group| self.groups.get(group).cloned())
+			.unwrap_or_else(|| vec![name.clone()])
+	}
+}
+
+/// Finds where an instance at a position ends up after being moved by an offset.
+/// The position must be taken after any animation has finished so that a move that
+/// interrupts another continues from where the interrupted move would have ended.
+fn move_destination(settings: &Settings, (x, y): (f32, f32), (offset_x, offset_y): (f32, f32)) -> (f32, f32) {
+	settings.clamp_position((x + offset_x, y + offset_y))
+}
+
+/// Moves an instance to a position, gliding there if there is an animation.
+fn move_instance(instance: &mut Instance, destination: (f32, f32),
+                 animation: Option<&AnimationDeclaration>, script: &Script) {
+	if let Some(animation) = animation {
+		let position_animation = PositionAnimation {
+			destination,
+			arguments: animation.arguments.clone(),
+		};
+		let animation = script.animations.position.get(&animation.name)
+			.unwrap_or_else(|| panic!("Error finding animation named `{}`", animation.name))
+			.initialise(position_animation);
+		instance.add_animation(animation);
+	} else {
+		instance.position = destination;
+	}
+}
+
+/// Passes a line of dialogue to the dialogue hook unless a save is being replayed.
+fn announce_dialogue(state: &ScriptState, settings: &Settings, character: Option<&CharacterName>, string: &str) {
+	if let (Some(DialogueHook(hook)), false) = (&settings.on_dialogue, state.replaying) {
+		hook(character.map(|CharacterName(name)| &**name), string);
+	}
+}
+
+/// Finds the instant that is an amount of milliseconds from now.
+/// Returns `None` if the instant is too far in the future to be represented.
+fn instant_after(milliseconds: f32) -> Option<Instant> {
+	let duration = Duration::try_from_secs_f32(milliseconds.max(0.0) / 1_000.0).ok()?;
+	Instant::now().checked_add(duration)
+}
+
+/// Finds the position of a placement.
+/// Scripts are validated when loaded so every slot that is used exists.
+fn resolve_placement(placement: &Placement, settings: &Settings) -> (f32, f32) {
+	placement.resolve(settings).unwrap_or_else(|error| panic!("{}", error))
+}
+
+/// Reports a problem with the script that does not stop it from continuing.
+/// Problems are shown on the screen in developer mode and logged otherwise.
+fn report(render: &mut Render, settings: &Settings, message: String) {
+	match settings.developer {
+		true => render.notify(message, settings),
+		false => eprintln!("Warning: {}", message),
+	}
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+/// Reports that an instance is about to be replaced by a new instance with the same name.
+fn warn_replaced(render: &mut Render, settings: &Settings, name: &InstanceName) {
+	if render.stage.contains(name) {
+		let InstanceName(name) = name;
+		report(render, settings, format!("Instance: {}, already exists and has been replaced, \
+			kill the instance before reusing its name", name));
+	}
+}
+
+/// Migrations that upgrade the save format, the migration at index `n` upgrades version `n`.
+const HISTORY_MIGRATIONS: &[fn(&mut toml::value::Table)] = &[
+	// Version 0 saves predate versioning and only lack the version field.
+	|_| (),
+	migrate_instance_appearance,
+	migrate_music_state,
+	migrate_instance_order,
+];
+
+/// Version 1 instance snapshots do not store the scale and colour of the instance.
+fn migrate_instance_appearance(history: &mut toml::value::Table) {
+	let instances = history.get_mut("snapshot").and_then(|snapshot| snapshot.get_mut("instances"))
+		.and_then(toml::Value::as_table_mut);
+	instances.into_iter().flat_map(|instances| instances.iter_mut().map(|(_, instance)| instance))
+		.filter_map(toml::Value::as_table_mut).for_each(|instance| {
+			instance.entry("scale").or_insert_with(|| vec![1.0; 2].into());
+			instance.entry("colour").or_insert_with(|| vec![1.0; 4].into());
+		});
+}
+
+/// Version 2 snapshots store the path of the playing music instead of its state.
+fn migrate_music_state(history: &mut toml::value::Table) {
+	let snapshot = history.get_mut("snapshot").and_then(toml::Value::as_table_mut);
+	if let Some(music) = snapshot.and_then(|snapshot| snapshot.get_mut("music")) {
+		if let Some(path) = music.as_str() {
+			let mut state = toml::value::Table::new();
+			state.insert("state".to_owned(), "playing".into());
+			state.insert("path".to_owned(), path.into());
+			*music = state.into();
+		}
+	}
+}
+
+/// Version 3 snapshots store the instances in a table keyed by name instead of a list in the order they are drawn.
+/// The instances listed in the draw order come first, followed by the others sorted by name.
+fn migrate_instance_order(history: &mut toml::value::Table) {
+	let snapshot = match history.get_mut("snapshot").and_then(toml::Value::as_table_mut) {
+		Some(snapshot) => snapshot,
+		None => return,
+	};
+
+	let draw_order: Vec<_> = snapshot.get("draw_order").and_then(toml::Value::as_array).into_iter()
+		.flatten().filter_map(toml::Value::as_str).map(str::to_owned).collect();
+	if let Some(toml::Value::Table(table)) = snapshot.remove("instances") {
+		let mut instances: Vec<_> = table.into_iter().collect();
+		instances.sort_by_key(|(name, _)| (draw_order.iter().position(|other| other == name)
+			.unwrap_or(draw_order.len()), name.clone()));
+		let instances = instances.into_iter().filter_map(|(name, mut instance)| {
+			instance.as_table_mut()?.insert("name".to_owned(), name.into());
+			Some(instance)
+		}).collect::<Vec<_>>();
+		snapshot.insert("instances".to_owned(), instances.into());
+	}
+}
+
+/// Version of the save format that is written by the engine.
+pub const HISTORY_VERSION: u32 = HISTORY_MIGRATIONS.len() as u32;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct History {
+	/// Version of the save format, saves without a version are version 0.
+	#[serde(default)]
+	pub version: u32,
 	pub divergences: Vec<Label>,
+	/// The execution count at which each divergence was made.
+	/// Saves made before these were recorded have fewer positions than divergences.
+	#[serde(default)]
+	pub divergence_positions: Vec<usize>,
 	pub execution_count: usize,
+	/// Command that the script was started from if it was not started from the beginning,
+	/// such as when a chapter is chosen in the chapter select. The script is replayed from here.
+	#[serde(default)]
+	pub start_target: Option<Target>,
+	/// Time in seconds that the music had been playing for when the game was saved.
+	/// The music continues from this position when the save is loaded.
+	#[serde(default)]
+	pub music_position: Option<f32>,
+	/// Screenshot of the game when it was saved, scaled down and encoded as a PNG image.
+	#[serde(default)]
+	pub thumbnail: Option<Vec<u8>>,
+	/// The state of the game when it was saved.
+	/// If present, it is restored instead of replaying the script.
+	#[serde(default)]
+	pub snapshot: Option<Snapshot>,
+	/// Message shown to the player when the game is loaded.
+	/// This is never saved.
+	#[serde(skip)]
+	pub notice: Option<String>,
+}
+
+impl History {
+	/// Parses a saved history, migrating it from older versions of the save format.
+	pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+		let mut value: toml::Value = toml::from_slice(bytes).map_err(|error| error.to_string())?;
+		let table = value.as_table_mut().ok_or("Save does not contain a table")?;
+		let version = match table.get("version") {
+			None => 0,
+			Some(version) => version.as_integer().filter(|version| *version >= 0)
+				.ok_or("Save version is not a valid integer")? as u32,
+		};
+
+		if version > HISTORY_VERSION {
+			return Err(format!("Save was made by a newer version (version: {}, supported: {})",
+				version, HISTORY_VERSION));
+		}
+
+		HISTORY_MIGRATIONS[version as usize..].iter().for_each(|migration| migration(table));
+		table.insert("version".to_owned(), toml::Value::Integer(HISTORY_VERSION as i64));
+		value.try_into().map_err(|error| error.to_string())
+	}
+
+	/// Records a divergence at the current execution count.
+	pub fn diverge(&mut self, label: Label) {
+		self.divergences.push(label);
+		self.divergence_positions.push(self.execution_count);
+	}
+
+	/// Removes all the divergences that have been made.
+	pub fn clear_divergences(&mut self) {
+		self.divergences.clear();
+		self.divergence_positions.clear();
+	}
+
+	/// Rewinds the history to an earlier execution count.
+	/// Divergences made at or after the execution count are removed along with the snapshot.
+	/// Divergences without a recorded position are always kept.
+	pub fn truncate_to(&mut self, execution_count: usize) {
+		let unrecorded = self.divergences.len().saturating_sub(self.divergence_positions.len());
+		let kept = self.divergence_positions.iter()
+			.take_while(|position| **position < execution_count).count();
+		self.divergence_positions.truncate(kept);
+		self.divergences.truncate(unrecorded + kept);
+		self.execution_count = execution_count;
+		self.snapshot = None;
+	}
+}
+
+impl Default for History {
+	fn default() -> Self {
+		History {
+			version: HISTORY_VERSION,
+			divergences: Vec::new(),
+			divergence_positions: Vec::new(),
+			execution_count: 0,
+			start_target: None,
+			music_position: None,
+			thumbnail: None,
+			snapshot: None,
+			notice: None,
+		}
+	}
+}
+
+/// The state of the game at a point in the script.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct Snapshot {
+	/// Index of the command that was last executed.
+	pub target: usize,
+	pub flags: HashSet<FlagName>,
+	/// Start and end in seconds of the section of the music that is repeated.
+	#[serde(default)]
+	pub music_loop: Option<(f32, f32)>,
+	/// Path of the background image.
+	pub background: Option<PathBuf>,
+	/// Offset of the camera once any movement has finished.
+	#[serde(default)]
+	pub camera_offset: (f32, f32),
+	/// Order that instances are drawn in if one has been set.
+	#[serde(default)]
+	pub draw_order: Option<Vec<InstanceName>>,
+	/// Music that is playing or paused.
+	/// This is a table so it is placed after the other values in the save.
+	#[serde(default)]
+	pub music: MusicState,
+	pub aliases: HashMap<InstanceName, InstanceName>,
+	pub groups: HashMap<String, Vec<InstanceName>>,
+	pub loop_counters: HashMap<Label, u32>,
+	/// Values of the counters set by the script.
+	#[serde(default)]
+	pub counters: HashMap<String, i32>,
+	/// Font used for the character name.
+	pub name_font: Option<FontDeclaration>,
+	/// Font used for the dialogue.
+	pub dialogue_font: Option<FontDeclaration>,
+	pub instances: StageSnapshot,
+}
+
+/// Whether music is playing when a snapshot is made.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "state", rename_all = "lowercase")]
+pub enum MusicState {
+	Playing { path: PathBuf },
+	/// Music that was paused after playing for a number of seconds.
+	Paused { path: PathBuf, position: f32 },
+	#[default]
+	Stopped,
+}
+
+/// Data that is kept across all playthroughs of the game.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct Persistent {
+	/// Flags that have been set with the `persist` command.
+	pub flags: HashSet<FlagName>,
+	/// Labels of the chapters that have been reached, which can be chosen in the chapter select.
+	#[serde(default)]
+	pub chapters: HashSet<Label>,
+}
+
+/// Number of times each branch of each divergence has been chosen by players.
+/// These are collected for playtesting when `collect_stats` is set in the settings.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct ChoiceStats {
+	#[serde(default)]
+	pub choices: Vec<ChoiceCount>,
+}
+
+/// Number of times a branch of a divergence has been chosen.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ChoiceCount {
+	/// Index of the divergence command.
+	pub target: Target,
+	/// Label of the branch that was chosen.
+	pub label: Label,
+	pub count: u32,
+}
+
+impl ChoiceStats {
+	/// Records that a branch of the divergence at a target has been chosen.
+	pub fn record(&mut self, target: &Target, label: &Label) {
+		match self.choices.iter_mut().find(|choice| &choice.target == target && &choice.label == label) {
+			Some(choice) => choice.count += 1,
+			None => self.choices.push(ChoiceCount { target: target.clone(), label: label.clone(), count: 1 }),
+		}
+	}
+
+	/// Number of times a branch of the divergence at a target has been chosen.
+	pub fn count(&self, target: &Target, label: &Label) -> u32 {
+		self.choices.iter().find(|choice| &choice.target == target && &choice.label == label)
+			.map(|choice| choice.count).unwrap_or(0)
+	}
+
+	/// Percentage of the choices made at the divergence at a target that chose each branch.
+	/// Every branch is zero if the divergence has never been chosen.
+	pub fn percentages<'a>(&self, target: &Target, labels: impl IntoIterator<Item = &'a Label>) -> Vec<f32> {
+		let total: u32 = self.choices.iter().filter(|choice| &choice.target == target)
+			.map(|choice| choice.count).sum();
+		labels.into_iter().map(|label| match total {
+			0 => 0.0,
+			_ => self.count(target, label) as f32 * 100.0 / total as f32,
+		}).collect()
+	}
 }
 
 #[derive(Debug, Clone)]
@@ -252,14 +1748,29 @@ pub struct Settings {
 	pub foreground_colour: [f32; 4],
 	/// Alternative colour for interface elements such as button hovers.
 	pub secondary_colour: [f32; 4],
+	/// Colour of the bars that fill the window outside of the view.
+	pub letterbox_colour: [f32; 4],
 	/// Amount of pixels between interface elements and the game window.
 	pub interface_margin: f32,
 	/// Height of the main text box expressed as a multiplier of the window height.
 	/// `0.5` is exactly half of the window height.
 	pub text_box_height: f32,
-	/// Width of the character name expressed as a multiplier of the window width.
+	/// Smallest width of the character name expressed as a multiplier of the window width.
+	/// The character name is sized to fit the name between the smallest and largest widths.
+	pub character_name_min_width: f32,
+	/// Largest width of the character name expressed as a multiplier of the window width.
 	/// `0.5` is exactly half of the window width.
+	pub character_name_max_width: f32,
+	/// Width of the character name expressed as a multiplier of the window width.
+	/// This has no effect as the character name is sized to fit the name.
+	#[deprecated(note = "use `character_name_min_width` and `character_name_max_width` instead")]
 	pub character_name_width: f32,
+	/// Where the character name is placed horizontally.
+	pub character_name_alignment: NameAlignment,
+	/// Dims the instances of characters that are not speaking.
+	pub speaker_highlight: bool,
+	/// Red, green and blue that the instances of characters that are not speaking are multiplied by.
+	pub inactive_tint: [f32; 3],
 	/// Height of the character name expressed as a multiplier of the window height.
 	/// `0.1` is exactly one tenth of the window height.
 	pub character_name_height: f32,
@@ -267,18 +1778,223 @@ pub struct Settings {
 	pub branch_button_width: f32,
 	/// Height of each branch button expressed as a multiplier of the window height.
 	pub branch_button_height: f32,
+	/// Smallest height that branch buttons shrink to when there are too many to fit in the view,
+	/// expressed as a multiplier of the window height. Buttons that still do not fit are scrolled.
+	pub branch_button_min_height: f32,
+	/// Places the branch buttons in two columns.
+	pub branch_two_columns: bool,
+	/// Shows a row of buttons along the bottom of the text box while dialogue is displayed.
+	pub quick_menu: bool,
+	/// Width of each quick menu button expressed as a multiplier of the window width.
+	pub quick_menu_button_width: f32,
+	/// Height of each quick menu button expressed as a multiplier of the window height.
+	pub quick_menu_button_height: f32,
+	/// Colour of quick menu buttons that toggle a mode while the mode is active.
+	pub active_colour: [f32; 4],
+	/// Time in milliseconds that dialogue stays on screen after it is finished
+	/// before the script advances when auto mode is active.
+	pub auto_delay: f32,
 	/// Paths to look for resource files.
 	pub resource_paths: Vec<String>,
-	/// Path to save the game history.
+	/// Directory that saves are written to.
+	/// If this is not set then the user data directory for the game is used.
+	pub save_directory: Option<PathBuf>,
+	/// Path to the characters file.
+	/// In developer mode the characters are reloaded whenever this file changes.
+	pub characters_path: Option<String>,
+	/// Path to save the game history, relative to the save directory.
 	pub save_path: String,
+	/// Path to save data that persists across playthroughs, relative to the save directory.
+	pub persistent_path: String,
+	/// Records how many times each branch is chosen so that playtesting choices can be reviewed.
+	/// In developer mode the share of choices is shown beside each branch.
+	pub collect_stats: bool,
+	/// Path to save the statistics of the choices that were made, relative to the save directory.
+	pub stats_path: String,
+	/// Number of choices that are recorded before the statistics are written.
+	/// The statistics are also written whenever the game is saved.
+	pub stats_flush_interval: u32,
+	/// Number of save slots shown by the save select menu.
+	pub save_slots: u32,
+	/// Width in pixels that the screenshot shown for a save slot is scaled to.
+	pub thumbnail_width: u32,
+	/// Height in pixels that the screenshot shown for a save slot is scaled to.
+	pub thumbnail_height: u32,
 	/// Volume of music that is played. The normal volume is `1.0`.
 	pub music_volume: f32,
 	/// Volume of sound effects that are played. The normal volume is `1.0`.
 	pub sound_volume: f32,
+	/// Path to an image that is drawn in place of the mouse cursor.
+	/// The cursor of the operating system is used if the image cannot be loaded.
+	pub cursor_image: Option<PathBuf>,
+	/// Point of the cursor image in pixels that is placed at the position of the mouse.
+	pub cursor_hotspot: (f32, f32),
+	/// Path to a sound that is played when the mouse moves over a button.
+	/// Nothing is played if the sound cannot be loaded.
+	pub ui_hover_sound: Option<String>,
+	/// Path to a sound that is played when the player clicks to advance the script.
+	/// Nothing is played if the sound cannot be loaded.
+	pub ui_click_sound: Option<String>,
 	/// Enables developer mode features.
 	pub developer: bool,
+	/// Shares loaded resources between files with identical contents.
+	pub dedupe_assets: bool,
+	/// Draws instances that share an image in a single draw call.
+	pub batched_drawing: bool,
+	/// Ratio of physical pixels to window coordinates.
+	/// This is typically `2.0` on macOS Retina displays.
+	pub scale_factor: f32,
+	/// Calculates the `scale_factor` when the game starts, overriding any value that was set.
+	pub auto_detect_scale: bool,
+	/// Amount that margins, text, text boxes and buttons are scaled by.
+	pub ui_scale: f32,
+	/// Records the `scale_factor` of the display when the game starts and resets the `ui_scale`
+	/// to `1.0` as the view is already scaled to the display, overriding any values that were set.
+	pub auto_detect_ui_scale: bool,
+	/// What clicking does while animations are running.
+	pub click_policy: ClickPolicy,
+	/// Where the player can click to advance the script.
+	/// Branches can always be chosen by clicking on them.
+	pub advance_click_area: AdvanceClickArea,
+	/// How the bars that fill the window outside of the view are drawn.
+	pub letterbox_style: LetterboxStyle,
+	/// Alignment of dialogue spoken by a character.
+	pub dialogue_alignment: TextAlignment,
+	/// Alignment of dialogue without a character.
+	pub narration_alignment: TextAlignment,
+	/// Number of the most recently revealed characters of dialogue that fade in.
+	/// Zero reveals each character fully at once.
+	pub text_fade_chars: usize,
+	/// Greatest number of lines of dialogue shown at once.
+	/// Dialogue that does not fit is split into pages if this is set.
+	pub max_dialogue_lines: Option<usize>,
+	/// Character that starts the name of a counter or flag whose value is shown in dialogue.
+	pub interpolation_prefix: char,
+	/// Character that ends the name of a counter or flag whose value is shown in dialogue.
+	pub interpolation_suffix: char,
+	/// Named positions in pixels that instances can be placed at with `slot`.
+	pub slots: HashMap<String, (f32, f32)>,
+	/// Keeps the centre of instances that are positioned or moved inside the view.
+	pub clamp_instance_to_viewport: bool,
+	/// Distance in pixels outside of the view that clamped instances can still be placed.
+	pub viewport_margin: f32,
+	/// Replaces the interface colours with white text on opaque black boxes when the game starts
+	/// so that no background image shows through behind text.
+	pub high_contrast: bool,
+	/// Called with each line of dialogue as it is displayed.
+	/// This is where a text-to-speech engine or screen reader can be connected.
+	pub on_dialogue: Option<DialogueHook>,
+}
+
+/// Function that receives the name of the speaker, if any, and the text of a line of dialogue.
+pub type DialogueFn = dyn Fn(Option<&str>, &str) + Send + Sync;
+
+/// Callback that is given each line of dialogue as it is displayed.
+#[derive(Clone)]
+pub struct DialogueHook(pub Arc<DialogueFn>);
+
+impl DialogueHook {
+	pub fn new<F: Fn(Option<&str>, &str) + Send + Sync + 'static>(function: F) -> Self {
+		DialogueHook(Arc::new(function))
+	}
+}
+
+impl std::fmt::Debug for DialogueHook {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		f.write_str("DialogueHook")
+	}
+}
+
+impl Settings {
+	/// Replaces the interface colours with the highest contrast pair if high contrast is enabled.
+	/// Text boxes become opaque so that nothing is drawn behind text.
+	pub fn apply_high_contrast(&mut self) {
+		if !self.high_contrast { return; }
+		self.foreground_colour = [1.0, 1.0, 1.0, 1.0];
+		self.background_colour = [0.0, 0.0, 0.0, 1.0];
+		self.secondary_colour = [0.0, 0.0, 0.6, 1.0];
+		self.active_colour = [0.0, 0.4, 0.0, 1.0];
+	}
+
+	/// Clamps a position to the view, widened by the viewport margin, if clamping is enabled.
+	/// The position is where the centre of an instance is placed so the centre stays visible.
+	pub fn clamp_position(&self, (x, y): (f32, f32)) -> (f32, f32) {
+		if !self.clamp_instance_to_viewport { return (x, y); }
+		let margin = self.viewport_margin;
+		(x.max(-margin).min(self.width + margin), y.max(-margin).min(self.height + margin))
+	}
+}
+
+/// Determines how the bars that fill the window outside of the view are drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LetterboxStyle {
+	/// Rectangles filled with the letterbox colour.
+	Rectangle,
+	/// Rectangles filled with the letterbox colour that have corners rounded by a radius in pixels.
+	Rounded(f32),
+	/// Rectangles filled with a gradient from the first colour at the edge of the window
+	/// to the second colour at the edge of the view.
+	Gradient([f32; 4], [f32; 4]),
+}
+
+/// Determines what clicking does while animations are running.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClickPolicy {
+	/// A single click finishes animations and also finishes the text or advances the script.
+	Immediate,
+	/// A click finishes animations, the next click finishes the text, and the next advances the script.
+	Sequential,
+}
+
+/// The type of animation that is applied to every instance on the stage.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum BatchAnimation {
+	Show,
+	Hide,
+	Kill,
+}
+
+/// Determines how the lines of dialogue are aligned in the text box.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TextAlignment {
+	Left,
+	Centre,
+	Right,
+}
+
+impl From<TextAlignment> for graphics::Align {
+	fn from(alignment: TextAlignment) -> Self {
+		match alignment {
+			TextAlignment::Left => graphics::Align::Left,
+			TextAlignment::Centre => graphics::Align::Center,
+			TextAlignment::Right => graphics::Align::Right,
+		}
+	}
 }
 
+/// Determines where the character name is placed horizontally.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NameAlignment {
+	/// Aligned with the left edge of the text box.
+	TextBox,
+	/// Centred above the instance of the speaking character if it is on the stage,
+	/// otherwise aligned with the left edge of the text box.
+	Speaker,
+}
+
+/// Determines where clicking advances the script.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AdvanceClickArea {
+	/// Clicking anywhere advances the script.
+	Anywhere,
+	/// Only clicking inside the dialogue text box advances the script.
+	/// Clicking anywhere advances the script if there is no dialogue displayed.
+	TextBoxOnly,
+	/// Only clicking in the bottom half of the view advances the script.
+	BottomHalf,
+}
+
+#[allow(deprecated)]
 impl Default for Settings {
 	fn default() -> Self {
 		Settings {
@@ -288,17 +2004,756 @@ impl Default for Settings {
 			background_colour: [0.8, 0.8, 0.8, 0.8],
 			foreground_colour: [0.0, 0.0, 0.0, 1.0],
 			secondary_colour: [0.5, 0.5, 0.5, 1.0],
+			letterbox_colour: [0.0, 0.0, 0.0, 1.0],
 			interface_margin: 8.0,
 			text_box_height: 0.25,
+			character_name_min_width: 0.1,
+			character_name_max_width: 0.25,
 			character_name_width: 0.25,
+			character_name_alignment: NameAlignment::TextBox,
+			speaker_highlight: false,
+			inactive_tint: [0.6, 0.6, 0.6],
 			character_name_height: 0.08,
 			branch_button_width: 0.3,
 			branch_button_height: 0.1,
+			branch_button_min_height: 0.05,
+			branch_two_columns: false,
+			quick_menu: false,
+			quick_menu_button_width: 0.1,
+			quick_menu_button_height: 0.05,
+			active_colour: [0.6, 0.8, 0.6, 0.8],
+			auto_delay: 1_500.0,
 			resource_paths: Vec::new(),
+			characters_path: None,
+			save_directory: None,
 			save_path: "/game.save".to_owned(),
+			persistent_path: "/persistent.save".to_owned(),
+			collect_stats: false,
+			stats_path: "/stats.save".to_owned(),
+			stats_flush_interval: 10,
+			save_slots: 3,
+			thumbnail_width: 128,
+			thumbnail_height: 96,
 			music_volume: 1.0,
 			sound_volume: 1.0,
+			cursor_image: None,
+			cursor_hotspot: (0.0, 0.0),
+			ui_hover_sound: None,
+			ui_click_sound: None,
 			developer: true,
+			dedupe_assets: false,
+			batched_drawing: true,
+			scale_factor: 1.0,
+			auto_detect_scale: true,
+			ui_scale: 1.0,
+			auto_detect_ui_scale: false,
+			click_policy: ClickPolicy::Immediate,
+			advance_click_area: AdvanceClickArea::Anywhere,
+			letterbox_style: LetterboxStyle::Rectangle,
+			dialogue_alignment: TextAlignment::Left,
+			narration_alignment: TextAlignment::Left,
+			text_fade_chars: 0,
+			max_dialogue_lines: None,
+			interpolation_prefix: '{',
+			interpolation_suffix: '}',
+			slots: [("left", (160.0, 400.0)), ("centre", (320.0, 400.0)), ("right", (480.0, 400.0))]
+				.iter().map(|(slot, position)| (slot.to_string(), *position)).collect(),
+			clamp_instance_to_viewport: false,
+			viewport_margin: 0.0,
+			high_contrast: false,
+			on_dialogue: None,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use character::InstanceSnapshot;
+
+	#[test]
+	fn persistent_flags() {
+		let mut persistent = Persistent::default();
+		persistent.flags.insert(FlagName("cleared-ending".to_owned()));
+		let serialized = toml::to_string(&persistent).unwrap();
+		let persistent: Persistent = toml::from_str(&serialized).unwrap();
+
+		let state = ScriptState { persistent, ..ScriptState::default() };
+		assert!(state.flag(&FlagName("cleared-ending".to_owned())));
+		assert!(!state.flag(&FlagName("other-ending".to_owned())));
+	}
+
+	#[test]
+	#[cfg(feature = "json")]
+	fn portable_script() {
+		let script = parser::parse("label start\n\"Hello\"\nspawn \"Girl\" \"Happy\" (1, 2)\n\
+			show \"Girl\" with fade[250]\njump start").unwrap();
+		let portable = script.to_portable();
+		let serialized = serde_json::to_string(&portable).unwrap();
+		let deserialized: PortableScript = serde_json::from_str(&serialized).unwrap();
+		assert_eq!(format!("{:?}", deserialized.commands), format!("{:?}", script.commands));
+		assert_eq!(deserialized.labels[&Label::from("start")].0, 0);
+	}
+
+	#[test]
+	fn spawn_defaults() {
+		let mut script = parser::parse("spawn \"Girl\"\nspawn \"Girl\" \"Happy\" as \"Other\"\n\
+			spawn \"Girl\" \"Happy\"\nspawn \"Girl\" as \"Other\"").unwrap();
+		assert!(script.validate(&Settings::default()).is_err());
+
+		let (characters, spawn_defaults) = Characters::parse_with_defaults("[Girl]\ndefault_position = [320.0, 400.0]\n\
+			default_state = \"Happy\"\n\n[Girl.Happy]\nimage = \"/happy.png\"\nscale = [0.5, 0.5]").unwrap();
+		script.characters = characters;
+		script.spawn_defaults = spawn_defaults;
+		script.validate(&Settings::default()).unwrap();
+		match &script.commands[1] {
+			Command::Spawn(_, Some(StateName(state)), None, Some(InstanceName(instance)), None) =>
+				assert_eq!((&**state, &**instance), ("Happy", "Other")),
+			command => panic!("Unexpected command: {:?}", command),
 		}
+		assert!(matches!(&script.commands[2], Command::Spawn(_, Some(_), None, None, None)));
+		assert!(matches!(&script.commands[3], Command::Spawn(_, None, None, Some(_), None)));
+	}
+
+	#[test]
+	fn random_branches() {
+		let script = parser::parse("random\n\t\"Heads\" 3 heads\n\t\"Tails\" 1 tails\n\
+			label heads\nflag heads\njump end\nlabel tails\nflag tails\nlabel end").unwrap();
+		let branches = match &script.commands[0] {
+			Command::Random(branches) => branches,
+			command => panic!("Unexpected command: {:?}", command),
+		};
+		let rolls: Vec<_> = (0..5).map(|roll| weighted_label(branches, roll)).collect();
+		let (heads, tails) = (Label::from("heads"), Label::from("tails"));
+		assert_eq!(rolls, vec![Some(&heads), Some(&heads), Some(&heads), Some(&tails), None]);
+		assert!((0..100).any(|_| random_label(branches) == &tails));
+
+		let result = script.simulate(History { divergences: vec![tails.clone()], ..History::default() });
+		assert!(result.errors.is_empty());
+		assert_eq!(result.history.divergences, vec![tails]);
+		assert!(result.state.flag(&FlagName("tails".to_owned())));
+		assert!(!result.state.flag(&FlagName("heads".to_owned())));
+
+		let script = parser::parse("random\n\t\"Never\" 0 end\nlabel end").unwrap();
+		assert!(script.validate(&Settings::default()).is_err());
+		let script = parser::parse("random\n\t\"Often\" 4000000000 end\n\t\"Again\" 4000000000 end\nlabel end");
+		assert!(script.unwrap().validate(&Settings::default()).is_err());
+		assert!(parser::parse("random\n\t\"Negative\" -1 end\nlabel end").is_err());
+		assert!(parser::parse("random\n\t\"Fraction\" 0.5 end\nlabel end").is_err());
+	}
+
+	#[test]
+	fn chapter_unlocks() {
+		let script = parser::parse("flag prologue\nchapter two \"Chapter Two\" setup-two\nflag two\n\
+			chapter three \"Chapter Three\"\nflag three\njump end\nlabel setup-two\nflag prologue\njump two\nlabel end").unwrap();
+		assert_eq!(script.labels[&Label::from("two")], Target(1));
+		let chapters: Vec<_> = script.chapters().map(Chapter::start).collect();
+		assert_eq!(chapters, vec![&Label::from("setup-two"), &Label::from("three")]);
+		assert!(script.validate(&Settings::default()).is_ok());
+
+		let history = History { start_target: Some(Target(3)), ..History::default() };
+		let result = script.simulate(history);
+		assert_eq!(result.history.start_target, Some(Target(3)));
+		assert!(result.state.flag(&FlagName("three".to_owned())));
+		assert!(!result.state.flag(&FlagName("prologue".to_owned())));
+		let unlocked: Vec<_> = script.unlocked_chapters(&result.state.persistent)
+			.map(|chapter| &chapter.title).collect();
+		assert_eq!(unlocked, vec!["Chapter Three"]);
+
+		let script = parser::parse("chapter two \"Chapter Two\" missing").unwrap();
+		assert!(script.validate(&Settings::default()).is_err());
+	}
+
+	#[test]
+	fn choice_percentages() {
+		let mut stats = ChoiceStats::default();
+		let (left, right) = (Label::from("left"), Label::from("right"));
+		assert_eq!(stats.percentages(&Target(2), vec![&left, &right]), vec![0.0, 0.0]);
+		stats.record(&Target(2), &left);
+		stats.record(&Target(2), &left);
+		stats.record(&Target(2), &left);
+		stats.record(&Target(2), &right);
+		stats.record(&Target(5), &right);
+		assert_eq!(stats.count(&Target(2), &left), 3);
+		assert_eq!(stats.percentages(&Target(2), vec![&left, &right]), vec![75.0, 25.0]);
+
+		let serialized = toml::to_string(&stats).unwrap();
+		assert_eq!(toml::from_str::<ChoiceStats>(&serialized).unwrap(), stats);
+	}
+
+	#[test]
+	fn validate_animation_names() {
+		let script = parser::parse("show \"Girl\" with fade []\nflip \"Girl\" with mirror []").unwrap();
+		assert!(script.validate(&Settings::default()).is_ok());
+		let script = parser::parse("hide \"Girl\" with spin []").unwrap();
+		assert_eq!(script.validate(&Settings::default()), Err("Animation: spin, is not registered for this command".to_owned()));
+		let script = parser::parse("hide \"Girl\" with mirror []").unwrap();
+		assert!(script.validate(&Settings::default()).is_err());
+		let script = parser::parse("flip \"Girl\" with fade []").unwrap();
+		assert!(!script.commands[0].animation_registered(&script.animations));
+
+		let animations = AnimationMap::default();
+		assert!(animations.contains("mirror") && !animations.contains("spin"));
+		assert_eq!(animations.all_names(), ["flip", "mirror", "fade", "glide"].iter().cloned().collect());
+		assert_eq!(animations.position_names().collect::<Vec<_>>(), vec!["glide"]);
+	}
+
+	#[test]
+	fn simulate_divergences() {
+		let script = parser::parse("flag seen\nlabel start\nrepeat start 2\ndiverge\n\
+			\t\"Left\" left\n\t\"Right\" right\nlabel left\nflag left\ndiverge\n\t\"Again\" start\n\
+			label right\nif seen end\nflag unreachable\nlabel end").unwrap();
+		let result = script.simulate(History::default());
+		assert_eq!(result.diverge.map(|Target(target)| target), Some(2));
+		assert!(result.errors.is_empty());
+
+		let divergences = vec![Label::from("left"), Label::from("start"), Label::from("right")];
+		let result = script.simulate(History { divergences: divergences.clone(), ..History::default() });
+		assert!(result.diverge.is_none());
+		assert_eq!(result.history.divergences, divergences);
+		assert!(result.state.flags.contains(&FlagName("left".to_owned())));
+		assert!(!result.state.flags.contains(&FlagName("unreachable".to_owned())));
+	}
+
+	#[test]
+	fn simulate_assertions() {
+		let script = parser::parse("flag seen\nassert seen true\nlabel middle\nassertlabel middle\n\
+			assert other false\nsetcounter score 3\nassertcount score 3\nlabel end\nassertlabel middle").unwrap();
+		let result = script.simulate(History::default());
+		assert_eq!(result.errors, vec!["Assertion failed: label: middle, \
+			was expected to be passed but label: end, was passed".to_owned()]);
+
+		let script = parser::parse("setcounter score 3\nassertcount score 4").unwrap();
+		assert_eq!(script.simulate(History::default()).errors,
+			vec!["Assertion failed: counter: score, is 3 but was expected to be 4".to_owned()]);
+	}
+
+	#[test]
+	fn repeat_loops() {
+		let script = parser::parse("repeat start\nrepeat start 3\nrepeat start if looping").unwrap();
+		let start = Label::from("start");
+		assert_eq!(script.commands, vec![Command::Repeat(start.clone()), Command::RepeatN(start.clone(), 3),
+			Command::RepeatIf(start, FlagName("looping".to_owned()))]);
+
+		let script = parser::parse("setcounter visits 0\nlabel loop\naddcounter visits 1\nrepeat loop 2\n\
+			assertcount visits 3\nflag looping\nlabel again\naddcounter visits 1\nunflag looping\n\
+			repeat again if looping\nassertcount visits 4").unwrap();
+		let result = script.simulate(History::default());
+		assert!(result.errors.is_empty(), "{:?}", result.errors);
+		assert!(result.state.loop_counters.is_empty());
+	}
+
+	#[test]
+	fn move_destinations() {
+		let settings = Settings { clamp_instance_to_viewport: true, viewport_margin: 0.0, ..Settings::default() };
+		let chained = move_destination(&settings, move_destination(&settings, (100.0, 100.0), (50.0, 0.0)), (0.0, 25.0));
+		assert_eq!(chained, (150.0, 125.0));
+
+		// An interrupted move finishes at its destination before the next offset is applied.
+		let interrupted = move_destination(&settings, (100.0, 100.0), (50.0, 0.0));
+		assert_eq!(move_destination(&settings, interrupted, (50.0, 0.0)), (200.0, 100.0));
+
+		let clamped = move_destination(&settings, (settings.width - 10.0, 0.0), (50.0, -50.0));
+		assert_eq!(clamped, (settings.width, 0.0));
+		assert_eq!(move_destination(&settings, clamped, (-20.0, 0.0)), (settings.width - 20.0, 0.0));
+	}
+
+	#[test]
+	fn group_expansion() {
+		let script = parser::parse("group \"cast\" [\"Girl\", \"Boy\"]\nungroup \"cast\"").unwrap();
+		let mut state = ScriptState::default();
+		script.commands[0].execute_state(&mut state, &script);
+		let names = |names: &[&str]| names.iter().map(|name| InstanceName::from(*name)).collect::<Vec<_>>();
+		assert_eq!(state.expand(&InstanceName::from("#cast")), names(&["Girl", "Boy"]));
+		assert_eq!(state.expand(&InstanceName::from("Girl")), names(&["Girl"]));
+		assert_eq!(state.expand(&InstanceName::from("#crew")), names(&["#crew"]));
+
+		script.commands[1].execute_state(&mut state, &script);
+		assert_eq!(state.expand(&InstanceName::from("#cast")), names(&["#cast"]));
+	}
+
+	#[test]
+	fn resolve_aliases() {
+		let script = parser::parse("alias \"Hero\" \"Boy\"\ngroup \"cast\" [\"Girl\", \"Hero\"]\n\
+			flip \"Hero\"\nrename \"Hero\" \"Other\"\ndraworder [\"#cast\"]\nflip \"Girl\"").unwrap();
+		let mut state = ScriptState::default();
+		script.commands.iter().take(2).for_each(|command| command.execute_state(&mut state, &script));
+		let resolved: Vec<_> = script.commands[2..].iter().map(|command| command.resolve_instances(&state)).collect();
+		assert_eq!(resolved, vec![
+			Some(Command::Flip(InstanceName::from("Boy"), None)),
+			Some(Command::Rename(InstanceName::from("Boy"), InstanceName::from("Other"))),
+			Some(Command::SetDrawOrder(vec![InstanceName::from("Girl"), InstanceName::from("Boy")])),
+			None,
+		]);
+	}
+
+	#[test]
+	fn label_ties() {
+		let script = parser::parse("flag a\nlabel zeta\nlabel alpha\nlabel middle\nflag b\nlabel end").unwrap();
+		assert_eq!(script.get_label_at(&Target(1)), Some(&Label::from("alpha")));
+		assert_eq!(script.nearest_preceding_label(&Target(1)), Some(&Label::from("alpha")));
+		assert_eq!(script.nearest_preceding_label(&Target(0)), None);
+		assert_eq!(script.get_label_at(&Target(2)), Some(&Label::from("end")));
+	}
+
+	#[test]
+	fn referenced_resources() {
+		let mut script = parser::parse("stage \"/background.png\"\nmusic \"/music.ogg\"\n\
+			sound \"/sound.ogg\"\ntextfont \"/font.ttf\" 16\nnamefont \"\" 16\n\
+			transition \"/transition.png\" 500\nsprite \"Girl\" \"/letter.png\"").unwrap();
+		script.characters = Characters::parse("[Girl.Happy]\nimage = \"/happy.png\"\nscale = [0.5, 0.5]\n\
+			overlay = \"/smile.png\"\nspawn_sound = \"/poof.ogg\"").unwrap();
+
+		let mut resources: Vec<_> = script.referenced_resources().map(|resource|
+			(resource.path.to_str().unwrap(), resource.kind, match resource.source {
+				ResourceSource::Command(Target(target)) => Some(target),
+				ResourceSource::State(_, _) => None,
+			})).collect();
+		resources.sort_by_key(|(path, _, _)| *path);
+		assert_eq!(resources, vec![
+			("/background.png", ResourceKind::Image, Some(0)),
+			("/font.ttf", ResourceKind::Font, Some(3)),
+			("/happy.png", ResourceKind::Image, None),
+			("/letter.png", ResourceKind::Image, Some(6)),
+			("/music.ogg", ResourceKind::Music, Some(1)),
+			("/poof.ogg", ResourceKind::Sound, None),
+			("/smile.png", ResourceKind::Image, None),
+			("/sound.ogg", ResourceKind::Sound, Some(2)),
+			("/transition.png", ResourceKind::Image, Some(5)),
+		]);
+	}
+
+	#[test]
+	fn interned_names() {
+		let source = "spawn \"Girl\" \"Happy\" (1, 2)\nchange \"Girl\" \"Happy\"\njump start\n".repeat(10_000);
+		let script = parser::parse(&format!("label start\n{}", source)).unwrap();
+		let names: HashSet<_> = script.commands.iter().filter_map(|command| match command {
+			Command::Spawn(CharacterName(name), _, _, _, _) | Command::Change(InstanceName(name), _, _) |
+			Command::Jump(Label(name), _) => Some(name.as_ptr()),
+			_ => None,
+		}).collect();
+		assert_eq!(names.len(), 2);
+		assert_eq!(Label::from("start"), script.labels.keys().next().unwrap().clone());
+	}
+
+	#[test]
+	fn parse_flip() {
+		let script = parser::parse("flip \"Girl\"\nflip \"Girl\" with mirror[500.0]\nflipvertical \"Girl\"\n").unwrap();
+		assert!(matches!(&script.commands[..], [Command::Flip(_, None), Command::Flip(_, Some(animation)),
+			Command::FlipVertical(_)] if animation.name == "mirror" && animation.arguments == [Some(500.0)]));
+	}
+
+	#[test]
+	fn parse_aliases() {
+		let script = parser::parse("enter \"Girl\" \"Happy\" (100, 200)\nexpression \"Girl\" \"Sad\"\n").unwrap();
+		assert!(matches!(&script.commands[..], [Command::Spawn(_, Some(StateName(happy)), Some(position), None, None),
+			Command::Change(_, StateName(sad), None)] if &**happy == "Happy" && *position == Placement::Point(100.0, 200.0) && &**sad == "Sad"));
+	}
+
+	#[test]
+	fn parse_slots() {
+		let mut script = parser::parse("spawn \"Girl\" \"Happy\" slot left\nposition \"Girl\" slot centre with glide[400]\n").unwrap();
+		assert!(matches!(&script.commands[..], [Command::Spawn(_, _, Some(Placement::Slot(left)), None, None),
+			Command::Position(_, Placement::Slot(centre), Some(_))] if left == "left" && centre == "centre"));
+		script.characters = Characters::parse("[Girl.Happy]\nimage = \"/happy.png\"\nscale = [0.5, 0.5]").unwrap();
+		script.validate(&Settings::default()).unwrap();
+		script.commands.push(Command::Copy(InstanceName::from("Girl"), InstanceName::from("Copy"),
+			Placement::Slot("middle".to_owned())));
+		assert!(script.validate(&Settings::default()).is_err());
+	}
+
+	#[test]
+	fn parse_character_commands() {
+		let script = parser::parse("charposition \"Alice\" (320, 240) with glide[500.0]\ncharshow \"Alice\"\n\
+			charhide \"Alice\" with fade[]\ncharkill \"Alice\"\n").unwrap();
+		assert!(matches!(&script.commands[..], [Command::CharacterPosition(CharacterName(name), Placement::Point(x, y), Some(_)),
+			Command::CharacterShow(_, None), Command::CharacterHide(_, Some(_)), Command::CharacterKill(_, None)]
+			if &**name == "Alice" && (*x, *y) == (320.0, 240.0)));
+		let command = script.commands[0].instance_command(InstanceName::from("Alice-happy")).unwrap();
+		assert!(matches!(command, Command::Position(InstanceName(name), _, Some(_)) if &*name == "Alice-happy"));
+	}
+
+	#[test]
+	fn parse_error_context() {
+		let errors = parser::parse("pause\nshow 5\npause\n").unwrap_err();
+		assert_eq!(errors.len(), 1);
+		assert_eq!(errors[0].error, parser::ParserError::ExpectedString);
+		assert_eq!(errors[0].context, "pause\nshow 5");
+	}
+
+	#[test]
+	fn parse_move() {
+		let script = parser::parse("move \"Girl\" (-20, 0)\nmove \"Girl\" (-20, 5.5) with glide[200]\n").unwrap();
+		assert!(matches!(&script.commands[..], [Command::Move(_, (x, y), None), Command::Move(_, offset, Some(animation))]
+			if (*x, *y) == (-20.0, 0.0) && *offset == (-20.0, 5.5) && animation.arguments == [Some(200.0)]));
+	}
+
+	#[test]
+	fn parse_opacity() {
+		let script = parser::parse("opacity \"Ghost\" 0.5\nopacity \"Ghost\" 1 with fade[300]\n").unwrap();
+		assert!(matches!(&script.commands[..], [Command::Opacity(_, half, None), Command::Opacity(_, full, Some(animation))]
+			if *half == 0.5 && *full == 1.0 && animation.name == "fade" && animation.arguments == [Some(300.0)]));
+	}
+
+	#[test]
+	fn parse_music_loop() {
+		let script = parser::parse("musicloop \"/song.ogg\" 4.5 120.0\n").unwrap();
+		assert_eq!(script.commands, [Command::MusicLoop("/song.ogg".into(), 4.5, 120.0)]);
+	}
+
+	#[test]
+	fn parse_dialogue_alignment() {
+		let script = parser::parse("\"The next morning...\" align centre\n\"Girl\" \"Hello\" align right\n\
+			\"Girl\" \"Bye\"\n").unwrap();
+		assert!(matches!(&script.commands[..], [Command::Dialogue(None, _, Some(TextAlignment::Centre)),
+			Command::Dialogue(Some(_), _, Some(TextAlignment::Right)), Command::Dialogue(Some(_), _, None)]));
+		assert!(parser::parse("\"Hello\" align middle\n").is_err());
+	}
+
+	#[test]
+	fn parse_animate_all() {
+		let script = parser::parse("animateall show with fade[500.0]\nanimateall kill with glide[_]\n").unwrap();
+		assert!(matches!(&script.commands[..], [Command::AnimateAll(BatchAnimation::Show, show),
+			Command::AnimateAll(BatchAnimation::Kill, kill)] if show.name == "fade" && kill.arguments == [None]));
+		assert!(parser::parse("animateall show\n").is_err());
+		assert!(parser::parse("animateall spin with fade[]\n").is_err());
+	}
+
+	#[test]
+	fn counter_conditions() {
+		let script = parser::parse("setcounter affection 40\naddcounter affection 10\n\
+			ifcount affection >= 50 good-ending\nifbetween affection 30 49 neutral-ending\n\
+			label good-ending\nlabel neutral-ending\n").unwrap();
+		assert!(matches!(&script.commands[2], Command::IfCounter(counter, Comparison::Ge, 50, Label(label))
+			if counter == "affection" && &**label == "good-ending"));
+		assert!(matches!(&script.commands[3], Command::IfBetween(_, 30, 49, _)));
+
+		let mut state = ScriptState::default();
+		script.commands.iter().take(2).for_each(|command| command.execute_state(&mut state, &script));
+		assert_eq!(state.counter("affection"), 50);
+		script.commands[3].execute_state(&mut state, &script);
+		assert_eq!(state.next_target, None);
+		script.commands[2].execute_state(&mut state, &script);
+		assert_eq!(state.next_target, Some(script.labels[&Label::from("good-ending")].clone()));
+		assert!(parser::parse("ifcount affection => 50 good-ending\n").is_err());
+		assert!(parser::parse("setcounter affection 49.9\n").is_err());
+		assert!(parser::parse("addcounter affection 1e12\n").is_err());
+		assert!(parser::parse("setcounter affection -2147483648\n").is_ok());
+
+		state.counters.insert("affection".to_owned(), i32::MAX - 1);
+		Command::AddCounter("affection".to_owned(), 10).execute_state(&mut state, &script);
+		assert_eq!(state.counter("affection"), i32::MAX);
+	}
+
+	#[test]
+	fn dialogue_interpolation() {
+		let mut state = ScriptState::default();
+		state.counters.insert("score".to_owned(), -12);
+		state.flags.insert(FlagName("won".to_owned()));
+		let settings = Settings::default();
+		assert_eq!(state.interpolate("Score: {score}, won: {won}, lost: {lost}", &settings),
+			"Score: -12, won: true, lost: false");
+		assert_eq!(state.interpolate("Literal \\{score\\} and {unclosed", &settings),
+			"Literal {score} and {unclosed");
+
+		let settings = Settings { interpolation_prefix: '<', interpolation_suffix: '>', ..settings };
+		assert_eq!(state.interpolate("<score> {score}", &settings), "-12 {score}");
+		let escaped = escape_interpolation("{score}", ('{', '}'));
+		assert_eq!(interpolate(&escaped, ('{', '}'), |_| unreachable!()), "{score}");
+	}
+
+	#[test]
+	fn parse_together() {
+		let script = parser::parse("label start\ntogether\n\thide \"Girl\"\n\n\ttogether\n\t\tshow \"Boy\"\n\
+			\t\tpan 10 0 500\n\tflag seen\npause\n").unwrap();
+		assert_eq!(script.commands.len(), 2);
+		assert_eq!(script.labels[&Label::from("start")], Target(0));
+		let commands = match &script.commands[0] {
+			Command::Together(commands) => commands,
+			command => panic!("Unexpected command: {:?}", command),
+		};
+		assert!(matches!(&commands[..], [Command::Hide(_, None), Command::Together(nested), Command::Flag(_)]
+			if matches!(&nested[..], [Command::Show(_, None), Command::Pan(_, _, _)])));
+		assert_eq!(script.commands[0].nested().len(), 6);
+
+		let mut state = ScriptState::default();
+		script.commands[0].execute_state(&mut state, &script);
+		assert!(state.flag(&FlagName("seen".to_owned())));
+
+		let errors = parser::parse("together\n\tshow \"Girl\"\n\t\"Hello\"\npause\n").unwrap_err();
+		assert_eq!(errors.iter().map(|error| &error.error).collect::<Vec<_>>(), vec![&parser::ParserError::BlockingCommand]);
+		assert!(parser::parse("together\n\tlabel inside\n").is_err());
+	}
+
+	#[test]
+	fn delay_until() {
+		let script = parser::parse("delay 1500\ndelay 1e30\n").unwrap();
+		assert_eq!(script.commands, vec![Command::Delay(1500.0), Command::Delay(1e30)]);
+		assert_eq!(parser::parse("delay 1e39\n").unwrap_err()[0].error, parser::ParserError::InvalidNumeric);
+		assert_eq!(parser::parse("delay -5\n").unwrap_err()[0].error, parser::ParserError::InvalidNumeric);
+
+		let (mut state, mut render) = (ScriptState::default(), Render::default());
+		let start = Instant::now();
+		script.commands[0].wait(&mut state, &mut render);
+		let delay = state.delay_until.unwrap() - start;
+		assert!(delay >= Duration::from_millis(1_500) && delay < Duration::from_secs(60));
+		script.commands[1].wait(&mut state, &mut render);
+		assert_eq!(state.delay_until, None);
+	}
+
+	#[test]
+	fn parse_sync() {
+		let script = parser::parse("sync\nflag done").unwrap();
+		assert_eq!(script.commands[0], Command::Sync);
+		assert!(script.commands[0].is_blocking());
+		let result = script.simulate(History::default());
+		assert!(result.errors.is_empty());
+		assert!(result.state.flag(&FlagName("done".to_owned())));
+	}
+
+	#[test]
+	fn parse_pause() {
+		let script = parser::parse("pause\npause clear\npause 2000\npause clear 500.5\n").unwrap();
+		assert_eq!(script.commands, vec![Command::Pause(false, None), Command::Pause(true, None),
+			Command::Pause(false, Some(2000.0)), Command::Pause(true, Some(500.5))]);
+		assert!(script.commands.iter().all(Command::is_blocking));
+		assert_eq!(parser::parse("pause 1e39\n").unwrap_err()[0].error, parser::ParserError::InvalidNumeric);
+
+		let (mut state, mut render) = (ScriptState::default(), Render::default());
+		let text = || TextBox::new(RenderText::empty(String::new(), [0.0; 4]), (0.0, 0.0), (10.0, 10.0), [0.0; 4]);
+		render.text = Some(text());
+		render.character = Some(text());
+		script.commands[2].wait(&mut state, &mut render);
+		assert!(state.delay_until.unwrap() > Instant::now() + Duration::from_millis(1_000));
+		assert!(render.text.is_some() && render.character.is_some());
+		script.commands[1].wait(&mut state, &mut render);
+		assert_eq!(state.delay_until, None);
+		assert!(render.text.is_none() && render.character.is_none());
+	}
+
+	#[test]
+	fn log_messages() {
+		let script = parser::parse("log \"Reached scene 3\"\nlogcount \"affection\"\n\
+			logcount affection \"After the date\"\nlogflag seen\n").unwrap();
+		let mut state = ScriptState::default();
+		state.counters.insert("affection".to_owned(), 7);
+		let messages: Vec<_> = script.commands.iter().map(|command| command.log_message(&state).unwrap()).collect();
+		assert_eq!(messages, vec!["Reached scene 3", "Counter: affection = 7",
+			"After the date: affection = 7", "Flag: seen is not set"]);
+		assert!(!script.commands.iter().any(Command::is_blocking));
+	}
+
+	#[test]
+	fn dialogue_hook_replaying() {
+		let lines = Arc::new(std::sync::Mutex::new(Vec::new()));
+		let recorded = lines.clone();
+		let settings = Settings {
+			on_dialogue: Some(DialogueHook::new(move |character, text|
+				recorded.lock().unwrap().push(format!("{:?}: {}", character, text)))),
+			..Settings::default()
+		};
+
+		let mut state = ScriptState { replaying: true, ..ScriptState::default() };
+		announce_dialogue(&state, &settings, None, "Earlier line");
+		state.replaying = false;
+		announce_dialogue(&state, &settings, Some(&CharacterName::from("Girl")), "Hello");
+		assert_eq!(*lines.lock().unwrap(), vec!["Some(\"Girl\"): Hello"]);
+	}
+
+	#[test]
+	fn high_contrast_colours() {
+		let mut settings = Settings::default();
+		settings.apply_high_contrast();
+		assert_eq!(settings.background_colour, Settings::default().background_colour);
+		settings.high_contrast = true;
+		settings.apply_high_contrast();
+		assert_eq!((settings.foreground_colour, settings.background_colour), ([1.0; 4], [0.0, 0.0, 0.0, 1.0]));
+	}
+
+	#[test]
+	fn clamp_to_viewport() {
+		let mut settings = Settings::default();
+		assert_eq!(settings.clamp_position((-50.0, 900.0)), (-50.0, 900.0));
+		settings.clamp_instance_to_viewport = true;
+		assert_eq!(settings.clamp_position((-50.0, 900.0)), (0.0, 480.0));
+		settings.viewport_margin = 20.0;
+		assert_eq!(settings.clamp_position((-50.0, 100.0)), (-20.0, 100.0));
+	}
+
+	#[test]
+	fn parse_tween() {
+		let script = parser::parse("tween \"Girl\" (100, 200) (400, 300) 1000.0\n").unwrap();
+		assert!(matches!(&script.commands[..], [Command::TweenPosition(InstanceName(name), Placement::Point(x, y), end, duration)]
+			if &**name == "Girl" && (*x, *y) == (100.0, 200.0) && *end == Placement::Point(400.0, 300.0) && *duration == 1000.0));
+	}
+
+	#[test]
+	fn parse_draw_order() {
+		let script = parser::parse("draworder [\"alice\", \"bob\", \"overlay\"]\ncleardraworder\n").unwrap();
+		assert!(matches!(&script.commands[..], [Command::SetDrawOrder(names), Command::ClearDrawOrder]
+			if names.iter().map(|name| &**name).eq(["alice", "bob", "overlay"].iter().copied())));
+		let script = parser::parse("front \"alice\"\nback \"#cast\"\n").unwrap();
+		assert!(matches!(&script.commands[..], [Command::Front(InstanceName(front)), Command::Back(InstanceName(back))]
+			if &**front == "alice" && &**back == "#cast"));
+	}
+
+	#[test]
+	fn script_diff() {
+		let old = parser::parse("pause\nlabel middle\nflag a\nflag b\npause\n").unwrap();
+		let new = parser::parse("pause\nlabel middle\nflag a\nflag c\nflag d\npause\n").unwrap();
+		let diff = Script::diff(&old, &new);
+		assert_eq!((diff.changed, diff.added, diff.removed), (vec![2], vec![3], vec![]));
+		assert!(diff.label_changes.is_empty());
+
+		let diff = Script::diff(&new, &old);
+		assert_eq!((&diff.changed, &diff.removed), (&vec![2], &vec![3]));
+		assert!(diff.is_safe_to_continue_from(&Target(1)));
+		assert!(!diff.is_safe_to_continue_from(&Target(2)));
+
+		let moved = parser::parse("pause\npause\nlabel middle\nflag a\nflag b\npause\n").unwrap();
+		let diff = Script::diff(&old, &moved);
+		assert_eq!(diff.label_changes[&Label::from("middle")], (Target(1), Target(2)));
+		assert!(diff.is_safe_to_continue_from(&Target(0)));
+		assert!(Script::diff(&old, &old) == ScriptDiff::default());
+	}
+
+	#[test]
+	fn jump_transition() {
+		let script = parser::parse("label start\njump start with fade[400]\njump start\n").unwrap();
+		assert!(matches!(&script.commands[..], [Command::Jump(_, Some(animation)), Command::Jump(_, None)]
+			if animation.name == "fade" && animation.arguments == [Some(400.0)]));
+		assert!(script.validate(&Settings::default()).is_ok());
+		assert!(parser::parse("label start\njump start with glide[400]\n").unwrap().validate(&Settings::default()).is_err());
+	}
+
+	#[test]
+	fn lint_never_spawned() {
+		let script = parser::parse("spawn \"Girl\" \"Happy\" (1, 2) \"Other\"\nalias \"Friend\" \"Other\"\n\
+			copy \"Other\" \"Copy\" (3, 4)\nrename \"Copy\" \"Renamed\"\nshow \"Other\"\nhide \"Friend\"\n\
+			show \"Renamed\"\nkill \"#group\"\nkill \"Gril\"").unwrap();
+		assert_eq!(script.lint(), vec!["Instance: Gril, is used by `kill` but is never spawned".to_owned()]);
+	}
+
+	#[test]
+	fn history_snapshot() {
+		let instance = InstanceSnapshot {
+			character: CharacterName::from("Character"),
+			state: StateName::from("Happy"),
+			sprite: Some("/letter.png".into()),
+			position: (320.0, 240.0),
+			scale: (0.5, 0.5),
+			rotation: 0.05,
+			flip_h: true,
+			flip_v: false,
+			colour: [1.0, 1.0, 1.0, 0.5],
+			visible: true,
+		};
+
+		let mut snapshot = Snapshot { target: 4, ..Snapshot::default() };
+		snapshot.flags.insert(FlagName("sighed".to_owned()));
+		snapshot.music = MusicState::Paused { path: "/music.ogg".into(), position: 12.5 };
+		snapshot.background = Some("/background.jpg".into());
+		snapshot.aliases.insert(InstanceName::from("Stranger"), InstanceName::from("Character"));
+		snapshot.groups.insert("cast".to_owned(), vec![InstanceName::from("Character")]);
+		snapshot.loop_counters.insert(Label::from("bruh-moment-end"), 2);
+		snapshot.counters.insert("affection".to_owned(), -5);
+		snapshot.dialogue_font = Some(FontDeclaration { path: "/fonts/serif.ttf".into(), size: 18.0 });
+		snapshot.draw_order = Some(vec![InstanceName::from("Character")]);
+		snapshot.instances.instances.push((InstanceName::from("Stranger"), instance.clone()));
+		snapshot.instances.instances.push((InstanceName::from("Character"), instance));
+
+		let divergences = vec![Label::from("bruh-moment-sigh")];
+		let history = History { divergences, execution_count: 12, snapshot: Some(snapshot), ..History::default() };
+		let serialized = toml::to_string(&history).unwrap();
+		assert_eq!(History::parse(serialized.as_bytes()).unwrap(), history);
+	}
+
+	#[test]
+	fn history_without_snapshot() {
+		let history = History::parse(b"divergences = []\nexecution_count = 3").unwrap();
+		assert_eq!(history, History { execution_count: 3, ..History::default() });
+	}
+
+	#[test]
+	fn history_music_position() {
+		let history = History { execution_count: 3, music_position: Some(42.5), ..History::default() };
+		let serialized = toml::to_string(&history).unwrap();
+		assert!(serialized.contains("music_position = 42.5"));
+		assert_eq!(History::parse(serialized.as_bytes()).unwrap(), history);
+
+		let serialized = toml::to_string(&History { execution_count: 3, ..History::default() }).unwrap();
+		assert!(!serialized.contains("music_position"));
+		assert_eq!(History::parse(serialized.as_bytes()).unwrap().music_position, None);
+	}
+
+	#[test]
+	fn history_version_0() {
+		let history = History::parse(include_bytes!("fixtures/history_v0.toml")).unwrap();
+		let divergences = vec![Label::from("bruh-moment-sigh")];
+		assert_eq!(history, History { divergences, execution_count: 12, ..History::default() });
+	}
+
+	#[test]
+	fn history_version_1() {
+		let history = History::parse(include_bytes!("fixtures/history_v1.toml")).unwrap();
+		let divergences = vec![Label::from("bruh-moment-sigh")];
+		assert_eq!(history.version, HISTORY_VERSION);
+		assert_eq!(history.divergences, divergences);
+
+		let snapshot = history.snapshot.unwrap();
+		let (name, instance) = &snapshot.instances.instances[0];
+		assert_eq!(name, &InstanceName::from("Character"));
+		assert_eq!((instance.scale, instance.colour), ((1.0, 1.0), [1.0; 4]));
+		assert_eq!(snapshot.target, 4);
+	}
+
+	#[test]
+	fn history_version_2() {
+		let history = History::parse(include_bytes!("fixtures/history_v2.toml")).unwrap();
+		let snapshot = history.snapshot.unwrap();
+		let (name, instance) = &snapshot.instances.instances[0];
+		assert_eq!(name, &InstanceName::from("Character"));
+		assert_eq!((instance.scale, instance.colour), ((0.5, 0.5), [1.0, 1.0, 1.0, 0.5]));
+		assert_eq!(instance.sprite, Some("/letter.png".into()));
+		assert_eq!(snapshot.music, MusicState::Playing { path: "/music.ogg".into() });
+	}
+
+	#[test]
+	fn history_version_3() {
+		let history = History::parse(include_bytes!("fixtures/history_v3.toml")).unwrap();
+		let snapshot = history.snapshot.unwrap();
+		let names: Vec<_> = snapshot.instances.instances.iter().map(|(name, _)| name.clone()).collect();
+		assert_eq!(names, ["Stranger", "Bystander", "Character"].iter()
+			.map(|name| InstanceName::from(*name)).collect::<Vec<_>>());
+		assert_eq!(snapshot.instances.instances[0].1.state, StateName::from("Sad"));
+	}
+
+	#[test]
+	fn history_truncate() {
+		let mut history = History { divergences: vec![Label::from("old")], ..History::default() };
+		["first", "second", "third"].iter().zip(&[3, 7, 12]).for_each(|(label, position)| {
+			history.execution_count = *position;
+			history.diverge(Label::from(*label));
+		});
+
+		history.truncate_to(7);
+		assert_eq!(history.execution_count, 7);
+		assert_eq!(history.divergences, vec![Label::from("old"), Label::from("first")]);
+		assert_eq!(history.divergence_positions, vec![3]);
+
+		history.clear_divergences();
+		assert!(history.divergences.is_empty() && history.divergence_positions.is_empty());
+
+		let mut history = History { divergences: vec![Label::from("first")],
+			divergence_positions: vec![3, 7, 12], ..History::default() };
+		history.truncate_to(10);
+		assert_eq!(history.divergences, vec![Label::from("first")]);
+		assert_eq!(history.divergence_positions, vec![3, 7]);
+	}
+
+	#[test]
+	fn history_newer_version() {
+		let error = History::parse(b"version = 4294967295\ndivergences = []\nexecution_count = 3");
+		assert!(error.unwrap_err().starts_with("Save was made by a newer version"));
 	}
 }
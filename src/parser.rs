@@ -1,6 +1,9 @@
-use crate::{Command, FlagName, Label, lexer::Lexer, Script, Target};
+use std::sync::Arc;
+
+use crate::{BatchAnimation, Chapter, Command, FlagName, Label, lexer::Lexer, Script, Target, TextAlignment};
 use crate::animation::AnimationDeclaration;
 use crate::character::{CharacterName, InstanceName, StateName};
+use crate::script_types::{Comparison, Placement};
 
 #[derive(Debug, PartialEq)]
 pub enum Token {
@@ -28,9 +31,36 @@ pub enum ParserError {
 	UnexpectedToken,
 	InvalidCommand,
 	InvalidNumeric,
+	/// A command that waits or changes which command runs next is in a `together` block.
+	BlockingCommand,
+}
+
+/// An error found while parsing along with the source text just before it.
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+	pub error: ParserError,
+	/// The end of the source text that had been consumed when the error was found.
+	pub context: String,
+}
+
+impl std::fmt::Display for ParseError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "{:?} after: {:?}", self.error, self.context)
+	}
+}
+
+/// Amount of bytes of consumed source text that are shown with an error.
+const ERROR_CONTEXT_LENGTH: usize = 40;
+
+/// Takes up to the last `ERROR_CONTEXT_LENGTH` bytes of the consumed source,
+/// starting at a character boundary.
+fn error_context(consumed: &str) -> String {
+	let start = (consumed.len().saturating_sub(ERROR_CONTEXT_LENGTH)..=consumed.len())
+		.find(|index| consumed.is_char_boundary(*index)).unwrap_or(consumed.len());
+	consumed[start..].to_owned()
 }
 
-pub fn parse(string: &str) -> Result<Script, Vec<ParserError>> {
+pub fn parse(string: &str) -> Result<Script, Vec<ParseError>> {
 	let mut errors = Vec::new();
 	let mut script = Script::default();
 	let lexer = &mut Lexer::new(string);
@@ -40,8 +70,9 @@ pub fn parse(string: &str) -> Result<Script, Vec<ParserError>> {
 			Ok(false) => (),
 			Ok(true) => break,
 			Err((error, target)) => {
+				let context = error_context(lexer.consumed_source());
 				lexer.skip_take(target);
-				errors.push(error);
+				errors.push(ParseError { error, context });
 			}
 		}
 	}
@@ -64,64 +95,307 @@ pub fn parse_command(lexer: &mut Lexer, script: &mut Script) -> Result<bool, (Pa
 		Token::Terminator => (),
 		Token::Identifier(identifier) => match identifier.as_str() {
 			"change" => {
-				let instance = InstanceName(inline(lexer.string())?);
-				let state = StateName(inline(lexer.string())?);
+				let instance = InstanceName(name(lexer)?);
+				let state = StateName(name(lexer)?);
 				let animation = animation(lexer)?;
 				script.commands.push(Command::Change(instance, state, animation));
 			}
+			"expression" => {
+				let instance = InstanceName(name(lexer)?);
+				let state = StateName(name(lexer)?);
+				script.commands.push(Command::Change(instance, state, None));
+			}
 			"diverge" => {
 				inline(lexer.expect(Token::Terminator))?;
 				inline(lexer.expect(Token::ScopeOpen))?;
 				parse_diverge(lexer, script).map_err(|error| (error, Token::ScopeClose))?;
 			}
+			"together" => {
+				inline(lexer.expect(Token::Terminator))?;
+				inline(lexer.expect(Token::ScopeOpen))?;
+				parse_together(lexer, script)?;
+			}
+			"random" => {
+				inline(lexer.expect(Token::Terminator))?;
+				inline(lexer.expect(Token::ScopeOpen))?;
+				parse_random(lexer, script).map_err(|error| (error, Token::ScopeClose))?;
+			}
 			"label" => {
-				let label = Label(inline(lexer.identifier())?);
+				let label = Label(identifier_name(lexer)?);
 				script.labels.insert(label, Target(script.commands.len()));
 			}
+			"chapter" => {
+				let label = Label(identifier_name(lexer)?);
+				let title = inline(lexer.string())?;
+				let init = match inline(lexer.peek())? {
+					Some(Token::Identifier(_)) => Some(Label(identifier_name(lexer)?)),
+					_ => None,
+				};
+				script.labels.insert(label.clone(), Target(script.commands.len()));
+				script.commands.push(Command::Chapter(Chapter { label, title, init }));
+			}
 			"position" => {
-				let instance = InstanceName(inline(lexer.string())?);
-				let position = position(lexer)?;
+				let instance = InstanceName(name(lexer)?);
+				let position = placement(lexer)?;
 				let animation = animation(lexer)?;
 				script.commands.push(Command::Position(instance, position, animation));
 			}
+			"move" => {
+				let instance = InstanceName(name(lexer)?);
+				let offset = position(lexer)?;
+				script.commands.push(Command::Move(instance, offset, animation(lexer)?));
+			}
+			"tween" => {
+				let instance = InstanceName(name(lexer)?);
+				let (start, end) = (placement(lexer)?, placement(lexer)?);
+				script.commands.push(Command::TweenPosition(instance, start, end, inline(lexer.numeric())?));
+			}
 			"spawn" => {
-				let character = CharacterName(inline(lexer.string())?);
-				let state = StateName(inline(lexer.string())?);
-				let position = position(lexer)?;
+				let character = CharacterName(name(lexer)?);
+				let state = match inline(lexer.peek())? {
+					Some(Token::String(_)) => Some(StateName(name(lexer)?)),
+					_ => None,
+				};
+
+				let position = match inline(lexer.peek())? {
+					Some(Token::BracketOpen) => Some(placement(lexer)?),
+					Some(Token::Identifier(identifier)) if identifier == "slot" => Some(placement(lexer)?),
+					_ => None,
+				};
+
+				// A second string is always the state so an instance name needs
+				// to follow a position or be introduced with `as`.
 				let instance_name = match inline(lexer.peek())? {
-					Some(Token::String(_)) => Some(InstanceName(lexer.string().unwrap())),
+					Some(Token::String(_)) if position.is_some() => Some(InstanceName(name(lexer)?)),
+					Some(Token::Identifier(identifier)) if identifier == "as" => {
+						lexer.identifier().unwrap();
+						Some(InstanceName(name(lexer)?))
+					}
 					_ => None,
 				};
 
 				let animation = animation(lexer)?;
 				script.commands.push(Command::Spawn(character, state, position, instance_name, animation));
 			}
+			"enter" => {
+				let character = CharacterName(name(lexer)?);
+				let state = StateName(name(lexer)?);
+				let position = placement(lexer)?;
+				script.commands.push(Command::Spawn(character, Some(state), Some(position), None, None));
+			}
 			"if" => {
 				let flag = FlagName(inline(lexer.identifier())?);
-				script.commands.push(Command::If(flag, Label(inline(lexer.identifier())?)));
+				script.commands.push(Command::If(flag, Label(identifier_name(lexer)?)));
+			}
+			"ifcount" => {
+				let counter = inline(lexer.identifier())?;
+				let comparison = comparison(lexer)?;
+				let number = integer(lexer)?;
+				script.commands.push(Command::IfCounter(counter, comparison, number, Label(identifier_name(lexer)?)));
+			}
+			"ifbetween" => {
+				let counter = inline(lexer.identifier())?;
+				let low = integer(lexer)?;
+				let high = integer(lexer)?;
+				script.commands.push(Command::IfBetween(counter, low, high, Label(identifier_name(lexer)?)));
 			}
-			"pause" => script.commands.push(Command::Pause),
+			"setcounter" => {
+				let counter = inline(lexer.identifier())?;
+				script.commands.push(Command::SetCounter(counter, integer(lexer)?));
+			}
+			"addcounter" => {
+				let counter = inline(lexer.identifier())?;
+				script.commands.push(Command::AddCounter(counter, integer(lexer)?));
+			}
+			"group" => {
+				let group = inline(lexer.string())?;
+				let instances = list(lexer)?.into_iter().map(|name| InstanceName(lexer.intern(name))).collect();
+				script.commands.push(Command::Group(group, instances));
+			}
+			"draworder" => {
+				let instances = list(lexer)?.into_iter().map(|name| InstanceName(lexer.intern(name))).collect();
+				script.commands.push(Command::SetDrawOrder(instances));
+			}
+			"cleardraworder" => script.commands.push(Command::ClearDrawOrder),
+			"front" => script.commands.push(Command::Front(InstanceName(name(lexer)?))),
+			"back" => script.commands.push(Command::Back(InstanceName(name(lexer)?))),
+			"animateall" => {
+				let kind = match inline(lexer.identifier())?.as_str() {
+					"show" => BatchAnimation::Show,
+					"hide" => BatchAnimation::Hide,
+					"kill" => BatchAnimation::Kill,
+					_ => return Err((ParserError::UnexpectedToken, Token::Terminator)),
+				};
+
+				if let None | Some(Token::Terminator) = inline(lexer.peek())? {
+					return Err((ParserError::Expected(Token::Identifier("with".to_owned())), Token::Terminator));
+				}
+				script.commands.push(Command::AnimateAll(kind, animation(lexer)?.unwrap()));
+			}
+			"ungroup" => script.commands.push(Command::UnGroup(inline(lexer.string())?)),
+			"alias" => {
+				let alias = InstanceName(name(lexer)?);
+				let instance = InstanceName(name(lexer)?);
+				script.commands.push(Command::Alias(alias, instance));
+			}
+			"copy" => {
+				let instance = InstanceName(name(lexer)?);
+				let new_name = InstanceName(name(lexer)?);
+				script.commands.push(Command::Copy(instance, new_name, placement(lexer)?));
+			}
+			"rename" => {
+				let instance = InstanceName(name(lexer)?);
+				let new_name = InstanceName(name(lexer)?);
+				script.commands.push(Command::Rename(instance, new_name));
+			}
+			"unalias" => script.commands.push(Command::RemoveAlias(InstanceName(name(lexer)?))),
+			"unload" => {
+				let path = match inline(lexer.peek())? {
+					Some(Token::String(_)) => Some(lexer.string().unwrap().into()),
+					_ => None,
+				};
+				script.commands.push(Command::Unload(path));
+			}
+			"pause" => {
+				let clear = match inline(lexer.peek())? {
+					Some(Token::Identifier(identifier)) if identifier == "clear" => {
+						lexer.identifier().unwrap();
+						true
+					}
+					_ => false,
+				};
+
+				let timeout = match inline(lexer.peek())? {
+					Some(Token::Numeric(_)) => Some(milliseconds(lexer)?),
+					_ => None,
+				};
+				script.commands.push(Command::Pause(clear, timeout));
+			}
+			"sync" => script.commands.push(Command::Sync),
+			"yield" => script.commands.push(Command::Yield),
+			"pausemusic" => script.commands.push(Command::PauseMusic),
+			"resumemusic" => script.commands.push(Command::ResumeMusic),
 			"flag" => script.commands.push(Command::Flag(FlagName(inline(lexer.identifier())?))),
 			"unflag" => script.commands.push(Command::Unflag(FlagName(inline(lexer.identifier())?))),
-			"kill" => script.commands.push(Command::Kill(InstanceName(inline(lexer.string())?), animation(lexer)?)),
-			"show" => script.commands.push(Command::Show(InstanceName(inline(lexer.string())?), animation(lexer)?)),
-			"hide" => script.commands.push(Command::Hide(InstanceName(inline(lexer.string())?), animation(lexer)?)),
+			"persist" => script.commands.push(Command::Persist(FlagName(inline(lexer.identifier())?))),
+			"kill" => script.commands.push(Command::Kill(InstanceName(name(lexer)?), animation(lexer)?)),
+			"show" => script.commands.push(Command::Show(InstanceName(name(lexer)?), animation(lexer)?)),
+			"hide" => script.commands.push(Command::Hide(InstanceName(name(lexer)?), animation(lexer)?)),
+			"charposition" => {
+				let character = CharacterName(name(lexer)?);
+				let position = placement(lexer)?;
+				script.commands.push(Command::CharacterPosition(character, position, animation(lexer)?));
+			}
+			"charshow" => script.commands.push(Command::CharacterShow(CharacterName(name(lexer)?), animation(lexer)?)),
+			"charhide" => script.commands.push(Command::CharacterHide(CharacterName(name(lexer)?), animation(lexer)?)),
+			"charkill" => script.commands.push(Command::CharacterKill(CharacterName(name(lexer)?), animation(lexer)?)),
+			"opacity" => {
+				let instance = InstanceName(name(lexer)?);
+				let opacity = inline(lexer.numeric())?;
+				script.commands.push(Command::Opacity(instance, opacity, animation(lexer)?));
+			}
+			"sprite" => {
+				let instance = InstanceName(name(lexer)?);
+				let path = inline(lexer.string())?.into();
+				script.commands.push(Command::Sprite(instance, path, animation(lexer)?));
+			}
+			"transition" => {
+				let path = inline(lexer.string())?.into();
+				script.commands.push(Command::SceneTransition(path, inline(lexer.numeric())?));
+			}
+			"textfont" | "namefont" | "dialoguefont" => {
+				let path = inline(lexer.string())?.into();
+				let size = inline(lexer.numeric())?;
+				script.commands.push(match identifier.as_str() {
+					"textfont" => Command::TextFont(path, size),
+					"namefont" => Command::NameFont(path, size),
+					_ => Command::DialogueFont(path, size),
+				});
+			}
 			"stage" => script.commands.push(Command::Stage(inline(lexer.string())?.into())),
-			"jump" => script.commands.push(Command::Jump(Label(inline(lexer.identifier())?))),
+			"jump" => {
+				let label = Label(identifier_name(lexer)?);
+				script.commands.push(Command::Jump(label, animation(lexer)?));
+			}
+			"repeat" => {
+				let label = Label(identifier_name(lexer)?);
+				script.commands.push(match inline(lexer.peek())? {
+					Some(Token::Numeric(_)) => Command::RepeatN(label, lexer.numeric().unwrap() as u32),
+					Some(Token::Identifier(identifier)) if identifier == "if" => {
+						lexer.identifier().unwrap();
+						Command::RepeatIf(label, FlagName(inline(lexer.identifier())?))
+					}
+					_ => Command::Repeat(label),
+				});
+			}
+			"stopanimations" => script.commands.push(Command::StopAllAnimations),
+			"finishanimations" => script.commands.push(Command::FinishAllAnimations),
+			"delay" => script.commands.push(Command::Delay(milliseconds(lexer)?)),
+			"pan" => {
+				let offset_x = inline(lexer.numeric())?;
+				let offset_y = inline(lexer.numeric())?;
+				script.commands.push(Command::Pan(offset_x, offset_y, inline(lexer.numeric())?));
+			}
+			"flip" => {
+				let instance = InstanceName(name(lexer)?);
+				script.commands.push(Command::Flip(instance, animation(lexer)?));
+			}
+			"flipvertical" => script.commands.push(Command::FlipVertical(InstanceName(name(lexer)?))),
+			"stopanimation" => script.commands.push(Command::StopAnimation(InstanceName(name(lexer)?))),
+			"log" => script.commands.push(Command::Log(inline(lexer.string())?)),
+			"logcount" => {
+				let counter = match inline(lexer.token())? {
+					Some(Token::Identifier(counter)) | Some(Token::String(counter)) => counter,
+					_ => return Err((ParserError::ExpectedIdentifier, Token::Terminator)),
+				};
+
+				let message = match inline(lexer.peek())? {
+					Some(Token::String(_)) => lexer.string().unwrap(),
+					_ => String::new(),
+				};
+				script.commands.push(Command::LogCounter(counter, message));
+			}
+			"logflag" => script.commands.push(Command::LogFlag(FlagName(inline(lexer.identifier())?))),
+			"assert" => {
+				let flag = FlagName(inline(lexer.identifier())?);
+				let expected = match inline(lexer.identifier())?.as_str() {
+					"true" => true,
+					"false" => false,
+					_ => return Err((ParserError::UnexpectedToken, Token::Terminator)),
+				};
+				script.commands.push(Command::Assert(flag, expected));
+			}
+			"assertlabel" => script.commands.push(Command::AssertLabel(Label(identifier_name(lexer)?))),
+			"assertcount" => {
+				let counter = inline(lexer.identifier())?;
+				script.commands.push(Command::AssertCounter(counter, integer(lexer)?));
+			}
 			"music" => script.commands.push(Command::Music(inline(lexer.string())?.into())),
+			"musicloop" => {
+				let path = inline(lexer.string())?.into();
+				let (start, end) = (inline(lexer.numeric())?, inline(lexer.numeric())?);
+				script.commands.push(Command::MusicLoop(path, start, end));
+			}
 			"sound" => script.commands.push(Command::Sound(inline(lexer.string())?.into())),
 			_ => return Err((ParserError::InvalidCommand, Token::Terminator)),
 		}
-		Token::String(string) => match lexer.token().map_err(|error| (error, Token::Terminator))? {
-			Some(Token::Terminator) =>
-				script.commands.push(Command::Dialogue(None, string)),
-			Some(Token::String(dialogue)) => {
-				let character = Some(CharacterName(string));
-				script.commands.push(Command::Dialogue(character, dialogue));
-				inline(lexer.expect(Token::Terminator))?;
-			}
-			_ => return Err((ParserError::Expected(Token::Terminator), Token::Terminator)),
-		},
+		Token::String(string) => {
+			let (character, dialogue) = match inline(lexer.peek())? {
+				Some(Token::String(_)) => (Some(CharacterName(lexer.intern(string))), inline(lexer.string())?),
+				_ => (None, string),
+			};
+
+			let alignment = match inline(lexer.token())? {
+				None | Some(Token::Terminator) => None,
+				Some(Token::Identifier(identifier)) if identifier == "align" => {
+					let alignment = alignment(lexer)?;
+					inline(lexer.expect(Token::Terminator))?;
+					Some(alignment)
+				}
+				_ => return Err((ParserError::Expected(Token::Terminator), Token::Terminator)),
+			};
+			script.commands.push(Command::Dialogue(character, dialogue, alignment));
+		}
 		Token::ScopeOpen => return Err((ParserError::UnexpectedToken, Token::ScopeClose)),
 		_ => return Err((ParserError::UnexpectedToken, Token::Terminator)),
 	};
@@ -132,6 +406,51 @@ pub fn inline<T>(result: Result<T, ParserError>) -> Result<T, (ParserError, Toke
 	result.map_err(|error| (error, Token::Terminator))
 }
 
+/// Parses a whole number that fits in a counter.
+pub fn integer(lexer: &mut Lexer) -> Result<i32, (ParserError, Token)> {
+	match inline(lexer.numeric())? {
+		// The upper bound of `i32` is not exactly representable so the comparison is exclusive.
+		number if number.fract() == 0.0 && number >= i32::MIN as f32 && number < -(i32::MIN as f32) => Ok(number as i32),
+		_ => Err((ParserError::InvalidNumeric, Token::Terminator)),
+	}
+}
+
+/// Parses an amount of milliseconds, which must be finite and not negative.
+pub fn milliseconds(lexer: &mut Lexer) -> Result<f32, (ParserError, Token)> {
+	match inline(lexer.numeric())? {
+		milliseconds if milliseconds.is_finite() && milliseconds >= 0.0 => Ok(milliseconds),
+		_ => Err((ParserError::InvalidNumeric, Token::Terminator)),
+	}
+}
+
+/// Parses a string and interns it so that equal names share memory.
+pub fn name(lexer: &mut Lexer) -> Result<Arc<str>, (ParserError, Token)> {
+	let string = inline(lexer.string())?;
+	Ok(lexer.intern(string))
+}
+
+/// Parses an identifier and interns it so that equal names share memory.
+pub fn identifier_name(lexer: &mut Lexer) -> Result<Arc<str>, (ParserError, Token)> {
+	let identifier = inline(lexer.identifier())?;
+	Ok(lexer.intern(identifier))
+}
+
+/// Parses a comparison operator such as `>=`.
+pub fn comparison(lexer: &mut Lexer) -> Result<Comparison, (ParserError, Token)> {
+	Comparison::from_operator(&inline(lexer.identifier())?)
+		.ok_or((ParserError::UnexpectedToken, Token::Terminator))
+}
+
+/// Parses the name of a text alignment.
+pub fn alignment(lexer: &mut Lexer) -> Result<TextAlignment, (ParserError, Token)> {
+	match inline(lexer.identifier())?.as_str() {
+		"left" => Ok(TextAlignment::Left),
+		"centre" | "center" => Ok(TextAlignment::Centre),
+		"right" => Ok(TextAlignment::Right),
+		_ => Err((ParserError::UnexpectedToken, Token::Terminator)),
+	}
+}
+
 pub fn animation(lexer: &mut Lexer) -> Result<Option<AnimationDeclaration>, (ParserError, Token)> {
 	match inline(lexer.token())? {
 		None | Some(Token::Terminator) => return Ok(None),
@@ -157,6 +476,38 @@ pub fn animation(lexer: &mut Lexer) -> Result<Option<AnimationDeclaration>, (Par
 	Ok(Some(AnimationDeclaration { name, arguments }))
 }
 
+pub fn list(lexer: &mut Lexer) -> Result<Vec<String>, (ParserError, Token)> {
+	inline(lexer.expect(Token::SquareOpen))?;
+	let mut strings = Vec::new();
+	while let Some(token) = inline(lexer.token())? {
+		if token == Token::SquareClose { break; }
+		if !strings.is_empty() {
+			if token != Token::ListSeparator {
+				return Err((ParserError::Expected(Token::ListSeparator), Token::Terminator));
+			}
+			strings.push(inline(lexer.string())?);
+			continue;
+		}
+
+		match token {
+			Token::String(string) => strings.push(string),
+			_ => return Err((ParserError::ExpectedString, Token::Terminator)),
+		}
+	}
+	Ok(strings)
+}
+
+/// Parses a position in pixels or the name of a slot after the `slot` keyword.
+pub fn placement(lexer: &mut Lexer) -> Result<Placement, (ParserError, Token)> {
+	match inline(lexer.peek())? {
+		Some(Token::Identifier(identifier)) if identifier == "slot" => {
+			lexer.identifier().unwrap();
+			Ok(Placement::Slot(inline(lexer.identifier())?))
+		}
+		_ => position(lexer).map(|(x, y)| Placement::Point(x, y)),
+	}
+}
+
 pub fn position(lexer: &mut Lexer) -> Result<(f32, f32), (ParserError, Token)> {
 	inline(lexer.expect(Token::BracketOpen))?;
 	let position_x = inline(lexer.numeric())?;
@@ -166,6 +517,49 @@ pub fn position(lexer: &mut Lexer) -> Result<(f32, f32), (ParserError, Token)> {
 	Ok((position_x, position_y))
 }
 
+/// Parses the commands of a together block until the end of the block.
+/// The block cannot contain labels or commands that wait or change which command runs next.
+pub fn parse_together(lexer: &mut Lexer, script: &mut Script) -> Result<(), (ParserError, Token)> {
+	let (start, labels) = (script.commands.len(), script.labels.len());
+	while inline(lexer.peek())? != Some(&Token::ScopeClose) {
+		parse_command(lexer, script).map_err(|(error, _)| (error, Token::ScopeClose))?;
+	}
+
+	let commands = script.commands.split_off(start);
+	if script.labels.len() != labels || commands.iter().any(Command::is_blocking) {
+		return Err((ParserError::BlockingCommand, Token::ScopeClose));
+	}
+
+	lexer.expect(Token::ScopeClose).unwrap();
+	script.commands.push(Command::Together(commands));
+	Ok(())
+}
+
+/// Parses the branches of a random block, the text of each branch only describes it.
+pub fn parse_random(lexer: &mut Lexer, script: &mut Script) -> Result<(), ParserError> {
+	let mut branches = Vec::new();
+	loop {
+		match lexer.token() {
+			Ok(Some(Token::ScopeClose)) => {
+				script.commands.push(Command::Random(branches));
+				return Ok(());
+			}
+			Ok(Some(Token::String(_))) => {
+				let weight = match lexer.numeric()? {
+					// The upper bound of `u32` is not exactly representable so the comparison is exclusive.
+					weight if weight.fract() == 0.0 && weight >= 0.0 && weight < u32::MAX as f32 => weight as u32,
+					_ => return Err(ParserError::InvalidNumeric),
+				};
+				let identifier = lexer.identifier()?;
+				branches.push((weight, Label(lexer.intern(identifier))));
+				lexer.expect(Token::Terminator)?;
+			}
+			Ok(Some(Token::Terminator)) => (),
+			_ => return Err(ParserError::ExpectedString),
+		}
+	}
+}
+
 pub fn parse_diverge(lexer: &mut Lexer, script: &mut Script) -> Result<(), ParserError> {
 	let mut branches = Vec::new();
 	loop {
@@ -176,7 +570,7 @@ pub fn parse_diverge(lexer: &mut Lexer, script: &mut Script) -> Result<(), Parse
 			}
 			Ok(Some(Token::String(string))) => {
 				let identifier = lexer.identifier()?;
-				branches.push((string, Label(identifier)));
+				branches.push((string, Label(lexer.intern(identifier))));
 				lexer.expect(Token::Terminator)?;
 			}
 			Ok(Some(Token::Terminator)) => (),
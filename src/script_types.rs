@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Settings;
+
+/// Compares the value of a counter with a number.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum Comparison {
+	Lt,
+	Le,
+	Eq,
+	Ge,
+	Gt,
+	Ne,
+}
+
+impl Comparison {
+	/// Finds the comparison that an operator such as `>=` is written as.
+	pub fn from_operator(operator: &str) -> Option<Self> {
+		Some(match operator {
+			"<" => Comparison::Lt,
+			"<=" => Comparison::Le,
+			"=" | "==" => Comparison::Eq,
+			">=" => Comparison::Ge,
+			">" => Comparison::Gt,
+			"!=" => Comparison::Ne,
+			_ => return None,
+		})
+	}
+
+	/// Checks whether the comparison holds between a value and a number.
+	pub fn compare(self, value: i32, number: i32) -> bool {
+		match self {
+			Comparison::Lt => value < number,
+			Comparison::Le => value <= number,
+			Comparison::Eq => value == number,
+			Comparison::Ge => value >= number,
+			Comparison::Gt => value > number,
+			Comparison::Ne => value != number,
+		}
+	}
+}
+
+/// Where an instance is placed on the stage.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum Placement {
+	/// A position in pixels.
+	Point(f32, f32),
+	/// A named slot whose position is taken from the settings when the command is executed.
+	Slot(String),
+}
+
+impl Placement {
+	/// Finds the position in pixels that the placement refers to.
+	pub fn resolve(&self, settings: &Settings) -> Result<(f32, f32), String> {
+		match self {
+			Placement::Point(x, y) => Ok((*x, *y)),
+			Placement::Slot(slot) => settings.slots.get(slot).copied()
+				.ok_or_else(|| format!("Slot: {}, is not defined in the settings", slot)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn comparison_operators() {
+		assert_eq!(Comparison::from_operator(">="), Some(Comparison::Ge));
+		assert_eq!(Comparison::from_operator("=>"), None);
+		assert!(Comparison::Ge.compare(50, 50));
+		assert!(!Comparison::Gt.compare(50, 50));
+		assert!(Comparison::Ne.compare(49, 50));
+	}
+
+	#[test]
+	fn resolve_slots() {
+		let mut settings = Settings::default();
+		assert_eq!(Placement::Point(1.0, 2.0).resolve(&settings), Ok((1.0, 2.0)));
+		assert_eq!(Placement::Slot("left".to_owned()).resolve(&settings), Ok((160.0, 400.0)));
+		settings.slots.insert("left".to_owned(), (200.0, 300.0));
+		assert_eq!(Placement::Slot("left".to_owned()).resolve(&settings), Ok((200.0, 300.0)));
+		assert!(Placement::Slot("middle".to_owned()).resolve(&settings).is_err());
+	}
+}